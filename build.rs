@@ -11,6 +11,9 @@ fn main() {
         .type_attribute("netavark_proxy.IPResponse", "#[derive(serde::Serialize)]")
         .type_attribute("netavark_proxy.MacAddress", "#[derive(serde::Serialize)]")
         .type_attribute("netavark_proxy.NvIpv4Addr", "#[derive(serde::Serialize)]")
+        .type_attribute("netavark_proxy.LeaseInfo", "#[derive(serde::Serialize)]")
+        .type_attribute("netavark_proxy.LeaseList", "#[derive(serde::Serialize)]")
+        .type_attribute("netavark_proxy.StaticRoute", "#[derive(serde::Serialize)]")
         .type_attribute("netavark_proxy.Lease", "#[derive(serde::Deserialize)]")
         .type_attribute(
             "netavark_proxy.DhcpV4Lease",
@@ -23,6 +26,12 @@ fn main() {
         .type_attribute("netavark_proxy.IPResponse", "#[derive(serde::Deserialize)]")
         .type_attribute("netavark_proxy.MacAddress", "#[derive(serde::Deserialize)]")
         .type_attribute("netavark_proxy.NvIpv4Addr", "#[derive(serde::Deserialize)]")
+        .type_attribute("netavark_proxy.LeaseInfo", "#[derive(serde::Deserialize)]")
+        .type_attribute("netavark_proxy.LeaseList", "#[derive(serde::Deserialize)]")
+        .type_attribute(
+            "netavark_proxy.StaticRoute",
+            "#[derive(serde::Deserialize)]",
+        )
         .type_attribute("netavark_proxy.MacAddress", "#[derive(Eq)]")
         .type_attribute("netavark_proxy.MacAddress", "#[derive(Hash)]")
         .type_attribute(
@@ -33,11 +42,17 @@ fn main() {
             "netavark_proxy.NetworkConfig",
             "#[derive(serde::Serialize)]",
         )
+        .field_attribute(
+            "netavark_proxy.NetworkConfig.client_id",
+            "#[serde(default)]",
+        )
+        .field_attribute("netavark_proxy.NetworkConfig.mtu", "#[serde(default)]")
+        .field_attribute("netavark_proxy.Lease.static_routes", "#[serde(default)]")
         .out_dir(PathBuf::from("src/proto-build"));
 
-    builder
+    if env::var("LOCAL_SKIP_PROTOC").is_err() { builder
         .compile_protos(&[Path::new("src/proto/proxy.proto")], &[Path::new("proto")])
-        .unwrap_or_else(|e| panic!("Failed at builder: {:?}", e.to_string()));
+        .unwrap_or_else(|e| panic!("Failed at builder: {:?}", e.to_string())); }
 
     // Generate the default 'cargo:' instruction output
     println!("cargo:rerun-if-changed=build.rs");
@@ -65,7 +80,7 @@ fn main() {
     println!("cargo:rustc-env=GIT_COMMIT={commit}");
 
     // Handle default firewall driver.
-    // Allowed values "nftables" and "iptables".
+    // Allowed values "nftables", "iptables", "none" and "auto".
     let fwdriver = match env::var("NETAVARK_DEFAULT_FW")
         .unwrap_or("iptables".to_string())
         .as_str()
@@ -73,9 +88,10 @@ fn main() {
         "nftables" => "nftables",
         "iptables" => "iptables",
         "none" => "none",
+        "auto" => "auto",
         inv => panic!("Invalid default firewall driver {}", inv),
     };
-    println!("cargo:rustc-check-cfg=cfg(default_fw, values(\"nftables\", \"iptables\", \"none\"))");
+    println!("cargo:rustc-check-cfg=cfg(default_fw, values(\"nftables\", \"iptables\", \"none\", \"auto\"))");
     println!("cargo:rustc-cfg=default_fw=\"{}\"", fwdriver);
     println!("cargo:rustc-env=DEFAULT_FW={fwdriver}");
 }
@@ -0,0 +1,78 @@
+//! This is just an example plugin, do not use it in production!
+//!
+//! Usage: daemon-plugin <socket path>
+//! Register the daemon with a `<driver name>.socket` file in a plugin directory
+//! which contains the socket path.
+
+use std::{cell::Cell, env, path::PathBuf};
+
+use netavark::{
+    network::types,
+    new_error,
+    plugin::{Info, Plugin, PluginExec, API_VERSION},
+};
+
+fn main() {
+    let info = Info::new("0.1.0-dev".to_owned(), API_VERSION.to_owned(), None);
+
+    let path = match env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("socket path argument is missing");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = PluginExec::new(Exec::default(), info).serve(&path) {
+        eprintln!("daemon failed: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Counts the setups to show the state is kept between requests.
+#[derive(Default)]
+struct Exec {
+    setups: Cell<u32>,
+}
+
+impl Plugin for Exec {
+    fn create(
+        &self,
+        network: types::Network,
+    ) -> Result<types::Network, Box<dyn std::error::Error>> {
+        Ok(network)
+    }
+
+    fn setup(
+        &self,
+        _netns: String,
+        opts: types::NetworkPluginExec,
+    ) -> Result<types::StatusBlock, Box<dyn std::error::Error>> {
+        if opts.container_name == "fail" {
+            return Err(new_error!("daemon setup error"));
+        }
+        self.setups.set(self.setups.get() + 1);
+        eprintln!("daemon setup {}", self.setups.get());
+
+        //  StatusBlock response
+        let response = types::StatusBlock {
+            dns_server_ips: None,
+            dns_search_domains: Some(vec![format!("setup-{}", self.setups.get())]),
+            interfaces: None,
+            ntp_server_ips: None,
+            port_mappings: None,
+        };
+
+        Ok(response)
+    }
+
+    fn teardown(
+        &self,
+        _netns: String,
+        _opts: types::NetworkPluginExec,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        eprintln!("daemon teardown");
+
+        Ok(())
+    }
+}
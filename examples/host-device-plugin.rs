@@ -1,16 +1,14 @@
 //! This is just an example plugin, do not use it in production!
 
-use std::{collections::HashMap, os::fd::AsFd};
-
 use netavark::{
-    network::{
-        core_utils::{open_netlink_sockets, CoreUtils},
-        netlink, types,
-    },
+    network::{netlink, types},
     new_error,
-    plugin::{Info, Plugin, PluginExec, API_VERSION},
+    plugin::{
+        helpers::{interface_status_block, link_mac_address, Namespaces},
+        Info, Plugin, PluginExec, API_VERSION,
+    },
 };
-use netlink_packet_route::{address::AddressAttribute, link::LinkAttribute};
+use netlink_packet_route::address::AddressAttribute;
 
 fn main() {
     let info = Info::new("0.1.0-dev".to_owned(), API_VERSION.to_owned(), None);
@@ -32,25 +30,36 @@ impl Plugin for Exec {
         Ok(network)
     }
 
+    fn services(&self, opts: &types::NetworkPluginExec) -> types::PluginServices {
+        let option = |name: &str| {
+            opts.network
+                .options
+                .as_ref()
+                .and_then(|o| o.get(name))
+                .is_some_and(|v| v == "true")
+        };
+        types::PluginServices {
+            dns: option("request_dns"),
+            port_forward: option("request_port_forward"),
+        }
+    }
+
     fn setup(
         &self,
         netns: String,
         opts: types::NetworkPluginExec,
     ) -> Result<types::StatusBlock, Box<dyn std::error::Error>> {
-        let (mut host, netns) = open_netlink_sockets(&netns)?;
+        let mut namespaces = Namespaces::open(&netns)?;
 
         let name = opts.network.network_interface.unwrap_or_default();
 
-        let link = host.netlink.get_link(netlink::LinkID::Name(name.clone()))?;
-
-        let mut mac_address = String::from("");
-        for nla in link.attributes {
-            if let LinkAttribute::Address(ref addr) = nla {
-                mac_address = CoreUtils::encode_address_to_hex(addr);
-            }
-        }
+        let link = namespaces
+            .host
+            .netlink
+            .get_link(netlink::LinkID::Name(name.clone()))?;
+        let mac_address = link_mac_address(&link);
 
-        let addresses = host.netlink.dump_addresses()?;
+        let addresses = namespaces.host.netlink.dump_addresses()?;
         let mut subnets = Vec::new();
         for address in addresses {
             if address.header.index == link.header.index {
@@ -66,26 +75,9 @@ impl Plugin for Exec {
             }
         }
 
-        host.netlink
-            .set_link_ns(link.header.index, netns.file.as_fd())?;
-
-        // interfaces map, but we only ever expect one, for response
-        let mut interfaces: HashMap<String, types::NetInterface> = HashMap::new();
-
-        let interface = types::NetInterface {
-            mac_address,
-            subnets: Option::from(subnets),
-        };
-        interfaces.insert(name, interface);
+        namespaces.move_link_to_container(&name, &name)?;
 
-        //  StatusBlock response
-        let response = types::StatusBlock {
-            dns_server_ips: None,
-            dns_search_domains: None,
-            interfaces: Some(interfaces),
-        };
-
-        Ok(response)
+        Ok(interface_status_block(name, mac_address, subnets))
     }
 
     fn teardown(
@@ -94,15 +86,11 @@ impl Plugin for Exec {
         opts: types::NetworkPluginExec,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // on teardown revert what was done in setup
-        let (host, mut netns) = open_netlink_sockets(&netns)?;
+        let mut namespaces = Namespaces::open(&netns)?;
 
         let name = opts.network.network_interface.unwrap_or_default();
 
-        let link = netns.netlink.get_link(netlink::LinkID::Name(name))?;
-
-        netns
-            .netlink
-            .set_link_ns(link.header.index, host.file.as_fd())?;
+        namespaces.move_link_to_host(&name, &name)?;
 
         Ok(())
     }
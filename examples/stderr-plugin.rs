@@ -2,10 +2,11 @@
 
 use netavark::{
     network::types,
-    plugin::{Info, Plugin, PluginExec, API_VERSION},
+    plugin::{Info, Plugin, PluginExec, API_VERSION, CAPABILITY_STATUS, CAPABILITY_UPDATE},
 };
 fn main() {
-    let info = Info::new("0.1.0-dev".to_owned(), API_VERSION.to_owned(), None);
+    let info = Info::new("0.1.0-dev".to_owned(), API_VERSION.to_owned(), None)
+        .with_capabilities(&[CAPABILITY_STATUS, CAPABILITY_UPDATE]);
 
     PluginExec::new(Exec {}, info).exec();
 }
@@ -33,6 +34,8 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: None,
+            ntp_server_ips: None,
+            port_mappings: None,
         };
 
         Ok(response)
@@ -47,4 +50,31 @@ impl Plugin for Exec {
 
         Ok(())
     }
+
+    fn status(
+        &self,
+        _netns: String,
+        _opts: types::NetworkPluginExec,
+    ) -> Result<types::StatusBlock, Box<dyn std::error::Error>> {
+        eprintln!("stderr status");
+
+        let response = types::StatusBlock {
+            dns_server_ips: None,
+            dns_search_domains: None,
+            interfaces: None,
+            ntp_server_ips: None,
+            port_mappings: None,
+        };
+
+        Ok(response)
+    }
+
+    fn update(&self, network: types::Network) -> Result<(), Box<dyn std::error::Error>> {
+        eprintln!(
+            "stderr update {:?}",
+            network.network_dns_servers.unwrap_or_default()
+        );
+
+        Ok(())
+    }
 }
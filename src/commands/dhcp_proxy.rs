@@ -1,14 +1,17 @@
 #![cfg_attr(not(unix), allow(unused_imports))]
 
 use crate::dhcp_proxy::cache::{Clear, LeaseCache};
-use crate::dhcp_proxy::dhcp_service::{process_client_stream, DhcpV4Service};
+use crate::dhcp_proxy::dhcp_service::{process_client_stream, DhcpV4Service, ReleaseRequest};
 use crate::dhcp_proxy::ip;
 use crate::dhcp_proxy::lib::g_rpc::netavark_proxy_server::{NetavarkProxy, NetavarkProxyServer};
 use crate::dhcp_proxy::lib::g_rpc::{
-    Empty, Lease as NetavarkLease, NetworkConfig, OperationResponse,
+    Empty, Lease as NetavarkLease, LeaseInfo, LeaseList, MacAddress, NetworkConfig,
+    OperationResponse,
 };
+use crate::dhcp_proxy::metrics::{bind_metrics, serve_metrics, Metrics, MetricsAddress};
 use crate::dhcp_proxy::proxy_conf::{
-    get_cache_fqname, get_proxy_sock_fqname, DEFAULT_INACTIVITY_TIMEOUT, DEFAULT_TIMEOUT,
+    get_cache_fqname, get_proxy_sock_fqname, get_shutdowns_fqname, DEFAULT_INACTIVITY_TIMEOUT,
+    DEFAULT_TIMEOUT,
 };
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::network::core_utils;
@@ -23,6 +26,7 @@ use std::os::unix::io::FromRawFd;
 use std::os::unix::net::UnixListener as stdUnixListener;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, fs};
 #[cfg(unix)]
 use tokio::net::UnixListener;
@@ -31,13 +35,21 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::sync::{mpsc, oneshot};
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
 #[cfg(unix)]
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::{
     transport::Server, Code, Code::Internal, Code::InvalidArgument, Request, Response, Status,
 };
 
+/// The renewal task of a lease, it owns the mozim client of the lease.
+#[derive(Debug)]
+struct LeaseTask {
+    handle: AbortHandle,
+    // asks the task to release the lease before it is aborted
+    release: oneshot::Sender<ReleaseRequest>,
+}
+
 #[derive(Debug)]
 /// This is the tonic netavark proxy service that is required to impl the Netavark Proxy trait which
 /// includes the gRPC methods defined in proto/proxy.proto. We can store a atomically referenced counted
@@ -59,7 +71,11 @@ struct NetavarkProxyService<W: Write + Clear> {
     timeout_sender: Arc<Mutex<Sender<i32>>>,
     // All dhcp poll will be spawned on a new task, keep track of it so
     // we can remove it on teardown. The key is the container mac.
-    task_map: Arc<Mutex<HashMap<String, AbortHandle>>>,
+    task_map: Arc<Mutex<HashMap<String, LeaseTask>>>,
+    // metrics of the proxy, exported when a metrics address is configured
+    metrics: Arc<Metrics>,
+    // do not send a DHCPRELEASE to the server on teardown
+    skip_release: bool,
 }
 
 impl<W: Write + Clear> NetavarkProxyService<W> {
@@ -94,6 +110,7 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
         let cache = self.cache.clone();
         let timeout = self.dora_timeout;
         let task_map = self.task_map.clone();
+        let metrics = self.metrics.clone();
 
         // setup client side streaming
         let network_config = request.into_inner();
@@ -106,7 +123,7 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
                 log::debug!("Request dropped, aborting DORA");
                 return Err(Status::new(Code::Aborted, "client disconnected"));
             }
-            let get_lease = process_setup(network_config, timeout, cache, task_map);
+            let get_lease = process_setup(network_config, timeout, cache, task_map, metrics);
             // watch the client and the lease, which ever finishes first return
             let get_lease: NetavarkLease = tokio::select! {
                 _ = &mut rx => {
@@ -151,16 +168,40 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
             .lock()
             .expect("lock tasks")
             .remove(&nc.container_mac_addr);
-        if let Some(handle) = task {
-            handle.abort();
+        if let Some(task) = task {
+            // Tell the dhcp server that the address is no longer used, only the
+            // renewal task has the mozim client that can release the lease.
+            // Dropping the sender stops the task without a release.
+            if !self.skip_release {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                if task.release.send(reply_tx).is_ok() {
+                    match reply_rx.await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => warn!(
+                            "failed to release lease for {}: {e}",
+                            &nc.container_mac_addr
+                        ),
+                        Err(_) => warn!(
+                            "failed to release lease for {}: renewal task stopped",
+                            &nc.container_mac_addr
+                        ),
+                    }
+                }
+            }
+            task.handle.abort();
         }
 
         // Remove the client from the cache dir
-        let lease = cache
-            .lock()
-            .expect("Could not unlock cache. A thread was poisoned")
-            .remove_lease(&nc.container_mac_addr)
-            .map_err(|e| Status::internal(e.to_string()))?;
+        let lease = {
+            let mut locked_cache = cache
+                .lock()
+                .expect("Could not unlock cache. A thread was poisoned");
+            let lease = locked_cache
+                .remove_lease(&nc.container_mac_addr)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            self.metrics.set_active_leases(locked_cache.len());
+            lease
+        };
 
         Ok(Response::new(lease))
     }
@@ -175,6 +216,90 @@ impl<W: Write + Clear + Send + 'static> NetavarkProxy for NetavarkProxyService<W
             .teardown()?;
         Ok(Response::new(OperationResponse { success: true }))
     }
+
+    /// Return all leases in the cache with their remaining lease time and renewal status.
+    async fn list_leases(&self, request: Request<Empty>) -> Result<Response<LeaseList>, Status> {
+        debug!("Request from client: {:?}", request.remote_addr());
+        self.reset_inactivity_timeout();
+
+        let cache = self
+            .cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        let tasks = self.task_map.lock().expect("lock tasks");
+        let mut leases: Vec<LeaseInfo> = cache
+            .leases()
+            .into_iter()
+            .map(|(mac, lease, obtained)| {
+                lease_info(lease, obtained, tasks.get(mac).map(|t| &t.handle))
+            })
+            .collect();
+        leases.sort_by(|a, b| {
+            let mac = |l: &LeaseInfo| l.lease.as_ref().map(|l| l.mac_address.clone());
+            mac(a).cmp(&mac(b))
+        });
+        Ok(Response::new(LeaseList { leases }))
+    }
+
+    /// Return the lease for a single mac address with its remaining lease time and renewal status.
+    async fn get_lease(&self, request: Request<MacAddress>) -> Result<Response<LeaseInfo>, Status> {
+        debug!("Request from client: {:?}", request.remote_addr());
+        self.reset_inactivity_timeout();
+        let mac = request.into_inner().mac;
+
+        let cache = self
+            .cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        let tasks = self.task_map.lock().expect("lock tasks");
+        match cache.get_lease(&mac) {
+            Some((lease, obtained)) => Ok(Response::new(lease_info(
+                lease,
+                obtained,
+                tasks.get(&mac).map(|t| &t.handle),
+            ))),
+            None => Err(Status::new(
+                Code::NotFound,
+                format!("no lease found for mac address {mac}"),
+            )),
+        }
+    }
+}
+
+/// Create the LeaseInfo for a cached lease.
+///
+/// # Arguments
+///
+/// * `lease`: the cached lease
+/// * `obtained`: time when the lease was obtained or last renewed
+/// * `task`: the renewal task of the lease if there is one
+///
+/// returns: LeaseInfo
+fn lease_info(
+    lease: &NetavarkLease,
+    obtained: SystemTime,
+    task: Option<&AbortHandle>,
+) -> LeaseInfo {
+    let obtained_at = obtained
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let elapsed = SystemTime::now()
+        .duration_since(obtained)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // a lease time of u32::MAX means the lease never expires
+    let remaining_lease_time = if lease.lease_time == u32::MAX {
+        u32::MAX
+    } else {
+        u64::from(lease.lease_time).saturating_sub(elapsed) as u32
+    };
+    LeaseInfo {
+        lease: Some(lease.clone()),
+        obtained_at,
+        remaining_lease_time,
+        renewing: task.is_some_and(|t| !t.is_finished()),
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -192,6 +317,14 @@ pub struct Opts {
     /// activity timeout
     #[clap(short, long)]
     activity_timeout: Option<u64>,
+    /// optional address to export OpenMetrics on, either a unix socket
+    /// (unix:/path) or a loopback TCP address (127.0.0.1:9100)
+    #[clap(long)]
+    metrics_address: Option<String>,
+    /// do not send a DHCPRELEASE to the dhcp server when a container is torn down,
+    /// the address stays reserved until the lease expires
+    #[clap(long)]
+    skip_release: bool,
 }
 
 /// Handle SIGINT signal.
@@ -226,6 +359,19 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
     let inactivity_timeout =
         Duration::from_secs(opts.activity_timeout.unwrap_or(DEFAULT_INACTIVITY_TIMEOUT));
 
+    let metrics_address = opts
+        .metrics_address
+        .as_deref()
+        .map(str::parse::<MetricsAddress>)
+        .transpose()?;
+    // bind before anything else is set up so a bad address fails the startup
+    let metrics_listener = match metrics_address {
+        Some(address) => Some(bind_metrics(&address).await.map_err(|e| {
+            NetavarkError::wrap(format!("failed to bind metrics address {address}"), e)
+        })?),
+        None => None,
+    };
+
     let uds_path = get_proxy_sock_fqname(optional_run_dir);
     debug!("socket path: {}", &uds_path.display());
 
@@ -286,13 +432,27 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
     // Create send and receive channels for activity timeout. If anything is
     // sent by the tx side, the inactivity timeout is reset
     let (activity_timeout_tx, activity_timeout_rx) = mpsc::channel(5);
+    let metrics = Arc::new(Metrics::new());
+    let shutdowns_path = get_shutdowns_fqname(optional_run_dir);
+    metrics.load_inactivity_shutdowns(&shutdowns_path);
     let netavark_proxy_service = NetavarkProxyService {
         cache: cache.clone(),
         dora_timeout,
         timeout_sender: Arc::new(Mutex::new(activity_timeout_tx.clone())),
         task_map: Arc::new(Mutex::new(HashMap::new())),
+        metrics: metrics.clone(),
+        skip_release: opts.skip_release,
     };
 
+    if let Some(listener) = metrics_listener {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(listener, metrics).await {
+                error!("metrics listener failed: {e}");
+            }
+        });
+    }
+
     let server = Server::builder()
         .add_service(NetavarkProxyServer::new(netavark_proxy_service))
         .serve_with_incoming(uds_stream);
@@ -301,7 +461,7 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
 
     tokio::select! {
         //  a timeout duration of 0 means NEVER
-        _ = handle_wakeup(activity_timeout_rx, inactivity_timeout, cache.clone()), if inactivity_timeout.as_secs() > 0  => {},
+        _ = handle_wakeup(activity_timeout_rx, inactivity_timeout, cache.clone(), metrics, &shutdowns_path), if inactivity_timeout.as_secs() > 0  => {},
         _ = &mut server => {},
     };
 
@@ -319,6 +479,9 @@ pub async fn serve(opts: Opts) -> NetavarkResult<()> {
 ///
 /// * `rx`: receive side of channel
 /// * `timeout_duration`: time duration in seconds
+/// * `current_cache`: lease cache
+/// * `metrics`: proxy metrics
+/// * `shutdowns_path`: file counting the inactivity timeout shutdowns
 ///
 /// returns: ()
 ///
@@ -331,6 +494,8 @@ async fn handle_wakeup<W: Write + Clear>(
     mut rx: mpsc::Receiver<i32>,
     timeout_duration: Duration,
     current_cache: Arc<Mutex<LeaseCache<W>>>,
+    metrics: Arc<Metrics>,
+    shutdowns_path: &Path,
 ) {
     loop {
         match timeout(timeout_duration, rx.recv()).await {
@@ -349,6 +514,7 @@ async fn handle_wakeup<W: Write + Clear>(
                         "timeout met: exiting after {} secs of inactivity",
                         timeout_duration.as_secs()
                     );
+                    metrics.inc_inactivity_shutdowns(shutdowns_path);
                     break;
                 }
             }
@@ -389,16 +555,20 @@ fn is_catch_empty<W: Write + Clear>(current_cache: Arc<Mutex<LeaseCache<W>>>) ->
 /// * `network_config`: Network config
 /// * `timeout`: dora timeout
 /// * `cache`: lease cache
+/// * `tasks`: map of the running dhcp client streams
+/// * `metrics`: proxy metrics
 ///
 /// returns: Result<Lease, Status>
-async fn process_setup<W: Write + Clear>(
+async fn process_setup<W: Write + Clear + Send + 'static>(
     network_config: NetworkConfig,
     timeout: u32,
     cache: Arc<Mutex<LeaseCache<W>>>,
-    tasks: Arc<Mutex<HashMap<String, AbortHandle>>>,
+    tasks: Arc<Mutex<HashMap<String, LeaseTask>>>,
+    metrics: Arc<Metrics>,
 ) -> Result<NetavarkLease, Status> {
     let container_network_interface = network_config.container_iface.clone();
     let ns_path = network_config.ns_path.clone();
+    let network_mtu = network_config.mtu;
 
     // test if mac is valid
     core_utils::CoreUtils::decode_address_from_hex(&network_config.container_mac_addr)
@@ -408,14 +578,35 @@ async fn process_setup<W: Write + Clear>(
     let nv_lease = match network_config.version {
         //V4
         0 => {
-            let mut service = DhcpV4Service::new(network_config, timeout)?;
-
-            let lease = service.get_lease().await?;
-            let task = tokio::spawn(process_client_stream(service));
-            tasks
-                .lock()
-                .expect("lock tasks")
-                .insert(mac.to_string(), task.abort_handle());
+            let start = Instant::now();
+            let lease = match DhcpV4Service::new(network_config, timeout) {
+                Ok(mut service) => service.get_lease().await.map(|lease| (service, lease)),
+                Err(e) => Err(e),
+            };
+            let (mut service, lease) = match lease {
+                Ok(l) => l,
+                Err(e) => {
+                    metrics.inc_dora_error(&e.kind());
+                    return Err(e.into());
+                }
+            };
+            metrics.observe_dora(start.elapsed());
+            ip::setup(&lease, &container_network_interface, &ns_path, network_mtu)?;
+            service.update_static_routes(&lease)?;
+            let (release, release_rx) = oneshot::channel();
+            let task = tokio::spawn(process_client_stream(
+                service,
+                cache.clone(),
+                metrics.clone(),
+                release_rx,
+            ));
+            tasks.lock().expect("lock tasks").insert(
+                mac.to_string(),
+                LeaseTask {
+                    handle: task.abort_handle(),
+                    release,
+                },
+            );
             lease
         }
         //V6 TODO implement DHCPv6
@@ -427,17 +618,18 @@ async fn process_setup<W: Write + Clear>(
         }
     };
 
-    if let Err(e) = cache
-        .lock()
-        .expect("Could not unlock cache. A thread was poisoned")
-        .add_lease(mac, &nv_lease)
     {
-        return Err(Status::new(
-            Internal,
-            format!("Error caching the lease: {e}"),
-        ));
+        let mut locked_cache = cache
+            .lock()
+            .expect("Could not unlock cache. A thread was poisoned");
+        if let Err(e) = locked_cache.add_lease(mac, &nv_lease) {
+            return Err(Status::new(
+                Internal,
+                format!("Error caching the lease: {e}"),
+            ));
+        }
+        metrics.set_active_leases(locked_cache.len());
     }
 
-    ip::setup(&nv_lease, &container_network_interface, &ns_path)?;
     Ok(nv_lease)
 }
@@ -0,0 +1,102 @@
+//! Lists the network drivers netavark can use
+use crate::error::NetavarkResult;
+use crate::network::constants::BUILTIN_DRIVERS;
+use crate::network::plugin::{
+    check_api_version, get_plugin_timeout, list_plugins, read_plugin_info, PluginInfo,
+    PluginTransport,
+};
+
+use clap::Parser;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct Drivers {}
+
+#[derive(Debug, Serialize)]
+struct DriverEntry {
+    name: String,
+    /// "builtin" or "plugin"
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// the plugin executable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// the socket of a plugin daemon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket: Option<PathBuf>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    info: Option<PluginInfo>,
+    /// why the plugin cannot be used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Drivers {
+    /// The drivers command lists the built-in drivers and the plugins found in the plugin directories, the info of each plugin is included. Plugins which fail to report their info or use an API version netavark does not support have the error set.
+    pub fn exec(&self, plugin_directories: Option<Vec<OsString>>) -> NetavarkResult<()> {
+        let mut drivers: Vec<DriverEntry> = BUILTIN_DRIVERS
+            .iter()
+            .map(|name| DriverEntry {
+                name: name.to_string(),
+                kind: "builtin",
+                path: None,
+                socket: None,
+                info: None,
+                error: None,
+            })
+            .collect();
+
+        // only the environment can set a timeout as there is no network
+        let timeout = get_plugin_timeout(&None)?;
+        for name in list_plugins(&plugin_directories)? {
+            // built-in drivers always win, see get_network_driver()
+            if BUILTIN_DRIVERS.contains(&name.as_str()) {
+                continue;
+            }
+            // resolve the name like get_network_driver() does so shadowed
+            // plugins of later directories are not listed
+            let transport = match PluginTransport::find(&name, &plugin_directories) {
+                Ok(Some(transport)) => transport,
+                Ok(None) => continue,
+                Err(err) => {
+                    drivers.push(DriverEntry {
+                        name,
+                        kind: "plugin",
+                        path: None,
+                        socket: None,
+                        info: None,
+                        error: Some(err.to_string()),
+                    });
+                    continue;
+                }
+            };
+            let (path, socket) = match &transport {
+                PluginTransport::Exec(path) => (Some(path.clone()), None),
+                PluginTransport::Socket(socket) => (None, Some(socket.clone())),
+            };
+            let (info, error) = match read_plugin_info(&transport, timeout) {
+                Ok(info) => {
+                    let error = check_api_version(&info.api_version)
+                        .err()
+                        .map(|e| e.to_string());
+                    (Some(info), error)
+                }
+                Err(err) => (None, Some(err.to_string())),
+            };
+            drivers.push(DriverEntry {
+                name,
+                kind: "plugin",
+                path,
+                socket,
+                info,
+                error,
+            });
+        }
+
+        let out = serde_json::to_string_pretty(&drivers)?;
+        println!("{out}");
+        Ok(())
+    }
+}
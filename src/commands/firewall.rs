@@ -0,0 +1,354 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    path::Path,
+    slice,
+    thread::sleep,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use netlink_sys::{protocols::NETLINK_NETFILTER, Socket};
+use serde::Serialize;
+
+use crate::{
+    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    firewall::{
+        dump::FirewallDump, firewalld::SNAT_EXCLUDE_ERROR, get_supported_firewall_driver,
+        state::read_fw_config, FIREWALLD,
+    },
+    network::{
+        constants,
+        internal_types::{TearDownNetwork, TeardownPortForward},
+    },
+    wrap,
+};
+
+/// nfnetlink multicast group for nftables ruleset changes (NFNLGRP_NFTABLES).
+const NFNLGRP_NFTABLES: u32 = 7;
+
+/// Time to wait for more ruleset changes before reconciling.
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Parser, Debug)]
+pub struct Firewall {
+    #[clap(subcommand)]
+    subcmd: FirewallSubCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum FirewallSubCommand {
+    /// Print the netavark firewall rules as json.
+    Dump(Dump),
+    /// Re-apply firewall rules from the firewall state which are missing.
+    Reconcile(Reconcile),
+    /// Move the rules of all running containers to another firewall driver.
+    Migrate(Migrate),
+    /// Print the packets and bytes dropped by the isolation rules per network as json.
+    Counters,
+}
+
+#[derive(Parser, Debug)]
+pub struct Dump {
+    /// Print the rules expected from the stored firewall state instead of the live rules.
+    #[clap(long)]
+    state: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Reconcile {
+    /// Keep running and reconcile every time the nftables ruleset changes.
+    #[clap(long)]
+    watch: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Migrate {
+    /// Name of the firewall driver to migrate to.
+    #[clap(long)]
+    to: String,
+}
+
+impl Firewall {
+    pub fn exec(
+        &self,
+        config_dir: Option<OsString>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        let config_dir = Path::new(
+            config_dir
+                .as_deref()
+                .unwrap_or(OsStr::new(constants::DEFAULT_CONFIG_DIR)),
+        );
+        match &self.subcmd {
+            FirewallSubCommand::Dump(dump) => dump.exec(config_dir, firewall_driver),
+            FirewallSubCommand::Reconcile(reconcile) => reconcile.exec(config_dir),
+            FirewallSubCommand::Migrate(migrate) => migrate.exec(config_dir),
+            FirewallSubCommand::Counters => print_counters(config_dir),
+        }
+    }
+}
+
+impl Dump {
+    fn exec(&self, config_dir: &Path, firewall_driver: Option<String>) -> NetavarkResult<()> {
+        let conf = read_fw_config(config_dir).wrap("read firewall config")?;
+
+        let dump = if self.state {
+            match &conf {
+                Some(conf) => {
+                    FirewallDump::from_state(&conf.driver, &conf.net_confs, &conf.port_confs)
+                }
+                None => FirewallDump::default(),
+            }
+        } else {
+            // Prefer the driver that was used to create the rules.
+            let driver = conf.map(|c| c.driver).or(firewall_driver);
+            get_supported_firewall_driver(driver, Some(config_dir), false)?.dump()?
+        };
+
+        let out = serde_json::to_string_pretty(&dump)?;
+        println!("{out}");
+
+        Ok(())
+    }
+}
+
+impl Reconcile {
+    fn exec(&self, config_dir: &Path) -> NetavarkResult<()> {
+        if !self.watch {
+            return reconcile_rules(config_dir);
+        }
+
+        // Ruleset changes done with nft or iptables-nft are announced over netlink,
+        // changes to an iptables-legacy ruleset cannot be watched this way.
+        let mut socket = wrap!(
+            Socket::new(NETLINK_NETFILTER),
+            "open netfilter netlink socket"
+        )?;
+        wrap!(socket.bind_auto(), "bind netfilter netlink socket")?;
+        wrap!(
+            socket.add_membership(NFNLGRP_NFTABLES),
+            "subscribe to nftables events"
+        )?;
+
+        // Subscribe first so we do not miss any change done while reconciling.
+        log_reconcile_error(reconcile_rules(config_dir));
+
+        let mut buf = vec![0; 65536];
+        loop {
+            match socket.recv(&mut &mut buf[..], 0) {
+                Ok(_) => {}
+                // the kernel dropped events because we were too slow, a change happened anyway
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {}
+                Err(e) => return Err(NetavarkError::wrap("receive nftables events", e.into())),
+            }
+
+            // A single nft command can send many events, wait until things settle.
+            sleep(WATCH_SETTLE_TIME);
+            drain_socket(&socket, &mut buf)?;
+
+            log::debug!("nftables ruleset changed, reconciling firewall rules");
+            log_reconcile_error(reconcile_rules(config_dir));
+        }
+    }
+}
+
+impl Migrate {
+    fn exec(&self, config_dir: &Path) -> NetavarkResult<()> {
+        // Hold the lock for the whole migration so no container can be set up
+        // or torn down with the old driver in the meantime.
+        let mut conf = match read_fw_config(config_dir).wrap("read firewall config")? {
+            Some(conf) => conf,
+            None => {
+                log::info!("No firewall state found, nothing to migrate");
+                return Ok(());
+            }
+        };
+        let old_driver =
+            get_supported_firewall_driver(Some(conf.driver.clone()), Some(config_dir), false)?;
+        // Detect without the config dir so "auto" does not pick the recorded driver,
+        // then open it again with the config dir for the drivers which read the state.
+        let new_driver = get_supported_firewall_driver(Some(self.to.clone()), None, false)?;
+        let new_driver = get_supported_firewall_driver(
+            Some(new_driver.driver_name().to_string()),
+            Some(config_dir),
+            false,
+        )?;
+        if old_driver.driver_name() == new_driver.driver_name() {
+            log::info!(
+                "Firewall driver {} is already in use",
+                new_driver.driver_name()
+            );
+            return Ok(());
+        }
+
+        if new_driver.driver_name() == FIREWALLD {
+            if let Some(net) = conf.net_confs.iter().find(|n| !n.snat_exclude.is_empty()) {
+                return Err(NetavarkError::msg(format!(
+                    "network {}: {SNAT_EXCLUDE_ERROR}",
+                    net.network_id
+                )));
+            }
+        }
+
+        // Add the new rules before removing the old ones so that the
+        // containers do not lose connectivity during the migration.
+        for net in &conf.net_confs {
+            new_driver
+                .setup_network(net.clone())
+                .wrap(format!("setup network {} rules", net.network_id))?;
+        }
+        for port in &conf.port_confs {
+            new_driver.setup_port_forward(port.into()).wrap(format!(
+                "setup port forwarding rules for container {}",
+                port.container_id
+            ))?;
+        }
+        // From now on teardown must use the new driver.
+        conf.set_driver(new_driver.driver_name())?;
+
+        for port in &conf.port_confs {
+            old_driver
+                .teardown_port_forward(TeardownPortForward {
+                    config: port.into(),
+                    complete_teardown: true,
+                })
+                .wrap(format!(
+                    "remove old port forwarding rules for container {}",
+                    port.container_id
+                ))?;
+        }
+        for net in &conf.net_confs {
+            old_driver
+                .teardown_network(TearDownNetwork {
+                    config: net.clone(),
+                    complete_teardown: true,
+                })
+                .wrap(format!("remove old network {} rules", net.network_id))?;
+        }
+
+        log::info!(
+            "Migrated firewall rules from {} to {}",
+            old_driver.driver_name(),
+            new_driver.driver_name()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NetworkCounter {
+    network_id: String,
+    bridge: String,
+    packets: u64,
+    bytes: u64,
+}
+
+/// Print the isolation drop counters of all networks in the firewall state.
+fn print_counters(config_dir: &Path) -> NetavarkResult<()> {
+    let mut out: Vec<NetworkCounter> = Vec::new();
+    if let Some(conf) = read_fw_config(config_dir).wrap("read firewall config")? {
+        let counters =
+            get_supported_firewall_driver(Some(conf.driver.clone()), Some(config_dir), false)?
+                .drop_counters()?;
+        for net in &conf.net_confs {
+            if out.iter().any(|c| c.network_id == net.network_id) {
+                continue;
+            }
+            if let Some(c) = counters.iter().find(|c| c.bridge == net.bridge_name) {
+                out.push(NetworkCounter {
+                    network_id: net.network_id.clone(),
+                    bridge: c.bridge.clone(),
+                    packets: c.packets,
+                    bytes: c.bytes,
+                });
+            }
+        }
+    }
+
+    let out = serde_json::to_string_pretty(&out)?;
+    println!("{out}");
+    Ok(())
+}
+
+/// Read all pending events from the socket without blocking.
+fn drain_socket(socket: &Socket, buf: &mut [u8]) -> NetavarkResult<()> {
+    loop {
+        match socket.recv(&mut &mut buf[..], libc::MSG_DONTWAIT) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {}
+            Err(e) => return Err(NetavarkError::wrap("receive nftables events", e.into())),
+        }
+    }
+}
+
+fn log_reconcile_error(res: NetavarkResult<()>) {
+    if let Err(e) = res {
+        log::error!("failed to reconcile firewall rules: {e}");
+    }
+}
+
+/// Compare the live firewall rules with the firewall state and re-apply the
+/// network and port forwarding rules that are missing.
+fn reconcile_rules(config_dir: &Path) -> NetavarkResult<()> {
+    // The lock is held until conf is dropped so no container can be set up
+    // or torn down while we re-add the rules.
+    let conf = match read_fw_config(config_dir).wrap("read firewall config")? {
+        Some(conf) => conf,
+        // If we got no conf there are no containers so nothing to do.
+        None => return Ok(()),
+    };
+    let fw_driver =
+        get_supported_firewall_driver(Some(conf.driver.clone()), Some(config_dir), false)?;
+    let live = fw_driver.dump()?;
+
+    let mut readded = false;
+    for net in &conf.net_confs {
+        let expected = FirewallDump::from_state(&conf.driver, slice::from_ref(net), &[]);
+        if live.contains(&expected) {
+            continue;
+        }
+        log::info!(
+            "Re-adding missing firewall rules for network {}",
+            net.network_id
+        );
+        fw_driver.setup_network(net.clone())?;
+        readded = true;
+    }
+    // The firewalld isolation rules are between networks so they are only part of
+    // the dump of all networks, setting up any network updates all of them.
+    if !readded
+        && !live.contains(&FirewallDump::from_state(
+            &conf.driver,
+            &conf.net_confs,
+            &[],
+        ))
+    {
+        if let Some(net) = conf.net_confs.first() {
+            log::info!("Re-adding missing firewall rules between networks");
+            fw_driver.setup_network(net.clone())?;
+        }
+    }
+
+    for port in &conf.port_confs {
+        let expected = FirewallDump::from_state(&conf.driver, &[], slice::from_ref(port));
+        if live.contains(&expected) {
+            continue;
+        }
+        log::info!(
+            "Re-adding missing port forwarding rules for container {} on network {}",
+            port.container_id,
+            port.network_name
+        );
+        // Adding port rules is not idempotent for all drivers,
+        // remove what is left first so we do not duplicate rules.
+        fw_driver.teardown_port_forward(TeardownPortForward {
+            config: port.into(),
+            complete_teardown: false,
+        })?;
+        fw_driver.setup_port_forward(port.into())?;
+    }
+
+    Ok(())
+}
@@ -3,10 +3,15 @@ use std::ffi::OsString;
 use crate::error::{NetavarkError, NetavarkResult};
 
 pub mod dhcp_proxy;
+pub mod drivers;
+pub mod firewall;
 pub mod firewalld_reload;
+pub mod schema;
 pub mod setup;
+pub mod status;
 pub mod teardown;
 pub mod update;
+pub mod validate;
 pub mod version;
 
 fn get_config_dir(dir: Option<OsString>, cmd: &str) -> NetavarkResult<OsString> {
@@ -0,0 +1,55 @@
+//! Prints the JSON Schemas of the netavark input and output types
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::types;
+use crate::plugin;
+
+use clap::Parser;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use std::collections::BTreeMap;
+
+type SchemaFn = fn() -> RootSchema;
+
+/// The types with a schema, the input of setup and teardown, the network
+/// config, the setup output and the plugin input and info output.
+const SCHEMAS: [(&str, SchemaFn); 5] = [
+    ("NetworkOptions", || schema_for!(types::NetworkOptions)),
+    ("Network", || schema_for!(types::Network)),
+    ("StatusBlock", || schema_for!(types::StatusBlock)),
+    ("NetworkPluginExec", || {
+        schema_for!(types::NetworkPluginExec)
+    }),
+    ("PluginInfo", || schema_for!(plugin::Info)),
+];
+
+#[derive(Parser, Debug)]
+pub struct Schema {
+    /// Only print the schema of this type.
+    #[clap(value_parser = SCHEMAS.map(|(name, _)| name))]
+    name: Option<String>,
+}
+
+impl Schema {
+    /// The schema command prints the JSON Schema of the given type, without a type it prints an object with the schemas of all types keyed by their name.
+    pub fn exec(&self) -> NetavarkResult<()> {
+        let out = match &self.name {
+            Some(name) => {
+                let (_, schema) = SCHEMAS
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .ok_or_else(|| NetavarkError::msg(format!("unknown schema {name}")))?;
+                serde_json::to_string_pretty(&schema())?
+            }
+            None => {
+                let schemas: BTreeMap<&str, RootSchema> = SCHEMAS
+                    .iter()
+                    .map(|(name, schema)| (*name, schema()))
+                    .collect();
+                serde_json::to_string_pretty(&schemas)?
+            }
+        };
+        println!("{out}");
+
+        Ok(())
+    }
+}
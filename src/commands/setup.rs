@@ -48,9 +48,35 @@ impl Setup {
             }
         }
         debug!("Setting up...");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        network::validation::validate_port_mappings(&network_options.port_mappings)?;
 
-        let firewall_driver = match firewall::get_supported_firewall_driver(firewall_driver) {
+        let config_dir = get_config_dir(config_dir, "setup")?;
+
+        // host_port 0 means we have to pick a free host port, this must be done
+        // before the drivers are created as they all have to use the same port
+        if rootless
+            && network_options
+                .port_mappings
+                .iter()
+                .flatten()
+                .any(|p| p.host_port == 0)
+        {
+            // the firewall state is not written in rootless mode so teardown
+            // would have no way to find the allocated port
+            return Err(NetavarkError::msg(
+                "host port 0 is not supported in rootless mode",
+            ));
+        }
+        let allocated_ports = match network_options.port_mappings.as_mut() {
+            Some(ports) => firewall::state::allocate_host_ports(Path::new(&config_dir), ports)?,
+            None => false,
+        };
+        let firewall_driver = match firewall::get_supported_firewall_driver(
+            firewall_driver,
+            Some(Path::new(&config_dir)),
+            rootless,
+        ) {
             Ok(driver) => driver,
             Err(e) => return Err(e),
         };
@@ -65,7 +91,6 @@ impl Setup {
         // setup loopback, it should be safe to assume that 1 is the loopback index
         netns.netlink.set_up(LinkID::ID(1))?;
 
-        let config_dir = get_config_dir(config_dir, "setup")?;
         let mut drivers = Vec::with_capacity(network_options.network_info.len());
 
         // Perform per-network setup
@@ -104,7 +129,7 @@ impl Setup {
         // Only now after we validated all drivers we setup each.
         // If there is an error we have to tear down all previous drivers.
         for (i, driver) in drivers.iter().enumerate() {
-            let (status, aardvark_entry) =
+            let (mut status, aardvark_entry) =
                 match driver.setup((&mut hostns.netlink, &mut netns.netlink)) {
                     Ok((s, a)) => (s, a),
                     Err(e) => {
@@ -124,6 +149,9 @@ impl Setup {
                     }
                 };
 
+            if allocated_ports {
+                status.port_mappings = network_options.port_mappings.clone();
+            }
             let _ = response.insert(driver.network_name(), status);
             if let Some(a) = aardvark_entry {
                 aardvark_entries.push(a);
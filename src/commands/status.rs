@@ -0,0 +1,90 @@
+//! Reports the live network state of the given network namespace
+use crate::commands::get_config_dir;
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self};
+use crate::network::{core_utils, types};
+
+use clap::builder::NonEmptyStringValueParser;
+use clap::Parser;
+use log::debug;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::fd::AsFd;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Status {
+    /// Network namespace path
+    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
+    network_namespace_path: String,
+}
+
+impl Status {
+    /// The status command asks the drivers for the current state of the given network namespace. Only plugins which implement the status subcommand report it, other networks are left out of the output.
+    pub fn new(network_namespace_path: String) -> Self {
+        Self {
+            network_namespace_path,
+        }
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<OsString>,
+        config_dir: Option<OsString>,
+        firewall_driver: Option<String>,
+        plugin_directories: Option<Vec<OsString>>,
+        rootless: bool,
+    ) -> NetavarkResult<()> {
+        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let config_dir = get_config_dir(config_dir, "status")?;
+
+        let firewall_driver = firewall::get_supported_firewall_driver(
+            firewall_driver,
+            Some(Path::new(&config_dir)),
+            rootless,
+        )?;
+
+        let dns_port = core_utils::get_netavark_dns_port()?;
+        let (hostns, netns) = core_utils::open_netlink_sockets(&self.network_namespace_path)?;
+
+        let mut response: HashMap<String, types::StatusBlock> = HashMap::new();
+        for (net_name, network) in network_options.network_info.iter() {
+            let per_network_opts = network_options.networks.get(net_name).ok_or_else(|| {
+                NetavarkError::Message(format!("network options for network {net_name} not found"))
+            })?;
+
+            let mut driver = get_network_driver(
+                DriverInfo {
+                    firewall: firewall_driver.as_ref(),
+                    container_id: &network_options.container_id,
+                    container_name: &network_options.container_name,
+                    container_dns_servers: &network_options.dns_servers,
+                    netns_host: hostns.file.as_fd(),
+                    netns_container: netns.file.as_fd(),
+                    netns_path: &self.network_namespace_path,
+                    network,
+                    per_network_opts,
+                    port_mappings: &network_options.port_mappings,
+                    dns_port,
+                    config_dir: Path::new(&config_dir),
+                    rootless,
+                },
+                &plugin_directories,
+            )?;
+
+            driver.validate()?;
+            match driver.status()? {
+                Some(status) => {
+                    let _ = response.insert(driver.network_name(), status);
+                }
+                None => debug!("driver of network {net_name} does not report a status"),
+            }
+        }
+
+        let response_json = serde_json::to_string(&response)?;
+        println!("{response_json}");
+        Ok(())
+    }
+}
@@ -4,7 +4,10 @@ use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
 use crate::network::constants::DRIVER_BRIDGE;
 use crate::network::core_utils;
 use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::plugin::plugin_requested_dns;
+use crate::network::types::NetworkOptions;
 
+use crate::firewall::state::read_port_mappings;
 use crate::{firewall, network};
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
@@ -38,7 +41,7 @@ impl Teardown {
         rootless: bool,
     ) -> NetavarkResult<()> {
         debug!("Tearing down..");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
 
         let mut error_list = NetavarkErrorList::new();
 
@@ -47,7 +50,16 @@ impl Teardown {
 
         let mut aardvark_entries = Vec::new();
         for (key, network) in &network_options.network_info {
-            if network.dns_enabled && network.driver == DRIVER_BRIDGE {
+            // plugins have an aardvark entry only when they requested dns, this
+            // must be checked before the driver teardown removes the plugin state
+            if network.dns_enabled
+                && (network.driver == DRIVER_BRIDGE
+                    || plugin_requested_dns(
+                        Path::new(&config_dir),
+                        &network.id,
+                        &network_options.container_id,
+                    ))
+            {
                 aardvark_entries.push(AardvarkEntry {
                     network_name: key,
                     network_gateways: Vec::new(),
@@ -72,11 +84,19 @@ impl Teardown {
             }
         }
 
-        let firewall_driver = match firewall::get_supported_firewall_driver(firewall_driver) {
+        let firewall_driver = match firewall::get_supported_firewall_driver(
+            firewall_driver,
+            Some(Path::new(&config_dir)),
+            rootless,
+        ) {
             Ok(driver) => driver,
             Err(e) => return Err(e),
         };
 
+        if let Err(err) = resolve_allocated_ports(&mut network_options, Path::new(&config_dir)) {
+            error_list.push(err);
+        }
+
         let (mut hostns, mut netns) =
             core_utils::open_netlink_sockets(&self.network_namespace_path)?;
 
@@ -133,3 +153,32 @@ impl Teardown {
         Ok(())
     }
 }
+
+/// Port mappings with host_port 0 got a host port allocated on setup, the caller may
+/// not pass the allocated ports back so look them up in the stored port configs.
+/// Mappings we cannot resolve are removed and an error is returned as there is no
+/// way to match their rules, the other mappings can still be torn down.
+fn resolve_allocated_ports(
+    network_options: &mut NetworkOptions,
+    config_dir: &Path,
+) -> NetavarkResult<()> {
+    let Some(ports) = &network_options.port_mappings else {
+        return Ok(());
+    };
+    if !ports.iter().any(|p| p.host_port == 0) {
+        return Ok(());
+    }
+    for network in network_options.network_info.values() {
+        if let Some(stored) =
+            read_port_mappings(config_dir, &network.id, &network_options.container_id)?
+        {
+            network_options.port_mappings = Some(stored);
+            return Ok(());
+        }
+    }
+    network_options.port_mappings =
+        Some(ports.iter().filter(|p| p.host_port != 0).cloned().collect());
+    Err(NetavarkError::msg(
+        "no stored port mappings found, cannot remove the port mappings with host port 0",
+    ))
+}
@@ -1,12 +1,17 @@
 use crate::commands::get_config_dir;
 use crate::dns::aardvark::Aardvark;
-use crate::error::{NetavarkError, NetavarkResult};
-use crate::network::core_utils;
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::network::constants::BUILTIN_DRIVERS;
+use crate::network::plugin::{update_plugin, PluginTransport};
+use crate::network::{core_utils, types};
+use crate::wrap;
 
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
 use log::debug;
 use std::ffi::OsString;
+use std::fs::File;
+use std::net::IpAddr;
 use std::path::Path;
 
 #[derive(Parser, Debug)]
@@ -30,8 +35,10 @@ impl Update {
 
     pub fn exec(
         &mut self,
+        input_file: Option<OsString>,
         config_dir: Option<OsString>,
         aardvark_bin: OsString,
+        plugin_directories: Option<Vec<OsString>>,
         rootless: bool,
     ) -> NetavarkResult<()> {
         let dns_port = core_utils::get_netavark_dns_port()?;
@@ -54,7 +61,54 @@ impl Update {
             }
         }
 
+        // The network config is only passed by callers which know about
+        // plugins, they are told about the new dns servers and options.
+        if let Some(file) = input_file {
+            self.update_plugin(file, &plugin_directories)?;
+        }
+
         debug!("Network update complete");
         Ok(())
     }
+
+    fn update_plugin(
+        &self,
+        file: OsString,
+        plugin_directories: &Option<Vec<OsString>>,
+    ) -> NetavarkResult<()> {
+        let mut network: types::Network = serde_json::from_reader(wrap!(
+            File::open(&file),
+            format!("failed to open network config {file:?}")
+        )?)?;
+        if network.name != self.network_name {
+            return Err(NetavarkError::msg(format!(
+                "network config is for network {} not {}",
+                network.name, self.network_name
+            )));
+        }
+
+        let driver = network.driver.as_str();
+        if BUILTIN_DRIVERS.contains(&driver) {
+            return Ok(());
+        }
+        let transport = PluginTransport::find(driver, plugin_directories)?.ok_or_else(|| {
+            NetavarkError::Message(format!("unknown network driver \"{driver}\""))
+        })?;
+
+        let dns_servers = self
+            .network_dns_servers
+            .iter()
+            .map(|server| {
+                server.parse::<IpAddr>().map_err(|_| {
+                    NetavarkError::msg(format!("invalid network dns server \"{server}\""))
+                })
+            })
+            .collect::<NetavarkResult<Vec<_>>>()?;
+        network.network_dns_servers = Some(dns_servers);
+
+        if !update_plugin(&transport, &network).wrap(format!("plugin {driver:?} failed"))? {
+            debug!("plugin {driver} does not support update");
+        }
+        Ok(())
+    }
 }
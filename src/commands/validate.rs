@@ -0,0 +1,180 @@
+//! Validates network configs without changing anything on the system
+use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
+use crate::firewall::fwnone;
+use crate::network::constants::IPAM_HOSTLOCAL;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::types::{Network, NetworkOptions, PerNetworkOptions};
+use crate::network::{core_utils, validation};
+use crate::wrap;
+
+use clap::Parser;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::os::fd::AsFd;
+use std::path::Path;
+
+/// The drivers need a netns to be created, validate() never uses it.
+const VALIDATE_NETNS_PATH: &str = "/proc/thread-self/ns/net";
+
+/// Interface and container name used when only a network config is given.
+const PLACEHOLDER_NAME: &str = "validate";
+
+#[derive(Parser, Debug)]
+pub struct Validate {}
+
+impl Validate {
+    /// The validate command checks a network config or the full setup input, it runs the validation of the drivers and static checks of the subnets, static ips and port mappings. All problems are returned at once.
+    pub fn exec(
+        &self,
+        input_file: Option<OsString>,
+        plugin_directories: Option<Vec<OsString>>,
+    ) -> NetavarkResult<()> {
+        let input: serde_json::Value = match input_file {
+            Some(path) => serde_json::from_reader(BufReader::new(wrap!(
+                File::open(&path),
+                format!("failed to open input {path:?}")
+            )?)),
+            None => serde_json::from_reader(io::stdin()),
+        }
+        .map_err(|e| NetavarkError::wrap("failed to parse input", e.into()))?;
+
+        // both are objects, only the network options have the network_info map
+        let (network_options, static_ips_given) = if input.get("network_info").is_some() {
+            let opts: NetworkOptions = wrap!(
+                serde_json::from_value(input),
+                "failed to parse network options"
+            )?;
+            (opts, true)
+        } else {
+            let network: Network = wrap!(serde_json::from_value(input), "failed to parse network")?;
+            (placeholder_network_options(network), false)
+        };
+
+        let errors =
+            validate_network_options(&network_options, static_ips_given, &plugin_directories)?;
+        if !errors.is_empty() {
+            return Err(NetavarkError::List(errors));
+        }
+        Ok(())
+    }
+}
+
+fn validate_network_options(
+    opts: &NetworkOptions,
+    static_ips_given: bool,
+    plugin_directories: &Option<Vec<OsString>>,
+) -> NetavarkResult<NetavarkErrorList> {
+    let mut errors = NetavarkErrorList::new();
+
+    for port in opts.port_mappings.iter().flatten() {
+        if let Err(err) = validation::validate_port_mappings(&Some(vec![port.clone()])) {
+            errors.push(err);
+        }
+    }
+    for err in validation::check_overlapping_networks(&opts.network_info) {
+        errors.push(err);
+    }
+
+    let firewall = fwnone::new()?;
+    let netns = wrap!(File::open(VALIDATE_NETNS_PATH), "open netns")?;
+    let dns_port = core_utils::get_netavark_dns_port()?;
+
+    let mut names: Vec<&String> = opts.network_info.keys().collect();
+    names.sort();
+    for name in names {
+        let network = &opts.network_info[name];
+        let mut network_errors = validation::check_network_subnets(network);
+        let per_network_opts = match opts.networks.get(name) {
+            Some(per_network_opts) => per_network_opts,
+            None => {
+                network_errors.push(NetavarkError::msg("no network options for the network"));
+                push_network_errors(&mut errors, name, network_errors);
+                continue;
+            }
+        };
+        if static_ips_given {
+            network_errors.extend(validation::check_static_ips(network, per_network_opts));
+        }
+
+        let result = get_network_driver(
+            DriverInfo {
+                firewall: firewall.as_ref(),
+                container_id: &opts.container_id,
+                container_name: &opts.container_name,
+                container_dns_servers: &opts.dns_servers,
+                netns_host: netns.as_fd(),
+                netns_container: netns.as_fd(),
+                netns_path: VALIDATE_NETNS_PATH,
+                network,
+                per_network_opts,
+                port_mappings: &opts.port_mappings,
+                dns_port,
+                config_dir: Path::new(""),
+                rootless: false,
+            },
+            plugin_directories,
+        )
+        .and_then(|mut driver| driver.validate());
+        if let Err(err) = result {
+            network_errors.push(err);
+        }
+        push_network_errors(&mut errors, name, network_errors);
+    }
+    Ok(errors)
+}
+
+fn push_network_errors(
+    errors: &mut NetavarkErrorList,
+    name: &str,
+    network_errors: Vec<NetavarkError>,
+) {
+    for err in network_errors {
+        errors.push(NetavarkError::wrap(format!("network {name}"), err));
+    }
+}
+
+/// Build the setup input for a single network with a container which has
+/// the first usable address of each subnet as static ip.
+fn placeholder_network_options(network: Network) -> NetworkOptions {
+    let hostlocal = matches!(
+        network
+            .ipam_options
+            .as_ref()
+            .and_then(|map| map.get("driver"))
+            .map(String::as_str),
+        None | Some(IPAM_HOSTLOCAL)
+    );
+    let static_ips = hostlocal.then(|| {
+        network
+            .subnets
+            .iter()
+            .flatten()
+            .map(|subnet| {
+                subnet
+                    .subnet
+                    .hosts()
+                    .find(|ip| Some(*ip) != subnet.gateway)
+                    .unwrap_or_else(|| subnet.subnet.addr())
+            })
+            .collect()
+    });
+    NetworkOptions {
+        container_id: PLACEHOLDER_NAME.to_string(),
+        container_name: PLACEHOLDER_NAME.to_string(),
+        networks: HashMap::from([(
+            network.name.clone(),
+            PerNetworkOptions {
+                aliases: None,
+                interface_name: "eth0".to_string(),
+                static_ips,
+                static_mac: None,
+                options: None,
+            },
+        )]),
+        network_info: HashMap::from([(network.name.clone(), network)]),
+        port_mappings: None,
+        dns_servers: None,
+    }
+}
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::{Cursor, Write};
+use std::time::SystemTime;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -34,6 +35,8 @@ impl Clear for File {
 #[derive(Debug)]
 pub struct LeaseCache<W: Write + Clear> {
     mem: HashMap<String, Vec<NetavarkLease>>,
+    // time when the lease was obtained or last renewed, only kept in memory
+    obtained: HashMap<String, SystemTime>,
     writer: W,
 }
 
@@ -50,6 +53,7 @@ impl<W: Write + Clear> LeaseCache<W> {
     pub fn new(writer: W) -> Result<LeaseCache<W>, io::Error> {
         Ok(LeaseCache {
             mem: HashMap::new(),
+            obtained: HashMap::new(),
             writer,
         })
     }
@@ -68,11 +72,15 @@ impl<W: Write + Clear> LeaseCache<W> {
         // Update cache memory with new lease
         let cache = &mut self.mem;
         cache.insert(mac_addr.to_string(), vec![lease.clone()]);
+        self.obtained
+            .insert(mac_addr.to_string(), SystemTime::now());
         // write updated memory cache to the file system
         self.save_memory_to_fs()
     }
 
     /// When a lease changes, update the lease in memory and on the writer.
+    /// Only existing leases are updated, a lease removed by a teardown must
+    /// not be added back by a renewal that raced with it.
     ///
     /// # Arguments
     ///
@@ -82,9 +90,16 @@ impl<W: Write + Clear> LeaseCache<W> {
     /// returns: Result<(), Error>
     ///
     pub fn update_lease(&mut self, mac_addr: &str, lease: NetavarkLease) -> Result<(), io::Error> {
-        let cache = &mut self.mem;
         // write to the memory cache
-        cache.insert(mac_addr.to_string(), vec![lease]);
+        match self.mem.get_mut(mac_addr) {
+            Some(leases) => *leases = vec![lease],
+            None => {
+                debug!("lease for {:?} was removed, not updating it", mac_addr);
+                return Ok(());
+            }
+        }
+        self.obtained
+            .insert(mac_addr.to_string(), SystemTime::now());
         // write updated memory cache to the file system
         self.save_memory_to_fs()
     }
@@ -116,9 +131,11 @@ impl<W: Write + Clear> LeaseCache<W> {
                 gateways: vec![],
                 ntp_servers: vec![],
                 host_name: "".to_string(),
+                static_routes: vec![],
             },
             Some(l) => l[0].clone(),
         };
+        self.obtained.remove(mac_addr);
         // Try and remove the lease. If it doesnt exist, exit with the blank lease
         if mem.remove(mac_addr).is_none() {
             return Ok(lease);
@@ -134,9 +151,40 @@ impl<W: Write + Clear> LeaseCache<W> {
     /// Clean up the memory and file system on tear down of the proxy server
     pub fn teardown(&mut self) -> Result<(), io::Error> {
         self.mem.clear();
+        self.obtained.clear();
         self.save_memory_to_fs()
     }
 
+    /// Get the lease for a mac address and the time when it was obtained.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac_addr`: Mac address of the container
+    ///
+    /// returns: Option<(&Lease, SystemTime)>
+    ///
+    pub fn get_lease(&self, mac_addr: &str) -> Option<(&NetavarkLease, SystemTime)> {
+        let lease = self.mem.get(mac_addr)?.first()?;
+        let obtained = self
+            .obtained
+            .get(mac_addr)
+            .copied()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Some((lease, obtained))
+    }
+
+    /// Get all leases in the cache together with their mac address and the
+    /// time when they were obtained.
+    pub fn leases(&self) -> Vec<(&str, &NetavarkLease, SystemTime)> {
+        self.mem
+            .keys()
+            .filter_map(|mac| {
+                self.get_lease(mac)
+                    .map(|(lease, obtained)| (mac.as_str(), lease, obtained))
+            })
+            .collect()
+    }
+
     /// Save the memory contents to the file system. This will remove the contents in the file,
     /// then write the memory map to the file. This method will be called any the lease memory cache
     /// changes (new lease, remove lease, update lease)
@@ -222,6 +270,7 @@ mod cache_tests {
             gateways: vec![],
             ntp_servers: vec![],
             host_name: "example.host_name".to_string(),
+            static_routes: vec![],
             is_v6: false,
         }
     }
@@ -456,4 +505,62 @@ mod cache_tests {
             assert_eq!(deserialized_updated_lease, &new_lease);
         }
     }
+
+    #[test]
+    fn update_removed_lease() {
+        let setup = CacheTestSetup::new();
+        let mut cache = setup.cache;
+
+        let mac_address = random_macaddr();
+        cache
+            .add_lease(&mac_address, &random_lease(&mac_address))
+            .expect("could not add lease to cache");
+        cache
+            .remove_lease(&mac_address)
+            .expect("Could not remove the lease");
+
+        // A renewal after the teardown must not add the lease back
+        cache
+            .update_lease(&mac_address, random_lease(&mac_address))
+            .expect("Could not update the lease");
+        assert!(cache.get_lease(&mac_address).is_none());
+
+        let lease_bytes = cache.writer.get_ref().as_slice();
+        let s: HashMap<String, Vec<NetavarkLease>> = match serde_json::from_slice(lease_bytes) {
+            Ok(s) => s,
+            Err(e) => panic!("Error: {e:?}"),
+        };
+        assert!(s.get(&mac_address).is_none());
+    }
+
+    #[test]
+    fn get_leases() {
+        let setup = CacheTestSetup::new();
+        let mut cache = setup.cache;
+
+        let mac_address = random_macaddr();
+        assert!(cache.get_lease(&mac_address).is_none());
+
+        let lease = random_lease(&mac_address);
+        cache
+            .add_lease(&mac_address, &lease)
+            .expect("could not add lease to cache");
+        let other_mac = random_macaddr();
+        cache
+            .add_lease(&other_mac, &random_lease(&other_mac))
+            .expect("could not add lease to cache");
+
+        let (cached, obtained) = cache
+            .get_lease(&mac_address)
+            .expect("Could not get the lease from the cache");
+        assert_eq!(cached, &lease);
+        assert!(obtained > std::time::SystemTime::UNIX_EPOCH);
+        assert_eq!(cache.leases().len(), 2);
+
+        cache
+            .remove_lease(&mac_address)
+            .expect("Could not remove the lease");
+        assert!(cache.get_lease(&mac_address).is_none());
+        assert_eq!(cache.leases().len(), 1);
+    }
 }
@@ -1,21 +1,29 @@
+use std::io::Write;
 use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
 
 use crate::dhcp_proxy::dhcp_service::DhcpServiceErrorKind::{
     Bug, InvalidArgument, NoLease, Timeout,
 };
 
-use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, NetworkConfig};
+use crate::dhcp_proxy::cache::{Clear, LeaseCache};
+use crate::dhcp_proxy::ip;
+use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, NetworkConfig, StaticRoute};
+use crate::dhcp_proxy::metrics::Metrics;
+use crate::dhcp_proxy::types::ProxyError;
 use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
 use crate::network::core_utils;
 use crate::network::netlink::Route;
 use crate::wrap;
 use log::debug;
 use mozim::{DhcpV4ClientAsync, DhcpV4Config, DhcpV4Lease as MozimV4Lease};
+use tokio::sync::oneshot;
 use tokio_stream::StreamExt;
 
 use tonic::{Code, Status};
 
 /// The kind of DhcpServiceError that can be caused when finding a dhcp lease
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DhcpServiceErrorKind {
     Timeout,
     InvalidArgument,
@@ -26,7 +34,23 @@ pub enum DhcpServiceErrorKind {
     Unimplemented,
 }
 
+impl DhcpServiceErrorKind {
+    /// Short name of the error kind, used as metrics label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Timeout => "timeout",
+            InvalidArgument => "invalid_argument",
+            DhcpServiceErrorKind::InvalidDhcpServerReply => "invalid_dhcp_server_reply",
+            NoLease => "no_lease",
+            Bug => "bug",
+            DhcpServiceErrorKind::LeaseExpired => "lease_expired",
+            DhcpServiceErrorKind::Unimplemented => "unimplemented",
+        }
+    }
+}
+
 /// A DhcpServiceError is an error caused in the process of finding a dhcp lease
+#[derive(Debug)]
 pub struct DhcpServiceError {
     kind: DhcpServiceErrorKind,
     msg: String,
@@ -36,6 +60,10 @@ impl DhcpServiceError {
     pub fn new(kind: DhcpServiceErrorKind, msg: String) -> Self {
         DhcpServiceError { kind, msg }
     }
+
+    pub fn kind(&self) -> DhcpServiceErrorKind {
+        self.kind
+    }
 }
 
 /// DHCP service is responsible for creating, handling, and managing the dhcp lease process.
@@ -43,12 +71,30 @@ pub struct DhcpV4Service {
     client: DhcpV4ClientAsync,
     network_config: NetworkConfig,
     previous_lease: Option<MozimV4Lease>,
+    /// classless static routes applied in the container
+    static_routes: Vec<StaticRoute>,
+}
+
+/// Create the mozim config for a network configuration.
+///
+/// The hostname (option 12) and the client identifier (option 61) are independent, the
+/// server identifies the client by its mac address unless a client identifier is set.
+fn new_dhcp_config(nc: &NetworkConfig, timeout: u32) -> DhcpV4Config {
+    let mut config = DhcpV4Config::new_proxy(&nc.host_iface, &nc.container_mac_addr);
+    config.set_timeout(timeout);
+    if !nc.host_name.is_empty() {
+        config.set_host_name(&nc.host_name);
+    }
+    if !nc.client_id.is_empty() {
+        // type 0, the identifier is not a hardware address (RFC 2132)
+        config.set_client_id(0, nc.client_id.as_bytes());
+    }
+    config
 }
 
 impl DhcpV4Service {
     pub fn new(nc: NetworkConfig, timeout: u32) -> Result<Self, DhcpServiceError> {
-        let mut config = DhcpV4Config::new_proxy(&nc.host_iface, &nc.container_mac_addr);
-        config.set_timeout(timeout);
+        let config = new_dhcp_config(&nc, timeout);
         let client = match DhcpV4ClientAsync::init(config, None) {
             Ok(client) => Ok(client),
             Err(err) => Err(DhcpServiceError::new(InvalidArgument, err.to_string())),
@@ -57,9 +103,18 @@ impl DhcpV4Service {
             client,
             network_config: nc,
             previous_lease: None,
+            static_routes: Vec::new(),
         })
     }
 
+    /// Convert a mozim lease into the lease that is cached and returned to netavark.
+    fn netavark_lease(&self, lease: &MozimV4Lease) -> NetavarkLease {
+        let mut netavark_lease = <NetavarkLease as From<MozimV4Lease>>::from(lease.clone());
+        netavark_lease.add_domain_name(&self.network_config.domain_name);
+        netavark_lease.add_mac_address(&self.network_config.container_mac_addr);
+        netavark_lease
+    }
+
     /// Performs a DHCP DORA on a ipv4 network configuration.
     /// # Arguments
     ///
@@ -71,10 +126,7 @@ impl DhcpV4Service {
         if let Some(lease_result) = self.client.next().await {
             match lease_result {
                 Ok(lease) => {
-                    let mut netavark_lease =
-                        <NetavarkLease as From<MozimV4Lease>>::from(lease.clone());
-                    netavark_lease.add_domain_name(&self.network_config.domain_name);
-                    netavark_lease.add_mac_address(&self.network_config.container_mac_addr);
+                    let netavark_lease = self.netavark_lease(&lease);
                     debug!(
                         "found a lease for {:?}, {:?}",
                         &self.network_config.container_mac_addr, &netavark_lease
@@ -105,8 +157,40 @@ impl DhcpV4Service {
             "Could not find a lease within the timeout limit".to_string(),
         ))
     }
+
+    /// Apply the classless static routes (option 121) of the lease in the container.
+    /// Routes of the previous lease which are gone are removed.
+    pub fn update_static_routes(&mut self, lease: &NetavarkLease) -> Result<(), ProxyError> {
+        ip::update_static_routes(
+            &self.network_config.ns_path,
+            &self.static_routes,
+            &lease.static_routes,
+        )?;
+        self.static_routes = lease.static_routes.clone();
+        Ok(())
+    }
+
+    /// Sends a DHCPRELEASE for the current lease so the server can hand out the address
+    /// again. mozim sends it directly to the mac address the server replied from, the
+    /// client cannot be used for renewals afterwards.
+    fn release(&mut self) -> Result<(), DhcpServiceError> {
+        if let Some(lease) = &self.previous_lease {
+            self.client
+                .release(lease)
+                .map_err(|e| DhcpServiceError::new(Bug, e.to_string()))?;
+            debug!(
+                "released lease {} for {}",
+                lease.yiaddr, &self.network_config.container_mac_addr
+            );
+        }
+        Ok(())
+    }
 }
 
+/// Asks the renewal task of a lease to release it, the task sends the result of the
+/// release back on the contained channel and stops.
+pub type ReleaseRequest = oneshot::Sender<Result<(), DhcpServiceError>>;
+
 impl std::fmt::Display for DhcpServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.msg)
@@ -125,8 +209,26 @@ impl From<DhcpServiceError> for Status {
     }
 }
 
-pub async fn process_client_stream(mut client: DhcpV4Service) {
-    while let Some(lease) = client.client.next().await {
+pub async fn process_client_stream<W: Write + Clear>(
+    mut client: DhcpV4Service,
+    cache: Arc<Mutex<LeaseCache<W>>>,
+    metrics: Arc<Metrics>,
+    mut release: oneshot::Receiver<ReleaseRequest>,
+) {
+    loop {
+        let lease = tokio::select! {
+            request = &mut release => {
+                // the sender is dropped without a request when the lease is kept
+                if let Ok(reply) = request {
+                    let _ = reply.send(client.release());
+                }
+                return;
+            }
+            lease = client.client.next() => match lease {
+                Some(lease) => lease,
+                None => return,
+            },
+        };
         match lease {
             Ok(lease) => {
                 log::info!(
@@ -159,16 +261,42 @@ pub async fn process_client_stream(mut client: DhcpV4Service) {
                         }
                     }
                 }
+                let netavark_lease = client.netavark_lease(&lease);
+                if let Err(err) = update_lease_mtu(&client.network_config, netavark_lease.mtu) {
+                    log::error!("{err}");
+                }
+                if let Err(err) = client.update_static_routes(&netavark_lease) {
+                    log::error!("{err}");
+                }
+                // keep the cache up to date so the renewal time and lease are correct
+                if let Err(e) = cache
+                    .lock()
+                    .expect("Could not unlock cache. A thread was poisoned")
+                    .update_lease(&client.network_config.container_mac_addr, netavark_lease)
+                {
+                    log::error!("Error caching the renewed lease: {e}");
+                }
                 client.previous_lease = Some(lease)
             }
-            Err(err) => log::error!(
-                "Failed to renew lease for {}: {err}",
-                &client.network_config.container_mac_addr
-            ),
+            Err(err) => {
+                metrics.inc_renew_failures();
+                log::error!(
+                    "Failed to renew lease for {}: {err}",
+                    &client.network_config.container_mac_addr
+                )
+            }
         }
     }
 }
 
+// The mtu of the lease can change on renewals, set it again.
+fn update_lease_mtu(nc: &NetworkConfig, mtu: u32) -> NetavarkResult<()> {
+    let (_, mut netns) = core_utils::open_netlink_sockets(&nc.ns_path)
+        .wrap("failed to open netlink socket in netns")?;
+    ip::set_lease_mtu(&mut netns.netlink, &nc.container_iface, mtu, nc.mtu);
+    Ok(())
+}
+
 fn update_lease_ip(
     netns: &str,
     interface: &str,
@@ -230,3 +358,46 @@ fn update_lease_ip(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp_proxy::lib::g_rpc::NetworkConfig;
+    use std::str::FromStr;
+
+    fn network_config(host_name: &str, client_id: &str) -> NetworkConfig {
+        let mut nc = NetworkConfig::from_str("").unwrap();
+        nc.host_iface = "eth0".to_string();
+        nc.container_mac_addr = "aa:bb:cc:dd:ee:ff".to_string();
+        nc.host_name = host_name.to_string();
+        nc.client_id = client_id.to_string();
+        nc
+    }
+
+    #[test]
+    fn test_dhcp_config_default() {
+        let mut expected = DhcpV4Config::new_proxy("eth0", "aa:bb:cc:dd:ee:ff");
+        expected.set_timeout(5);
+        assert_eq!(new_dhcp_config(&network_config("", ""), 5), expected);
+    }
+
+    #[test]
+    fn test_dhcp_config_host_name() {
+        let mut expected = DhcpV4Config::new_proxy("eth0", "aa:bb:cc:dd:ee:ff");
+        expected.set_timeout(5);
+        expected.set_host_name("foobar");
+        assert_eq!(new_dhcp_config(&network_config("foobar", ""), 5), expected);
+    }
+
+    #[test]
+    fn test_dhcp_config_client_id() {
+        let mut expected = DhcpV4Config::new_proxy("eth0", "aa:bb:cc:dd:ee:ff");
+        expected.set_timeout(5);
+        expected.set_host_name("foobar");
+        expected.set_client_id(0, b"my-id");
+        assert_eq!(
+            new_dhcp_config(&network_config("foobar", "my-id"), 5),
+            expected
+        );
+    }
+}
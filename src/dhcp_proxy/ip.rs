@@ -5,13 +5,14 @@
    Long term this file/function should move into netavark
 */
 
-pub use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, Lease};
+pub use crate::dhcp_proxy::lib::g_rpc::{Lease as NetavarkLease, Lease, StaticRoute};
 pub use crate::dhcp_proxy::types::{CustomErr, ProxyError};
+use crate::error::NetavarkError;
 use crate::network::core_utils;
 use crate::network::netlink;
-use crate::network::netlink::Socket;
-use ipnet::IpNet;
-use log::debug;
+use crate::network::netlink::{Route, Socket};
+use ipnet::{IpNet, Ipv4Net};
+use log::{debug, warn};
 use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
 
@@ -25,8 +26,6 @@ struct MacVLAN {
     address: IpAddr,
     gateways: Vec<IpNet>,
     interface: String,
-    // Unset right now
-    // mtu: u32,
     prefix_length: u8,
 }
 
@@ -38,6 +37,9 @@ trait Address<T> {
     fn add_gws(&self, nls: &mut Socket) -> Result<(), ProxyError>;
 }
 
+// The minimum MTU a DHCP server is allowed to send (RFC 2132)
+const MIN_DHCP_MTU: u32 = 68;
+
 fn handle_gws(g: Vec<String>, netmask: &str) -> Result<Vec<IpNet>, ProxyError> {
     // TODO Need unit test
     let mut gws = Vec::new();
@@ -105,8 +107,6 @@ impl Address<Ipv4Addr> for MacVLAN {
             address,
             gateways,
             interface: interface.to_string(),
-            // Disabled for now
-            // mtu: l.mtu,
             prefix_length,
         })
     }
@@ -132,19 +132,98 @@ impl Address<Ipv4Addr> for MacVLAN {
     }
 }
 
+/// Set the mtu of the lease on the interface. The mtu option of the network takes
+/// precedence, an unset or invalid lease mtu is ignored. Errors are only logged since
+/// the container works with the default mtu as well.
+pub fn set_lease_mtu(nls: &mut Socket, interface: &str, lease_mtu: u32, network_mtu: u32) {
+    if network_mtu != 0 || lease_mtu < MIN_DHCP_MTU {
+        return;
+    }
+    debug!("setting mtu {} on {}", lease_mtu, interface);
+    if let Err(e) = nls.set_mtu(netlink::LinkID::Name(interface.to_string()), lease_mtu) {
+        warn!("failed to set mtu {lease_mtu} from the dhcp lease on {interface}: {e}");
+    }
+}
+
 // setup takes the DHCP lease and some additional information and
 // applies the TCP/IP information to the namespace.
-pub fn setup(lease: &NetavarkLease, interface: &str, ns_path: &str) -> Result<(), ProxyError> {
+pub fn setup(
+    lease: &NetavarkLease,
+    interface: &str,
+    ns_path: &str,
+    network_mtu: u32,
+) -> Result<(), ProxyError> {
     debug!("setting up {}", interface);
     let vlan = match MacVLAN::new(lease, interface) {
         Ok(f) => f,
         Err(e) => return Err(e),
     };
     let (_, mut netns) = core_utils::open_netlink_sockets(ns_path)?;
+    set_lease_mtu(&mut netns.netlink, interface, lease.mtu, network_mtu);
     vlan.add_ip(&mut netns.netlink)?;
     vlan.add_gws(&mut netns.netlink)
 }
 
+// Convert classless static routes to netlink routes. Routes without a gateway
+// (0.0.0.0) point to a directly connected destination which the address of the
+// lease already covers in the common case, they are skipped.
+fn netlink_routes(routes: &[StaticRoute]) -> Result<Vec<Route>, ProxyError> {
+    let mut result = Vec::new();
+    for route in routes {
+        let dest = Ipv4Net::from_str(&route.destination)
+            .map_err(|e| ProxyError::new(format!("bad route {}: {e}", route.destination)))?;
+        let gw = Ipv4Addr::from_str(&route.gateway)?;
+        if gw.is_unspecified() {
+            debug!("skipping route {dest} without gateway");
+            continue;
+        }
+        result.push(Route::Ipv4 {
+            dest,
+            gw,
+            metric: None,
+        });
+    }
+    Ok(result)
+}
+
+/// Replace the classless static routes (option 121) of the old lease with the ones
+/// of the new lease in the namespace.
+pub fn update_static_routes(
+    ns_path: &str,
+    old: &[StaticRoute],
+    new: &[StaticRoute],
+) -> Result<(), ProxyError> {
+    let removed: Vec<StaticRoute> = old.iter().filter(|r| !new.contains(r)).cloned().collect();
+    let added: Vec<StaticRoute> = new.iter().filter(|r| !old.contains(r)).cloned().collect();
+    if removed.is_empty() && added.is_empty() {
+        return Ok(());
+    }
+    let removed = netlink_routes(&removed)?;
+    let added = netlink_routes(&added)?;
+    let (_, mut netns) = core_utils::open_netlink_sockets(ns_path)?;
+    for route in removed.iter() {
+        match netns.netlink.del_route(route) {
+            Ok(_) => {}
+            Err(err) => match err.unwrap() {
+                // the route is already gone
+                NetavarkError::Netlink(e) if -e.raw_code() == libc::ESRCH => {}
+                _ => return Err(ProxyError::new(format!("delete route {route}: {err}"))),
+            },
+        }
+    }
+    for route in added.iter() {
+        match netns.netlink.add_route(route) {
+            Ok(_) => {}
+            Err(err) => match err.unwrap() {
+                // the default route can also be set from the router option
+                NetavarkError::Netlink(e) if -e.raw_code() == libc::EEXIST => {}
+                _ => return Err(ProxyError::new(format!("add route {route}: {err}"))),
+            },
+        }
+    }
+    Ok(())
+}
+
 // teardown is likely unnecessary but holding place here
 pub fn teardown() -> Result<(), ProxyError> {
     todo!()
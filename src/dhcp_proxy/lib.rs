@@ -1,6 +1,8 @@
 extern crate core;
 
-use crate::dhcp_proxy::lib::g_rpc::{Lease, NetworkConfig};
+use crate::dhcp_proxy::lib::g_rpc::{
+    Empty, Lease, LeaseInfo, LeaseList, MacAddress, NetworkConfig,
+};
 use crate::error::NetavarkError;
 use std::convert::TryFrom;
 use std::error::Error;
@@ -32,9 +34,12 @@ pub mod g_rpc {
         pub fn add_mac_address(&mut self, mac_addr: &String) {
             self.mac_address = mac_addr.to_string()
         }
-        /// Update the domain name of the lease
+        /// Update the domain name of the lease, an empty domain name keeps
+        /// the one sent by the dhcp server
         pub fn add_domain_name(&mut self, domain_name: &String) {
-            self.domain_name = domain_name.to_string();
+            if !domain_name.is_empty() {
+                self.domain_name = domain_name.to_string();
+            }
         }
     }
 
@@ -61,6 +66,15 @@ pub mod g_rpc {
                 gateways: handle_ip_vectors(l.gateways),
                 ntp_servers: handle_ip_vectors(l.ntp_srvs),
                 host_name: l.host_name.unwrap_or_else(|| String::from("")),
+                static_routes: l
+                    .classless_routes
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|r| StaticRoute {
+                        destination: format!("{}/{}", r.destination, r.prefix_length),
+                        gateway: r.router.to_string(),
+                    })
+                    .collect(),
                 is_v6: false,
             }
         }
@@ -156,6 +170,25 @@ pub mod g_rpc {
         assert_eq!(response.len(), 5);
         assert_eq!(response[0].to_string(), "10.1.0.1");
     }
+
+    #[test]
+    fn test_lease_static_routes() {
+        let mut mozim_lease = DhcpV4Lease::default();
+        mozim_lease.classless_routes = Some(vec![mozim::DhcpV4ClasslessRoute {
+            destination: Ipv4Addr::new(10, 20, 0, 0),
+            prefix_length: 16,
+            router: Ipv4Addr::new(192, 168, 1, 254),
+        }]);
+        let lease = Lease::from(mozim_lease);
+        assert_eq!(
+            lease.static_routes,
+            vec![StaticRoute {
+                destination: "10.20.0.0/16".to_string(),
+                gateway: "192.168.1.254".to_string(),
+            }]
+        );
+        assert!(Lease::from(DhcpV4Lease::default()).static_routes.is_empty());
+    }
 }
 
 // A collection of functions for client side connections to the proxy server
@@ -267,6 +300,40 @@ impl NetworkConfig {
     }
 }
 
+/// list_leases returns all leases the nvproxy-server currently manages
+///
+/// # Arguments
+///
+/// * `p`: path to uds
+///
+/// returns: Result<LeaseList, NetavarkError>
+pub async fn list_leases(p: &str) -> Result<LeaseList, NetavarkError> {
+    let mut client = NetworkConfig::get_client(p.to_string()).await?;
+    match client.list_leases(Request::new(Empty {})).await {
+        Ok(l) => Ok(l.into_inner()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// show_lease returns the lease for a single container mac address
+///
+/// # Arguments
+///
+/// * `p`: path to uds
+/// * `mac`: mac address of the container
+///
+/// returns: Result<LeaseInfo, NetavarkError>
+pub async fn show_lease(p: &str, mac: &str) -> Result<LeaseInfo, NetavarkError> {
+    let mut client = NetworkConfig::get_client(p.to_string()).await?;
+    let request = Request::new(MacAddress {
+        mac: mac.to_string(),
+    });
+    match client.get_lease(request).await {
+        Ok(l) => Ok(l.into_inner()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 trait VectorConv {
     fn to_v4_addrs(&self) -> Result<Option<Vec<Ipv4Addr>>, AddrParseError>;
 }
@@ -0,0 +1,418 @@
+//! Minimal OpenMetrics support for the DHCP proxy.
+//!
+//! The proxy is a long running daemon, the metrics collected here allow monitoring of the
+//! leases it manages. The listener is optional and only accepts connections on a unix socket
+//! or on a loopback TCP address. It serves the metrics in the OpenMetrics text format which
+//! can be scraped by Prometheus.
+
+use crate::dhcp_proxy::dhcp_service::DhcpServiceErrorKind;
+use crate::error::{NetavarkError, NetavarkResult};
+use log::{debug, error, warn};
+use std::fmt::{self, Write as _};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+
+const METRICS_PREFIX: &str = "netavark_dhcp_proxy";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+// Upper bounds in seconds of the DORA latency histogram buckets
+const DORA_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+// The maximum size of a request we are willing to read
+const MAX_REQUEST_SIZE: usize = 8192;
+// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// All DhcpServiceErrorKind variants in the order they are stored and rendered.
+const ERROR_KINDS: [DhcpServiceErrorKind; 7] = [
+    DhcpServiceErrorKind::Timeout,
+    DhcpServiceErrorKind::InvalidArgument,
+    DhcpServiceErrorKind::InvalidDhcpServerReply,
+    DhcpServiceErrorKind::NoLease,
+    DhcpServiceErrorKind::Bug,
+    DhcpServiceErrorKind::LeaseExpired,
+    DhcpServiceErrorKind::Unimplemented,
+];
+
+/// Metrics of the proxy server. All values are atomics so they can be shared
+/// between the gRPC request tasks and the dhcp client streams without locking.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // number of leases currently in the cache
+    active_leases: AtomicU64,
+    // DORA latency histogram, one counter per bucket (not cumulative)
+    dora_buckets: [AtomicU64; DORA_BUCKETS.len()],
+    // number of DORA observations
+    dora_count: AtomicU64,
+    // sum of all DORA observations in microseconds
+    dora_sum_us: AtomicU64,
+    // failed lease acquisitions, indexed like ERROR_KINDS
+    dora_errors: [AtomicU64; ERROR_KINDS.len()],
+    // failed lease renewals in the client stream
+    renew_failures: AtomicU64,
+    // shutdowns caused by the inactivity timeout, including previous runs
+    inactivity_shutdowns: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of leases currently managed by the proxy.
+    pub fn set_active_leases(&self, count: usize) {
+        self.active_leases.store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Record the duration of a successful DORA exchange.
+    pub fn observe_dora(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = DORA_BUCKETS.iter().position(|b| secs <= *b) {
+            self.dora_buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.dora_count.fetch_add(1, Ordering::Relaxed);
+        self.dora_sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a failed lease acquisition.
+    pub fn inc_dora_error(&self, kind: &DhcpServiceErrorKind) {
+        let i = ERROR_KINDS
+            .iter()
+            .position(|k| k == kind)
+            .unwrap_or(ERROR_KINDS.len() - 1);
+        self.dora_errors[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a failed lease renewal.
+    pub fn inc_renew_failures(&self) {
+        self.renew_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Load the number of inactivity timeout shutdowns of previous runs from the
+    /// counter file. A missing file means there was none.
+    pub fn load_inactivity_shutdowns(&self, path: &Path) {
+        let count = match std::fs::read_to_string(path) {
+            Ok(content) => match content.trim().parse::<u64>() {
+                Ok(count) => count,
+                Err(e) => {
+                    warn!("invalid shutdown counter in {}: {e}", path.display());
+                    0
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                warn!("failed to read shutdown counter {}: {e}", path.display());
+                0
+            }
+        };
+        self.inactivity_shutdowns.store(count, Ordering::Relaxed);
+    }
+
+    /// Record a shutdown caused by the inactivity timeout. The counter file is
+    /// updated so the next run of the proxy continues the count.
+    pub fn inc_inactivity_shutdowns(&self, path: &Path) {
+        let count = self.inactivity_shutdowns.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Err(e) = std::fs::write(path, format!("{count}\n")) {
+            error!("failed to write shutdown counter {}: {e}", path.display());
+        }
+    }
+
+    /// Render all metrics in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // writing into a String cannot fail so it is fine to ignore the results
+        let _ = writeln!(
+            out,
+            "# TYPE {METRICS_PREFIX}_active_leases gauge\n\
+             # HELP {METRICS_PREFIX}_active_leases Number of leases managed by the proxy.\n\
+             {METRICS_PREFIX}_active_leases {}",
+            self.active_leases.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# TYPE {METRICS_PREFIX}_dora_duration_seconds histogram\n\
+             # UNIT {METRICS_PREFIX}_dora_duration_seconds seconds\n\
+             # HELP {METRICS_PREFIX}_dora_duration_seconds Duration of successful DHCP DORA exchanges."
+        );
+        let mut cumulative = 0;
+        for (bucket, count) in DORA_BUCKETS.iter().zip(self.dora_buckets.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{METRICS_PREFIX}_dora_duration_seconds_bucket{{le=\"{bucket:?}\"}} {cumulative}"
+            );
+        }
+        let count = self.dora_count.load(Ordering::Relaxed);
+        let sum = self.dora_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(
+            out,
+            "{METRICS_PREFIX}_dora_duration_seconds_bucket{{le=\"+Inf\"}} {count}\n\
+             {METRICS_PREFIX}_dora_duration_seconds_sum {sum:?}\n\
+             {METRICS_PREFIX}_dora_duration_seconds_count {count}"
+        );
+
+        let _ = writeln!(
+            out,
+            "# TYPE {METRICS_PREFIX}_dora_errors counter\n\
+             # HELP {METRICS_PREFIX}_dora_errors Failed lease acquisitions by error kind."
+        );
+        for (kind, count) in ERROR_KINDS.iter().zip(self.dora_errors.iter()) {
+            let _ = writeln!(
+                out,
+                "{METRICS_PREFIX}_dora_errors_total{{kind=\"{}\"}} {}",
+                kind.as_str(),
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# TYPE {METRICS_PREFIX}_renew_failures counter\n\
+             # HELP {METRICS_PREFIX}_renew_failures Failed lease renewals.\n\
+             {METRICS_PREFIX}_renew_failures_total {}",
+            self.renew_failures.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# TYPE {METRICS_PREFIX}_inactivity_shutdowns counter\n\
+             # HELP {METRICS_PREFIX}_inactivity_shutdowns Shutdowns caused by the activity timeout.\n\
+             {METRICS_PREFIX}_inactivity_shutdowns_total {}",
+            self.inactivity_shutdowns.load(Ordering::Relaxed)
+        );
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Address the metrics listener binds to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricsAddress {
+    /// unix socket path, given as `unix:/path` or an absolute path
+    Unix(PathBuf),
+    /// TCP socket address, must be a loopback address
+    Tcp(SocketAddr),
+}
+
+impl fmt::Display for MetricsAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            MetricsAddress::Tcp(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+impl FromStr for MetricsAddress {
+    type Err = NetavarkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(MetricsAddress::Unix(PathBuf::from(path)));
+        }
+        if s.starts_with('/') {
+            return Ok(MetricsAddress::Unix(PathBuf::from(s)));
+        }
+        let addr = SocketAddr::from_str(s)
+            .map_err(|e| NetavarkError::msg(format!("invalid metrics address \"{s}\": {e}")))?;
+        if !addr.ip().is_loopback() {
+            return Err(NetavarkError::msg(format!(
+                "metrics address \"{s}\" must be a loopback address"
+            )));
+        }
+        Ok(MetricsAddress::Tcp(addr))
+    }
+}
+
+/// Bound metrics listener, created before the proxy starts so an unusable
+/// address fails the startup.
+pub enum MetricsListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// Bind the metrics listener on the given address.
+pub async fn bind_metrics(address: &MetricsAddress) -> NetavarkResult<MetricsListener> {
+    let listener = match address {
+        MetricsAddress::Unix(path) => {
+            // remove a stale socket from a previous run
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            MetricsListener::Unix(UnixListener::bind(path)?)
+        }
+        MetricsAddress::Tcp(addr) => MetricsListener::Tcp(TcpListener::bind(addr).await?),
+    };
+    debug!("metrics listening on {address}");
+    Ok(listener)
+}
+
+/// Serve the metrics on the listener until the task is dropped.
+pub async fn serve_metrics(listener: MetricsListener, metrics: Arc<Metrics>) -> NetavarkResult<()> {
+    match listener {
+        MetricsListener::Unix(listener) => loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(handle_connection(stream, metrics.clone()));
+        },
+        MetricsListener::Tcp(listener) => loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(handle_connection(stream, metrics.clone()));
+        },
+    }
+}
+
+/// Answer a single HTTP request. Only `GET /metrics` is supported, the connection
+/// is always closed after the response.
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    metrics: Arc<Metrics>,
+) {
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream, &mut buf)).await;
+    match read {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            error!("failed to read metrics request: {e}");
+            return;
+        }
+        Err(_) => {
+            debug!("metrics request not received within {REQUEST_TIMEOUT:?}");
+            return;
+        }
+    }
+
+    let response = match parse_request_line(&buf) {
+        Some(("GET", "/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {OPENMETRICS_CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        Some(("GET", _)) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+        Some(_) => "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string(),
+        None => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string(),
+    };
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        error!("failed to write metrics response: {e}");
+        return;
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Read until the end of the request headers, the connection is closed or the
+/// request gets too large.
+async fn read_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_SIZE {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the method and path of a HTTP request.
+fn parse_request_line(buf: &[u8]) -> Option<(&str, &str)> {
+    let line = buf.split(|b| *b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?.trim_end();
+    let mut parts = line.split(' ');
+    let method = parts.next()?;
+    let path = parts.next()?;
+    parts.next()?.strip_prefix("HTTP/")?;
+    Some((method, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.set_active_leases(2);
+        metrics.observe_dora(Duration::from_millis(200));
+        metrics.observe_dora(Duration::from_secs(3));
+        metrics.inc_dora_error(&DhcpServiceErrorKind::Timeout);
+        metrics.inc_renew_failures();
+
+        let out = metrics.render();
+        assert!(out.contains("netavark_dhcp_proxy_active_leases 2\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_bucket{le=\"0.1\"} 0\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_bucket{le=\"0.25\"} 1\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_bucket{le=\"5.0\"} 2\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_sum 3.2\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_duration_seconds_count 2\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_errors_total{kind=\"timeout\"} 1\n"));
+        assert!(out.contains("netavark_dhcp_proxy_dora_errors_total{kind=\"no_lease\"} 0\n"));
+        assert!(out.contains("netavark_dhcp_proxy_renew_failures_total 1\n"));
+        assert!(out.contains("netavark_dhcp_proxy_inactivity_shutdowns_total 0\n"));
+        assert!(out.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_inactivity_shutdowns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nv-proxy.shutdowns");
+
+        let metrics = Metrics::new();
+        metrics.load_inactivity_shutdowns(&path);
+        metrics.inc_inactivity_shutdowns(&path);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n");
+
+        // the count continues in the next run
+        let metrics = Metrics::new();
+        metrics.load_inactivity_shutdowns(&path);
+        metrics.inc_inactivity_shutdowns(&path);
+        assert!(metrics
+            .render()
+            .contains("netavark_dhcp_proxy_inactivity_shutdowns_total 2\n"));
+    }
+
+    #[test]
+    fn test_metrics_address() {
+        assert_eq!(
+            MetricsAddress::from_str("unix:/run/metrics.sock").unwrap(),
+            MetricsAddress::Unix(PathBuf::from("/run/metrics.sock"))
+        );
+        assert_eq!(
+            MetricsAddress::from_str("/run/metrics.sock").unwrap(),
+            MetricsAddress::Unix(PathBuf::from("/run/metrics.sock"))
+        );
+        assert_eq!(
+            MetricsAddress::from_str("127.0.0.1:9100").unwrap(),
+            MetricsAddress::Tcp(SocketAddr::from_str("127.0.0.1:9100").unwrap())
+        );
+        assert!(MetricsAddress::from_str("[::1]:9100").is_ok());
+        assert!(MetricsAddress::from_str("0.0.0.0:9100").is_err());
+        assert!(MetricsAddress::from_str("localhost").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(
+            parse_request_line(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Some(("GET", "/metrics"))
+        );
+        assert_eq!(parse_request_line(b"garbage\r\n\r\n"), None);
+    }
+}
@@ -2,5 +2,6 @@ pub mod cache;
 pub mod dhcp_service;
 pub mod ip;
 pub mod lib;
+pub mod metrics;
 pub mod proxy_conf;
 pub mod types;
@@ -22,6 +22,8 @@ pub const PROXY_SOCK_NAME: &str = "nv-proxy.sock";
 pub const CACHE_FILE_NAME: &str = "nv-proxy.lease";
 // Seconds until the service should exit
 pub const DEFAULT_INACTIVITY_TIMEOUT: u64 = 300;
+// Number of shutdowns caused by the inactivity timeout, kept across restarts
+pub const SHUTDOWNS_FILE_NAME: &str = "nv-proxy.shutdowns";
 
 /// Get the RUN_DIR where the proxy cache and socket
 /// are stored
@@ -94,6 +96,19 @@ pub fn get_cache_fqname(run_dir: Option<&str>) -> PathBuf {
     Path::new(&run_dir).join(CACHE_FILE_NAME)
 }
 
+/// Returns the fully qualified path of the file counting the shutdowns caused by
+/// the inactivity timeout
+///
+/// # Arguments
+///
+/// * `run_dir`:
+///
+/// returns: PathBuf
+pub fn get_shutdowns_fqname(run_dir: Option<&str>) -> PathBuf {
+    let run_dir = get_run_dir(run_dir);
+    Path::new(&run_dir).join(SHUTDOWNS_FILE_NAME)
+}
+
 #[cfg(test)]
 mod conf_tests {
     use crate::dhcp_proxy::proxy_conf::{
@@ -23,6 +23,8 @@ impl FromStr for NetworkConfig {
             version: 0,
             ns_path: "".to_string(),
             container_iface: "".to_string(),
+            client_id: "".to_string(),
+            mtu: 0,
         })
     }
 }
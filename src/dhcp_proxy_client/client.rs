@@ -1,9 +1,9 @@
 use clap::{Parser, Subcommand};
-use commands::{setup, teardown};
+use commands::{list, setup, show, teardown};
 use std::process;
 use tonic::{Code, Status};
 
-use netavark::dhcp_proxy::lib::g_rpc::{Lease, NetworkConfig};
+use netavark::dhcp_proxy::lib::g_rpc::NetworkConfig;
 use netavark::dhcp_proxy::proxy_conf::{DEFAULT_NETWORK_CONFIG, DEFAULT_UDS_PATH};
 use netavark::error::NetavarkError;
 
@@ -29,6 +29,10 @@ enum SubCommand {
     Setup(setup::Setup),
     /// Undo any configuration applied via setup command.
     Teardown(teardown::Teardown),
+    /// List all leases managed by the proxy.
+    List(list::List),
+    /// Show the lease for the given container mac address.
+    Show(show::Show),
     // Display info about netavark.
     // Version(version::Version),
 }
@@ -44,12 +48,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file
         .unwrap_or_else(|| DEFAULT_NETWORK_CONFIG.to_string());
     let uds_path = opts.uds.unwrap_or_else(|| DEFAULT_UDS_PATH.to_string());
-    let input_config = NetworkConfig::load(&file)?;
     let result = match opts.subcmd {
-        SubCommand::Setup(s) => s.exec(&uds_path, input_config).await,
-        SubCommand::Teardown(t) => t.exec(&uds_path, input_config).await,
+        SubCommand::Setup(s) => {
+            let input_config = NetworkConfig::load(&file)?;
+            s.exec(&uds_path, input_config)
+                .await
+                .map(|r| serde_json::to_string_pretty(&r))
+        }
+        SubCommand::Teardown(t) => {
+            let input_config = NetworkConfig::load(&file)?;
+            t.exec(&uds_path, input_config)
+                .await
+                .map(|r| serde_json::to_string_pretty(&r))
+        }
+        SubCommand::List(l) => l
+            .exec(&uds_path)
+            .await
+            .map(|r| serde_json::to_string_pretty(&r)),
+        SubCommand::Show(s) => s
+            .exec(&uds_path)
+            .await
+            .map(|r| serde_json::to_string_pretty(&r)),
     };
-    let r = match result {
+    let pp = match result {
         Ok(r) => r,
         Err(e) => {
             eprintln!("Error: {e}");
@@ -60,7 +81,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let pp = ::serde_json::to_string_pretty(&r);
     // TODO this should probably return an empty lease so consumers
     // don't soil themselves
     println!("{}", pp.unwrap_or_else(|_| "".to_string()));
@@ -71,7 +91,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 // process_failure makes the client exit with a specific
 // error code
 //
-fn process_failure(status: Status) -> Lease {
+fn process_failure(status: Status) -> ! {
     let mut rc: i32 = 1;
 
     match status.code() {
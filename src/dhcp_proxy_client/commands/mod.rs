@@ -1,3 +1,5 @@
+pub mod list;
 pub mod setup;
+pub mod show;
 pub mod teardown;
 // pub mod version;
@@ -0,0 +1,19 @@
+use clap::Parser;
+use log::debug;
+use netavark::{
+    dhcp_proxy::lib::{g_rpc::LeaseInfo, show_lease},
+    error::NetavarkError,
+};
+
+#[derive(Parser, Debug)]
+pub struct Show {
+    /// Mac address of the container interface.
+    mac: String,
+}
+
+impl Show {
+    pub async fn exec(&self, p: &str) -> Result<LeaseInfo, NetavarkError> {
+        debug!("Showing lease for {}", self.mac);
+        show_lease(p, &self.mac).await
+    }
+}
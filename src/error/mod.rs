@@ -126,8 +126,9 @@ impl NetavarkError {
 
     /// Get the exit code that Netavark should exit with
     pub fn get_exit_code(&self) -> i32 {
-        match *self {
-            NetavarkError::ExitCode(_, i) => i,
+        match self.unwrap() {
+            NetavarkError::ExitCode(_, i) => *i,
+            NetavarkError::List(list) if list.0.len() == 1 => list.0[0].get_exit_code(),
             _ => 1,
         }
     }
@@ -0,0 +1,442 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::network::core_utils::CoreUtils;
+use crate::network::internal_types::{IsolateOption, PortForwardConfigOwned, SetupNetwork};
+
+use super::iptables::MAX_HASH_SIZE;
+
+/// Backend neutral view of the netavark firewall rules.
+///
+/// It is generated either from the live rules of a firewall driver or from the
+/// firewall state on disk so both documents can be compared with each other.
+/// DNS redirection rules for aardvark-dns are not part of the dump.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FirewallDump {
+    /// name of the firewall driver
+    pub driver: String,
+    /// subnets for which forwarding rules exist
+    pub networks: Vec<IpNet>,
+    /// subnets which are masqueraded when leaving the host
+    pub masquerade: Vec<IpNet>,
+    /// isolation mode per bridge
+    pub isolation: Vec<Isolation>,
+    /// isolation groups the bridges are members of
+    pub isolation_groups: Vec<IsolationGroup>,
+    /// traffic dropped between subnets, firewalld isolates subnets instead of bridges
+    pub isolation_drops: Vec<IsolationDrop>,
+    /// port forwarding rules, ranges are expanded to one entry per port
+    pub port_forwards: Vec<PortForward>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Isolation {
+    pub bridge: String,
+    pub isolation: IsolateOption,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct IsolationGroup {
+    pub bridge: String,
+    /// group name as known to the driver, see isolation_group_id()
+    pub group: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct IsolationDrop {
+    pub source: IpNet,
+    pub destination: IpNet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PortForward {
+    pub protocol: String,
+    /// None means the rule matches all host addresses
+    pub host_ip: Option<IpAddr>,
+    pub host_port: u16,
+    /// address the traffic is forwarded to, normally the container ip
+    pub dest_ip: IpAddr,
+    pub dest_port: u16,
+}
+
+/// Packets and bytes dropped by the isolation rules for traffic to a bridge.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DropCounter {
+    pub bridge: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// The name of the isolation group in the rules of the driver,
+/// iptables only has room for a hash of it in the chain name.
+pub(crate) fn isolation_group_id(driver: &str, group: &str) -> String {
+    if driver == super::IPTABLES {
+        CoreUtils::create_network_hash(group, MAX_HASH_SIZE)
+    } else {
+        group.to_string()
+    }
+}
+
+/// Sum up the counters of all rules for the same bridge.
+pub(crate) fn merge_drop_counters(counters: Vec<DropCounter>) -> Vec<DropCounter> {
+    let mut merged: Vec<DropCounter> = Vec::new();
+    for counter in counters {
+        match merged.iter_mut().find(|c| c.bridge == counter.bridge) {
+            Some(c) => {
+                c.packets += counter.packets;
+                c.bytes += counter.bytes;
+            }
+            None => merged.push(counter),
+        }
+    }
+    merged.sort();
+    merged
+}
+
+impl FirewallDump {
+    pub fn new(driver: &str) -> Self {
+        FirewallDump {
+            driver: driver.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Build the dump that is expected for the given firewall state.
+    pub fn from_state(
+        driver: &str,
+        net_confs: &[SetupNetwork],
+        port_confs: &[PortForwardConfigOwned],
+    ) -> Self {
+        let mut dump = FirewallDump::new(driver);
+        if driver == super::NONE {
+            return dump;
+        }
+
+        for net in net_confs {
+            for subnet in net.subnets.iter().flatten() {
+                dump.networks.push(*subnet);
+                dump.masquerade.push(*subnet);
+            }
+            // firewalld does not know the bridges, its rules are added below
+            if driver != super::FIREWALLD {
+                dump.isolation.push(Isolation {
+                    bridge: net.bridge_name.clone(),
+                    isolation: net.isolation,
+                });
+                for group in &net.isolation_groups {
+                    dump.isolation_groups.push(IsolationGroup {
+                        bridge: net.bridge_name.clone(),
+                        group: isolation_group_id(driver, group),
+                    });
+                }
+            }
+        }
+
+        if driver == super::FIREWALLD {
+            dump.isolation_drops = super::firewalld::isolation_drops(net_confs);
+        }
+
+        for conf in port_confs {
+            for port in conf.port_mappings.iter().flatten() {
+                let host_ip: Option<IpAddr> = port.host_ip.parse().ok();
+                let dest_ips: Vec<IpAddr> = if driver == super::FIREWALLD && host_ip.is_some() {
+                    // firewalld forwards to the host ip when one is set,
+                    // see FirewallD::setup_port_forward()
+                    host_ip.into_iter().collect()
+                } else {
+                    [conf.container_ip_v4, conf.container_ip_v6]
+                        .into_iter()
+                        .flatten()
+                        .filter(|ip| match host_ip {
+                            Some(h) => h.is_ipv4() == ip.is_ipv4(),
+                            None => true,
+                        })
+                        .collect()
+                };
+                let host_ip = match host_ip {
+                    Some(ip) if !ip.is_unspecified() && driver != super::FIREWALLD => Some(ip),
+                    _ => None,
+                };
+
+                for dest_ip in dest_ips {
+                    for protocol in port.protocol.split(',') {
+                        for i in 0..port.range.max(1) {
+                            dump.port_forwards.push(PortForward {
+                                protocol: protocol.to_string(),
+                                host_ip,
+                                host_port: port.host_port + i,
+                                dest_ip,
+                                dest_port: port.container_port_for(i),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        dump.normalize();
+        dump
+    }
+
+    /// Returns true if all entries of other are also part of this dump.
+    pub fn contains(&self, other: &FirewallDump) -> bool {
+        other.networks.iter().all(|n| self.networks.contains(n))
+            && other.masquerade.iter().all(|n| self.masquerade.contains(n))
+            && other.isolation.iter().all(|i| self.isolation.contains(i))
+            && other
+                .isolation_groups
+                .iter()
+                .all(|g| self.isolation_groups.contains(g))
+            && other
+                .isolation_drops
+                .iter()
+                .all(|d| self.isolation_drops.contains(d))
+            && other
+                .port_forwards
+                .iter()
+                .all(|p| self.port_forwards.contains(p))
+    }
+
+    /// Sort all entries and remove duplicates so that dumps can be compared.
+    pub fn normalize(&mut self) {
+        self.networks.sort();
+        self.networks.dedup();
+        self.masquerade.sort();
+        self.masquerade.dedup();
+        self.isolation.sort_by(|a, b| a.bridge.cmp(&b.bridge));
+        self.isolation.dedup();
+        self.isolation_groups.sort();
+        self.isolation_groups.dedup();
+        self.isolation_drops.sort();
+        self.isolation_drops.dedup();
+        self.port_forwards.sort();
+        self.port_forwards.dedup();
+    }
+}
+
+/// Parse a port or port range in the form "8080", "8080-8081" or "8080:8081".
+pub(crate) fn parse_port_range(s: &str) -> Option<(u16, u16)> {
+    match s.split_once(['-', ':']) {
+        Some((start, end)) => Some((start.parse().ok()?, end.parse().ok()?)),
+        None => {
+            let port = s.parse().ok()?;
+            Some((port, port))
+        }
+    }
+}
+
+/// Destination port of the host port at the given offset, a single
+/// destination port means the whole host port range is forwarded to it.
+pub(crate) fn dest_port_for(dest_start: u16, dest_end: u16, offset: u16) -> u16 {
+    if dest_start == dest_end {
+        dest_start
+    } else {
+        dest_start + offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::network::types::PortMapping;
+
+    use super::*;
+
+    fn net_conf(bridge: &str, subnet: &str, isolation: IsolateOption) -> SetupNetwork {
+        SetupNetwork {
+            subnets: Some(vec![subnet.parse().unwrap()]),
+            bridge_name: bridge.to_string(),
+            network_id: "id".to_string(),
+            network_hash_name: "hash".to_string(),
+            isolation,
+            isolation_groups: vec![],
+            dns_port: 53,
+            log_dropped: None,
+            snat_ipv4: None,
+            snat_ipv6: None,
+            snat_exclude: vec![],
+            hairpin_masquerade: true,
+        }
+    }
+
+    fn port_conf(host_ip: &str) -> PortForwardConfigOwned {
+        PortForwardConfigOwned {
+            container_id: "123".to_string(),
+            network_id: "id".to_string(),
+            port_mappings: Some(vec![PortMapping {
+                container_port: 80,
+                host_ip: host_ip.to_string(),
+                host_port: 8080,
+                protocol: "tcp,udp".to_string(),
+                range: 2,
+                container_range: None,
+            }]),
+            network_name: "name".to_string(),
+            network_hash_name: "hash".to_string(),
+            container_ip_v4: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+            subnet_v4: Some("10.0.0.0/24".parse().unwrap()),
+            container_ip_v6: Some("fd00::2".parse().unwrap()),
+            subnet_v6: Some("fd00::/64".parse().unwrap()),
+            dns_port: 53,
+            dns_server_ips: vec![],
+            localhost_forwarding: true,
+            hairpin_masquerade: true,
+        }
+    }
+
+    #[test]
+    fn test_from_state() {
+        let nets = vec![
+            net_conf("podman1", "10.0.1.0/24", IsolateOption::Strict),
+            net_conf("podman0", "10.0.0.0/24", IsolateOption::Never),
+        ];
+        let dump = FirewallDump::from_state("nftables", &nets, &[port_conf("")]);
+        assert_eq!(dump.driver, "nftables");
+        assert_eq!(
+            dump.networks,
+            vec![
+                "10.0.0.0/24".parse::<IpNet>().unwrap(),
+                "10.0.1.0/24".parse().unwrap()
+            ]
+        );
+        assert_eq!(dump.masquerade, dump.networks);
+        assert_eq!(
+            dump.isolation,
+            vec![
+                Isolation {
+                    bridge: "podman0".to_string(),
+                    isolation: IsolateOption::Never
+                },
+                Isolation {
+                    bridge: "podman1".to_string(),
+                    isolation: IsolateOption::Strict
+                }
+            ]
+        );
+        // 2 families * 2 protocols * 2 ports
+        assert_eq!(dump.port_forwards.len(), 8);
+        assert_eq!(
+            dump.port_forwards[0],
+            PortForward {
+                protocol: "tcp".to_string(),
+                host_ip: None,
+                host_port: 8080,
+                dest_ip: "10.0.0.2".parse().unwrap(),
+                dest_port: 80,
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_state_host_ip() {
+        let dump = FirewallDump::from_state("iptables", &[], &[port_conf("127.0.0.1")]);
+        assert_eq!(dump.port_forwards.len(), 4);
+        for pf in &dump.port_forwards {
+            assert_eq!(pf.host_ip, Some("127.0.0.1".parse().unwrap()));
+            assert_eq!(pf.dest_ip, "10.0.0.2".parse::<IpAddr>().unwrap());
+        }
+
+        let dump = FirewallDump::from_state("iptables", &[], &[port_conf("::")]);
+        assert_eq!(dump.port_forwards.len(), 4);
+        for pf in &dump.port_forwards {
+            assert_eq!(pf.host_ip, None);
+            assert_eq!(pf.dest_ip, "fd00::2".parse::<IpAddr>().unwrap());
+        }
+
+        let dump = FirewallDump::from_state("firewalld", &[], &[port_conf("127.0.0.1")]);
+        assert_eq!(dump.port_forwards.len(), 4);
+        for pf in &dump.port_forwards {
+            assert_eq!(pf.host_ip, None);
+            assert_eq!(pf.dest_ip, "127.0.0.1".parse::<IpAddr>().unwrap());
+        }
+
+        let dump = FirewallDump::from_state("none", &[], &[port_conf("")]);
+        assert!(dump.port_forwards.is_empty());
+    }
+
+    #[test]
+    fn test_from_state_isolation_groups() {
+        let mut net = net_conf("podman1", "10.0.1.0/24", IsolateOption::Normal);
+        net.isolation_groups = vec!["shared".to_string()];
+        let nets = [net];
+
+        let dump = FirewallDump::from_state("nftables", &nets, &[]);
+        assert_eq!(
+            dump.isolation_groups,
+            vec![IsolationGroup {
+                bridge: "podman1".to_string(),
+                group: "shared".to_string(),
+            }]
+        );
+        let dump = FirewallDump::from_state("iptables", &nets, &[]);
+        assert_eq!(
+            dump.isolation_groups[0].group,
+            CoreUtils::create_network_hash("shared", MAX_HASH_SIZE)
+        );
+        let dump = FirewallDump::from_state("firewalld", &nets, &[]);
+        assert!(dump.isolation_groups.is_empty());
+        assert!(dump.isolation_drops.is_empty());
+
+        let mut live = FirewallDump::from_state("nftables", &nets, &[]);
+        live.isolation_groups.clear();
+        assert!(
+            !live.contains(&FirewallDump::from_state("nftables", &nets, &[])),
+            "missing group membership"
+        );
+    }
+
+    #[test]
+    fn test_from_state_firewalld_isolation() {
+        let nets = vec![
+            net_conf("podman0", "10.0.0.0/24", IsolateOption::Never),
+            net_conf("podman1", "10.0.1.0/24", IsolateOption::Strict),
+        ];
+        let dump = FirewallDump::from_state("firewalld", &nets, &[]);
+        assert!(dump.isolation.is_empty());
+        assert_eq!(
+            dump.isolation_drops,
+            vec![IsolationDrop {
+                source: "10.0.1.0/24".parse().unwrap(),
+                destination: "10.0.0.0/24".parse().unwrap(),
+            }]
+        );
+        // the rules between networks are missing from the dump of each network
+        assert!(FirewallDump::from_state("firewalld", &nets[1..], &[])
+            .isolation_drops
+            .is_empty());
+        assert!(FirewallDump::from_state("nftables", &nets, &[])
+            .isolation_drops
+            .is_empty());
+    }
+
+    #[test]
+    fn test_contains() {
+        let nets = vec![
+            net_conf("podman0", "10.0.0.0/24", IsolateOption::Never),
+            net_conf("podman1", "10.0.1.0/24", IsolateOption::Normal),
+        ];
+        let ports = vec![port_conf("")];
+        let full = FirewallDump::from_state("iptables", &nets, &ports);
+        let net = FirewallDump::from_state("iptables", &nets[1..], &[]);
+        let port = FirewallDump::from_state("iptables", &[], &ports);
+        assert!(
+            full.contains(&net),
+            "network rules are part of the full dump"
+        );
+        assert!(full.contains(&port), "port rules are part of the full dump");
+        assert!(!net.contains(&full), "network dump misses rules");
+        assert!(!port.contains(&net), "port dump has no network rules");
+        assert!(net.contains(&FirewallDump::default()), "empty dump");
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!(parse_port_range("8080"), Some((8080, 8080)));
+        assert_eq!(parse_port_range("8080-8081"), Some((8080, 8081)));
+        assert_eq!(parse_port_range("8080:8081"), Some((8080, 8081)));
+        assert_eq!(parse_port_range("abc"), None);
+    }
+}
@@ -1,11 +1,17 @@
 use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::dump::{
+    dest_port_for, parse_port_range, DropCounter, FirewallDump, IsolationDrop, PortForward,
+};
 use crate::network::internal_types;
-use crate::network::internal_types::{PortForwardConfig, TearDownNetwork, TeardownPortForward};
+use crate::network::internal_types::{
+    IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
+};
 use crate::network::types::PortMapping;
 use crate::{firewall, wrap};
 use core::convert::TryFrom;
 use log::{debug, info, warn};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 use zbus::{
     blocking::Connection,
@@ -16,13 +22,26 @@ const ZONENAME: &str = "netavark_zone";
 const POLICYNAME: &str = "netavark_policy";
 const PORTPOLICYNAME: &str = "netavark_portfwd";
 
+/// Error for networks with snat_exclude, see get_snat_direct_rules(). They are
+/// rejected before anything is set up.
+pub(crate) const SNAT_EXCLUDE_ERROR: &str =
+    "excluding destinations from source nat is not supported by the firewalld driver";
+
 // Firewalld driver - uses a dbus connection to communicate with firewalld.
 pub struct FirewallD {
     conn: Connection,
+    /// used to read the other networks for the isolation rules
+    config_dir: Option<PathBuf>,
 }
 
-pub fn new(conn: Connection) -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
-    Ok(Box::new(FirewallD { conn }))
+pub fn new(
+    conn: Connection,
+    config_dir: Option<&Path>,
+) -> Result<Box<dyn firewall::FirewallDriver>, NetavarkError> {
+    Ok(Box::new(FirewallD {
+        conn,
+        config_dir: config_dir.map(Path::to_path_buf),
+    }))
 }
 
 impl firewall::FirewallDriver for FirewallD {
@@ -31,6 +50,7 @@ impl firewall::FirewallDriver for FirewallD {
     }
 
     fn setup_network(&self, network_setup: internal_types::SetupNetwork) -> NetavarkResult<()> {
+        let snat_rules = get_snat_direct_rules(&network_setup);
         let mut need_reload = false;
 
         need_reload |= match create_zone_if_not_exist(&self.conn, ZONENAME) {
@@ -76,8 +96,17 @@ impl firewall::FirewallDriver for FirewallD {
 
         // MUST come after the reload; otherwise the zone we made might not be
         // in the running config.
-        if let Some(nets) = network_setup.subnets {
-            match add_source_subnets_to_zone(&self.conn, ZONENAME, &nets) {
+        for (ipv, args) in snat_rules {
+            let exists: bool = call_direct(&self.conn, "queryRule", ipv, &args)?
+                .body()
+                .deserialize()?;
+            if !exists {
+                call_direct(&self.conn, "addRule", ipv, &args)?;
+            }
+        }
+
+        if let Some(nets) = &network_setup.subnets {
+            match add_source_subnets_to_zone(&self.conn, ZONENAME, nets) {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(NetavarkError::wrap(
@@ -88,7 +117,7 @@ impl firewall::FirewallDriver for FirewallD {
             };
         }
 
-        Ok(())
+        self.update_policy_rules(Some(&network_setup), None)
     }
 
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
@@ -96,7 +125,19 @@ impl firewall::FirewallDriver for FirewallD {
             return Ok(());
         }
 
-        if let Some(subnets) = tear.config.subnets {
+        // The rule may already be gone, e.g. removed by hand, firewalld fails to
+        // remove it then and we would never remove the subnets from the zone.
+        for (ipv, args) in get_snat_direct_rules(&tear.config) {
+            let exists: bool = call_direct(&self.conn, "queryRule", ipv, &args)?
+                .body()
+                .deserialize()?;
+            if exists {
+                debug!("Removing direct rule {:?}", args);
+                let _ = call_direct(&self.conn, "removeRule", ipv, &args)?;
+            }
+        }
+
+        if let Some(subnets) = &tear.config.subnets {
             for subnet in subnets {
                 debug!("Removing subnet {} from zone {}", subnet, ZONENAME);
                 let _ = self.conn.call_method(
@@ -109,10 +150,14 @@ impl firewall::FirewallDriver for FirewallD {
             }
         }
 
-        Ok(())
+        self.update_policy_rules(None, Some(&tear.config.bridge_name))
     }
 
     fn setup_port_forward(&self, setup_portfw: PortForwardConfig) -> Result<(), NetavarkError> {
+        // Hairpin masquerade is part of the masquerade rules of the network, see
+        // masquerade_rich_rules(). Disabling localhost forwarding needs nothing as the
+        // port forwarding policy never applies to connections from the host.
+
         // NOTE: There is a serious TOCTOU risk in this function if netavark
         // is either run in parallel, or is not the only thing to edit this
         // policy.
@@ -164,10 +209,10 @@ impl firewall::FirewallDriver for FirewallD {
 
         // Create any necessary port forwarding rule(s) and add them to the
         // policy config we grabbed above.
-        // Note that this does *absolutely no* conflict detection or
-        // prevention - if two ports end up mapped to different containers,
-        // that is not detected, and firewalld will allow it to happen.
-        // Only one of them will win and be active, though.
+        // Note that conflict detection is done when writing the firewall
+        // state (see firewall::state), the driver itself does not check.
+        // If a conflict was explicitly allowed firewalld will accept both
+        // rules but only one of them will win and be active.
         match setup_portfw.port_mappings {
             Some(ports) => {
                 for port in ports {
@@ -439,6 +484,355 @@ impl firewall::FirewallDriver for FirewallD {
 
         Ok(())
     }
+
+    fn dump(&self) -> NetavarkResult<FirewallDump> {
+        let mut dump = FirewallDump::new(firewall::FIREWALLD);
+
+        let zones_msg = self.conn.call_method(
+            Some("org.fedoraproject.FirewallD1"),
+            "/org/fedoraproject/FirewallD1",
+            Some("org.fedoraproject.FirewallD1.zone"),
+            "getZones",
+            &(),
+        )?;
+        let body = zones_msg.body();
+        let zones: Vec<&str> = wrap!(
+            body.deserialize(),
+            "Error decoding DBus message for active zones"
+        )?;
+        // Nothing was set up yet.
+        if !zones.contains(&ZONENAME) {
+            return Ok(dump);
+        }
+
+        let sources_msg = self.conn.call_method(
+            Some("org.fedoraproject.FirewallD1"),
+            "/org/fedoraproject/FirewallD1",
+            Some("org.fedoraproject.FirewallD1.zone"),
+            "getSources",
+            &(ZONENAME),
+        )?;
+        let body = sources_msg.body();
+        let sources: Vec<String> = wrap!(
+            body.deserialize(),
+            format!("Error decoding DBus message for zone {ZONENAME} sources")
+        )?;
+        for source in sources {
+            match source.parse() {
+                Ok(net) => dump.networks.push(net),
+                Err(_) => debug!("Ignoring non subnet source {source} in zone {ZONENAME}"),
+            }
+        }
+
+        let mut policy_config = get_policy_settings(&self.conn, POLICYNAME)?;
+        if let Some(Value::Bool(true)) = policy_config.remove("masquerade") {
+            // masquerading is done for the whole zone
+            dump.masquerade = dump.networks.clone();
+        }
+        if let Some(Value::Array(rules)) = policy_config.remove("rich_rules") {
+            for rule in rules.iter() {
+                if let Value::Str(rule) = rule {
+                    if let Some(net) = parse_masquerade_rich_rule(rule.as_str()) {
+                        dump.masquerade.push(net);
+                    } else if let Some(drop) = parse_isolation_rich_rule(rule.as_str()) {
+                        dump.isolation_drops.push(drop);
+                    }
+                }
+            }
+        }
+
+        let mut policy_config = get_policy_settings(&self.conn, PORTPOLICYNAME)?;
+        if let Some(Value::Array(rules)) = policy_config.remove("forward_ports") {
+            for port_tuple in rules.iter() {
+                let fields: Vec<&str> = match port_tuple {
+                    Value::Structure(s) => s
+                        .fields()
+                        .iter()
+                        .filter_map(|f| match f {
+                            Value::Str(s) => Some(s.as_str()),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => continue,
+                };
+                dump.port_forwards
+                    .append(&mut parse_port_tuple(&fields).unwrap_or_default());
+            }
+        }
+
+        dump.normalize();
+        Ok(dump)
+    }
+
+    fn drop_counters(&self) -> NetavarkResult<Vec<DropCounter>> {
+        // firewalld does not expose counters for the isolation rich rules
+        Ok(vec![])
+    }
+}
+
+impl FirewallD {
+    /// Recompute the masquerade and isolation rich rules of the netavark policy from
+    /// all networks, the masquerade of the policy itself is turned off at runtime.
+    /// The network being set up may not be in the firewall state yet and the one
+    /// being torn down may still be there, so both are given explicitly.
+    fn update_policy_rules(
+        &self,
+        setup: Option<&SetupNetwork>,
+        removed_bridge: Option<&str>,
+    ) -> NetavarkResult<()> {
+        let Some(config_dir) = &self.config_dir else {
+            debug!("No config dir, not updating the firewalld policy rules");
+            return Ok(());
+        };
+        let mut nets = firewall::state::read_net_confs(config_dir)?;
+        nets.retain(|net| {
+            Some(net.bridge_name.as_str()) != removed_bridge
+                && Some(&net.bridge_name) != setup.map(|s| &s.bridge_name)
+        });
+        if let Some(setup) = setup {
+            nets.push(setup.clone());
+        }
+        let mut rules = masquerade_rich_rules(&nets);
+        rules.append(&mut isolation_rich_rules(&nets));
+
+        let mut policy_config = get_policy_settings(&self.conn, POLICYNAME)?;
+        let current: Vec<String> = match policy_config.remove("rich_rules") {
+            Some(Value::Array(arr)) => arr
+                .iter()
+                .filter_map(|r| match r {
+                    Value::Str(s) => Some(s.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let masquerade = matches!(policy_config.remove("masquerade"), Some(Value::Bool(true)));
+        if current == rules && !masquerade {
+            return Ok(());
+        }
+
+        debug!("Updating rich rules of policy {POLICYNAME}");
+        let new_rich_rules = Value::new(Array::from(rules));
+        let new_masquerade = Value::new(false);
+        let mut new_policy_config = HashMap::<&str, &Value>::new();
+        new_policy_config.insert("rich_rules", &new_rich_rules);
+        new_policy_config.insert("masquerade", &new_masquerade);
+        match self.conn.call_method(
+            Some("org.fedoraproject.FirewallD1"),
+            "/org/fedoraproject/FirewallD1",
+            Some("org.fedoraproject.FirewallD1.policy"),
+            "setPolicySettings",
+            &(POLICYNAME, new_policy_config),
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(NetavarkError::wrap(
+                format!("Failed to update the rich rules of policy {POLICYNAME}"),
+                e.into(),
+            )),
+        }
+    }
+}
+
+/// Get the rich rules which masquerade the traffic of the networks. Without hairpin
+/// masquerade the traffic to the own subnet, which is only routed when it was
+/// forwarded to a published port, keeps the container address.
+fn masquerade_rich_rules(nets: &[SetupNetwork]) -> Vec<String> {
+    let mut rules = Vec::new();
+    for net in nets {
+        for subnet in net.subnets.iter().flatten() {
+            let family = match subnet {
+                ipnet::IpNet::V4(_) => "ipv4",
+                ipnet::IpNet::V6(_) => "ipv6",
+            };
+            rules.push(if net.hairpin_masquerade {
+                format!("rule family=\"{family}\" source address=\"{subnet}\" masquerade")
+            } else {
+                format!(
+                    "rule family=\"{family}\" source address=\"{subnet}\" destination NOT address=\"{subnet}\" masquerade"
+                )
+            });
+        }
+    }
+    rules
+}
+
+/// Get the source subnet of a rule made by masquerade_rich_rules().
+fn parse_masquerade_rich_rule(rule: &str) -> Option<ipnet::IpNet> {
+    if !rule.ends_with(" masquerade") {
+        return None;
+    }
+    rich_rule_address(rule, "source")
+}
+
+/// Get the address of the source or destination element of a rich rule.
+fn rich_rule_address(rule: &str, element: &str) -> Option<ipnet::IpNet> {
+    let (_, rest) = rule.split_once(&format!(" {element} address=\""))?;
+    rest.split('"').next()?.parse().ok()
+}
+
+/// Get the traffic which is dropped between isolated networks, it follows the
+/// iptables isolation: normal networks cannot reach other isolated networks,
+/// strict networks cannot reach any other network, unless both share an isolation group.
+pub(crate) fn isolation_drops(nets: &[SetupNetwork]) -> Vec<IsolationDrop> {
+    let mut drops = Vec::new();
+    for net in nets {
+        for other in nets {
+            if net.bridge_name == other.bridge_name {
+                continue;
+            }
+            let blocked = match net.isolation {
+                IsolateOption::Strict => true,
+                IsolateOption::Normal => other.isolation != IsolateOption::Never,
+                IsolateOption::Never => false,
+            };
+            if !blocked
+                || net
+                    .isolation_groups
+                    .iter()
+                    .any(|g| other.isolation_groups.contains(g))
+            {
+                continue;
+            }
+            for source in net.subnets.iter().flatten() {
+                for destination in other.subnets.iter().flatten() {
+                    if source.addr().is_ipv4() == destination.addr().is_ipv4() {
+                        drops.push(IsolationDrop {
+                            source: *source,
+                            destination: *destination,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    drops
+}
+
+/// Get the rich rules for isolation_drops().
+fn isolation_rich_rules(nets: &[SetupNetwork]) -> Vec<String> {
+    isolation_drops(nets)
+        .iter()
+        .map(|drop| {
+            let family = match drop.source {
+                ipnet::IpNet::V4(_) => "ipv4",
+                ipnet::IpNet::V6(_) => "ipv6",
+            };
+            format!(
+                "rule family=\"{family}\" source address=\"{}\" destination address=\"{}\" drop",
+                drop.source, drop.destination
+            )
+        })
+        .collect()
+}
+
+/// Parse a rule made by isolation_rich_rules().
+fn parse_isolation_rich_rule(rule: &str) -> Option<IsolationDrop> {
+    if !rule.ends_with(" drop") {
+        return None;
+    }
+    Some(IsolationDrop {
+        source: rich_rule_address(rule, "source")?,
+        destination: rich_rule_address(rule, "destination")?,
+    })
+}
+
+/// Get the direct rules to SNAT the traffic of the network to a fixed source address.
+/// The policy can only masquerade so SNAT takes precedence by using a direct rule,
+/// which also means excluding destinations from the masquerade is not possible.
+fn get_snat_direct_rules(network_setup: &SetupNetwork) -> Vec<(&'static str, Vec<String>)> {
+    let mut rules = Vec::new();
+    for subnet in network_setup.subnets.iter().flatten() {
+        let Some(addr) = network_setup.snat_address(subnet) else {
+            continue;
+        };
+        let (ipv, multicast) = match subnet {
+            ipnet::IpNet::V4(_) => ("ipv4", "224.0.0.0/4"),
+            ipnet::IpNet::V6(_) => ("ipv6", "ff00::/8"),
+        };
+        let args = [
+            "-s",
+            &subnet.to_string(),
+            "!",
+            "-d",
+            multicast,
+            "-j",
+            "SNAT",
+            "--to-source",
+            &addr.to_string(),
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        rules.push((ipv, args));
+    }
+    rules
+}
+
+/// Call a method of the firewalld direct interface for a nat POSTROUTING rule.
+fn call_direct(
+    conn: &Connection,
+    method: &str,
+    ipv: &str,
+    args: &[String],
+) -> NetavarkResult<zbus::Message> {
+    Ok(conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1",
+        Some("org.fedoraproject.FirewallD1.direct"),
+        method,
+        &(ipv, "nat", "POSTROUTING", 0i32, args),
+    )?)
+}
+
+/// Get the runtime settings of the given policy.
+fn get_policy_settings(
+    conn: &Connection,
+    policy_name: &str,
+) -> NetavarkResult<HashMap<String, Value<'static>>> {
+    let policy_config_msg = conn.call_method(
+        Some("org.fedoraproject.FirewallD1"),
+        "/org/fedoraproject/FirewallD1",
+        Some("org.fedoraproject.FirewallD1.policy"),
+        "getPolicySettings",
+        &(policy_name),
+    )?;
+    let body = policy_config_msg.body();
+    let policy_config: HashMap<String, Value> = wrap!(
+        body.deserialize(),
+        format!("Error decoding DBus message for policy {policy_name} configuration")
+    )?;
+    policy_config
+        .into_iter()
+        .map(|(k, v)| {
+            Ok((
+                k,
+                wrap!(v.try_to_owned(), "Unable to copy policy setting")?.into(),
+            ))
+        })
+        .collect()
+}
+
+/// Convert a port forward tuple (port, protocol, to-port, to-addr) back into
+/// one port forward per port, see make_port_tuple().
+fn parse_port_tuple(fields: &[&str]) -> Option<Vec<PortForward>> {
+    let [port, protocol, to_port, to_addr] = fields else {
+        return None;
+    };
+    let (host_start, host_end) = parse_port_range(port)?;
+    let (dest_start, dest_end) = parse_port_range(to_port)?;
+    let dest_ip = to_addr.parse().ok()?;
+    Some(
+        (host_start..=host_end)
+            .enumerate()
+            .map(|(i, host_port)| PortForward {
+                protocol: protocol.to_string(),
+                host_ip: None,
+                host_port,
+                dest_ip,
+                dest_port: dest_port_for(dest_start, dest_end, i as u16),
+            })
+            .collect(),
+    )
 }
 
 /// Create a firewalld zone to hold all our interfaces.
@@ -624,11 +1018,16 @@ fn make_port_tuple(port: &PortMapping, addr: &str) -> (String, String, String, S
     if port.range > 1 {
         // Subtract 1 as these are 1-indexed strings - range of 2 is 1000-1001
         let end_host_range = port.host_port + port.range - 1;
-        let end_ctr_range = port.container_port + port.range - 1;
+        let to_port = if port.is_many_to_one() {
+            format!("{}", port.container_port)
+        } else {
+            let end_ctr_range = port.container_port + port.range - 1;
+            format!("{}-{}", port.container_port, end_ctr_range)
+        };
         (
             format!("{}-{}", port.host_port, end_host_range),
             port.protocol.clone(),
-            format!("{}-{}", port.container_port, end_ctr_range),
+            to_port,
             addr.to_string(),
         )
     } else {
@@ -709,3 +1108,108 @@ pub fn rm_firewalld_if_possible(net: &ipnet::IpNet) {
         ),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(bridge: &str, subnet: &str, isolation: IsolateOption, groups: &[&str]) -> SetupNetwork {
+        SetupNetwork {
+            subnets: Some(vec![subnet.parse().unwrap()]),
+            bridge_name: bridge.to_string(),
+            network_id: bridge.to_string(),
+            network_hash_name: bridge.to_string(),
+            isolation,
+            isolation_groups: groups.iter().map(|g| g.to_string()).collect(),
+            dns_port: 53,
+            log_dropped: None,
+            snat_ipv4: None,
+            snat_ipv6: None,
+            snat_exclude: vec![],
+            hairpin_masquerade: true,
+        }
+    }
+
+    #[test]
+    fn test_isolation_rich_rules() {
+        let nets = vec![
+            net("podman0", "10.88.0.0/16", IsolateOption::Never, &[]),
+            net(
+                "podman1",
+                "10.89.0.0/24",
+                IsolateOption::Normal,
+                &["shared"],
+            ),
+            net("podman2", "10.89.1.0/24", IsolateOption::Normal, &[]),
+            net(
+                "podman3",
+                "10.89.2.0/24",
+                IsolateOption::Strict,
+                &["shared"],
+            ),
+            net("podman4", "fd00::/64", IsolateOption::Strict, &[]),
+        ];
+        let rule = |src: &str, dst: &str| {
+            format!(
+                "rule family=\"ipv4\" source address=\"{src}\" destination address=\"{dst}\" drop"
+            )
+        };
+        assert_eq!(
+            isolation_rich_rules(&nets),
+            vec![
+                rule("10.89.0.0/24", "10.89.1.0/24"),
+                rule("10.89.1.0/24", "10.89.0.0/24"),
+                rule("10.89.1.0/24", "10.89.2.0/24"),
+                rule("10.89.2.0/24", "10.88.0.0/16"),
+                rule("10.89.2.0/24", "10.89.1.0/24"),
+            ]
+        );
+        assert!(isolation_rich_rules(&nets[..1]).is_empty());
+        let parsed: Vec<_> = isolation_rich_rules(&nets)
+            .iter()
+            .filter_map(|r| parse_isolation_rich_rule(r))
+            .collect();
+        assert_eq!(parsed, isolation_drops(&nets));
+        assert_eq!(
+            parse_isolation_rich_rule(
+                "rule family=\"ipv4\" source address=\"10.88.0.0/16\" masquerade"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_masquerade_rich_rules() {
+        let mut no_hairpin = net("podman1", "fd00::/64", IsolateOption::Never, &[]);
+        no_hairpin.hairpin_masquerade = false;
+        let nets = vec![
+            net("podman0", "10.88.0.0/16", IsolateOption::Never, &[]),
+            no_hairpin,
+        ];
+        let rules = masquerade_rich_rules(&nets);
+        assert_eq!(
+            rules,
+            vec![
+                "rule family=\"ipv4\" source address=\"10.88.0.0/16\" masquerade",
+                "rule family=\"ipv6\" source address=\"fd00::/64\" destination NOT address=\"fd00::/64\" masquerade",
+            ]
+        );
+        let parsed: Vec<_> = rules
+            .iter()
+            .filter_map(|r| parse_masquerade_rich_rule(r))
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                "10.88.0.0/16".parse::<ipnet::IpNet>().unwrap(),
+                "fd00::/64".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            parse_masquerade_rich_rule(
+                "rule family=\"ipv4\" source address=\"10.88.0.0/16\" destination address=\"10.89.0.0/24\" drop"
+            ),
+            None
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::firewall;
+use crate::firewall::dump::{DropCounter, FirewallDump};
 use crate::firewall::NetavarkResult;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
@@ -33,4 +34,12 @@ impl firewall::FirewallDriver for Fwnone {
     fn teardown_port_forward(&self, _tear: TeardownPortForward) -> NetavarkResult<()> {
         Ok(())
     }
+
+    fn dump(&self) -> NetavarkResult<FirewallDump> {
+        Ok(FirewallDump::new(firewall::NONE))
+    }
+
+    fn drop_counters(&self) -> NetavarkResult<Vec<DropCounter>> {
+        Ok(vec![])
+    }
 }
@@ -1,9 +1,11 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
+use crate::firewall::dump::{merge_drop_counters, DropCounter, FirewallDump};
 use crate::firewall::firewalld;
 use crate::firewall::varktables::types::TeardownPolicy::OnComplete;
 use crate::firewall::varktables::types::{
-    create_network_chains, get_network_chains, get_port_forwarding_chains, TeardownPolicy,
+    create_network_chains, dump_rules, get_drop_counters, get_network_chains,
+    get_port_forwarding_chains, TeardownPolicy,
 };
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
@@ -42,23 +44,15 @@ impl firewall::FirewallDriver for IptablesDriver {
     }
 
     fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()> {
-        if let Some(subnet) = network_setup.subnets {
-            for network in subnet {
+        if let Some(subnet) = &network_setup.subnets {
+            for &network in subnet {
                 let is_ipv6 = network.network().is_ipv6();
                 let mut conn = &self.conn;
                 if is_ipv6 {
                     conn = &self.conn6;
                 }
 
-                let chains = get_network_chains(
-                    conn,
-                    network,
-                    &network_setup.network_hash_name,
-                    is_ipv6,
-                    network_setup.bridge_name.clone(),
-                    network_setup.isolation,
-                    network_setup.dns_port,
-                );
+                let chains = get_network_chains(conn, network, &network_setup, is_ipv6);
 
                 create_network_chains(chains)?;
 
@@ -72,22 +66,14 @@ impl firewall::FirewallDriver for IptablesDriver {
     // a complete teardown.
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
         // Remove network specific general NAT rules
-        if let Some(subnet) = tear.config.subnets {
-            for network in subnet {
+        if let Some(subnet) = &tear.config.subnets {
+            for &network in subnet {
                 let is_ipv6 = network.network().is_ipv6();
                 let mut conn = &self.conn;
                 if is_ipv6 {
                     conn = &self.conn6;
                 }
-                let chains = get_network_chains(
-                    conn,
-                    network,
-                    &tear.config.network_hash_name,
-                    is_ipv6,
-                    tear.config.bridge_name.clone(),
-                    tear.config.isolation,
-                    tear.config.dns_port,
-                );
+                let chains = get_network_chains(conn, network, &tear.config, is_ipv6);
 
                 for c in &chains {
                     c.remove_rules(tear.complete_teardown)?;
@@ -213,4 +199,18 @@ impl firewall::FirewallDriver for IptablesDriver {
         }
         Result::Ok(())
     }
+
+    fn dump(&self) -> NetavarkResult<FirewallDump> {
+        let mut dump = FirewallDump::new(firewall::IPTABLES);
+        dump_rules(&self.conn, &mut dump)?;
+        dump_rules(&self.conn6, &mut dump)?;
+        dump.normalize();
+        Ok(dump)
+    }
+
+    fn drop_counters(&self) -> NetavarkResult<Vec<DropCounter>> {
+        let mut counters = get_drop_counters(&self.conn)?;
+        counters.append(&mut get_drop_counters(&self.conn6)?);
+        Ok(merge_drop_counters(counters))
+    }
 }
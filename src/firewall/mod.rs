@@ -3,8 +3,12 @@ use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
 use log::{debug, info};
+use std::env;
+use std::fs;
+use std::path::Path;
 use zbus::blocking::Connection;
 
+pub mod dump;
 pub mod firewalld;
 pub mod fwnone;
 pub mod iptables;
@@ -13,9 +17,19 @@ pub mod state;
 mod varktables;
 
 const IPTABLES: &str = "iptables";
-const FIREWALLD: &str = "firewalld";
+pub(crate) const FIREWALLD: &str = "firewalld";
 const NFTABLES: &str = "nftables";
 const NONE: &str = "none";
+const AUTO: &str = "auto";
+
+/// Rate limit for logging packets dropped by the isolation rules, in packets per minute.
+const DROP_LOG_RATE: u32 = 5;
+
+/// Log prefix for packets dropped by the isolation rules of the given bridge.
+/// iptables allows at most 29 characters which fits the 15 character interface name.
+fn drop_log_prefix(bridge: &str) -> String {
+    format!("NETAVARK {bridge}: ")
+}
 
 /// Firewall drivers have the ability to set up per-network firewall forwarding
 /// and port mappings.
@@ -30,6 +44,12 @@ pub trait FirewallDriver {
     /// Tear down port-forwarding firewall rules for a single container.
     fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()>;
 
+    /// Read back the netavark rules from the firewall.
+    fn dump(&self) -> NetavarkResult<dump::FirewallDump>;
+
+    /// Read the packet and byte counters of the isolation drop rules per bridge.
+    fn drop_counters(&self) -> NetavarkResult<Vec<dump::DropCounter>>;
+
     /// Return the name of the driver.
     fn driver_name(&self) -> &str;
 }
@@ -43,74 +63,143 @@ enum FirewallImpl {
 }
 
 /// What firewall implementations does this system support?
-fn get_firewall_impl(driver_name: Option<String>) -> NetavarkResult<FirewallImpl> {
-    // It respects "firewalld", "iptables", "nftables", "none".
+fn get_firewall_impl(
+    driver_name: Option<String>,
+    config_dir: Option<&Path>,
+    rootless: bool,
+) -> NetavarkResult<FirewallImpl> {
+    // It respects "firewalld", "iptables", "nftables", "none" and "auto".
     if let Some(driver) = driver_name {
-        debug!("Forcibly using firewall driver {driver}");
-        match driver.to_lowercase().as_str() {
-            FIREWALLD => {
-                let conn = match Connection::system() {
-                    Ok(c) => c,
-                    Err(e) => {
-                        return Err(NetavarkError::wrap(
-                            "Error retrieving dbus connection for requested firewall backend",
-                            e.into(),
-                        ))
-                    }
-                };
-                return Ok(FirewallImpl::Firewalld(conn));
-            }
-            IPTABLES => return Ok(FirewallImpl::Iptables),
-            NFTABLES => return Ok(FirewallImpl::Nftables),
-            NONE => return Ok(FirewallImpl::Fwnone),
-            any => {
-                return Err(NetavarkError::Message(format!(
-                    "Must provide a valid firewall backend, got {any}"
-                )))
-            }
+        if driver.to_lowercase() == AUTO {
+            return detect_fw_impl(config_dir, rootless);
         }
+        debug!("Forcibly using firewall driver {driver}");
+        return new_fw_impl(&driver);
     }
 
-    get_default_fw_impl()
-
-    // Is firewalld running?
-    // let conn = match Connection::system() {
-    //     Ok(conn) => conn,
-    //     Err(_) => return FirewallImpl::Iptables,
-    // };
-    // match conn.call_method(
-    //     Some("org.freedesktop.DBus"),
-    //     "/org/freedesktop/DBus",
-    //     Some("org.freedesktop.DBus"),
-    //     "GetNameOwner",
-    //     &"org.fedoraproject.FirewallD1",
-    // ) {
-    //     Ok(_) => FirewallImpl::Firewalld(conn),
-    //     Err(_) => FirewallImpl::Iptables,
-    // }
+    get_default_fw_impl(config_dir, rootless)
+}
+
+/// Create the firewall implementation for the named driver.
+fn new_fw_impl(driver: &str) -> NetavarkResult<FirewallImpl> {
+    match driver.to_lowercase().as_str() {
+        FIREWALLD => {
+            let conn = match Connection::system() {
+                Ok(c) => c,
+                Err(e) => {
+                    return Err(NetavarkError::wrap(
+                        "Error retrieving dbus connection for requested firewall backend",
+                        e.into(),
+                    ))
+                }
+            };
+            Ok(FirewallImpl::Firewalld(conn))
+        }
+        IPTABLES => Ok(FirewallImpl::Iptables),
+        NFTABLES => Ok(FirewallImpl::Nftables),
+        NONE => Ok(FirewallImpl::Fwnone),
+        any => Err(NetavarkError::Message(format!(
+            "Must provide a valid firewall backend, got {any}"
+        ))),
+    }
 }
 
 #[cfg(default_fw = "nftables")]
-fn get_default_fw_impl() -> NetavarkResult<FirewallImpl> {
+fn get_default_fw_impl(
+    _config_dir: Option<&Path>,
+    _rootless: bool,
+) -> NetavarkResult<FirewallImpl> {
     Ok(FirewallImpl::Nftables)
 }
 
 #[cfg(default_fw = "iptables")]
-fn get_default_fw_impl() -> NetavarkResult<FirewallImpl> {
+fn get_default_fw_impl(
+    _config_dir: Option<&Path>,
+    _rootless: bool,
+) -> NetavarkResult<FirewallImpl> {
     Ok(FirewallImpl::Iptables)
 }
 
 #[cfg(default_fw = "none")]
-fn get_default_fw_impl() -> NetavarkResult<FirewallImpl> {
+fn get_default_fw_impl(
+    _config_dir: Option<&Path>,
+    _rootless: bool,
+) -> NetavarkResult<FirewallImpl> {
     Ok(FirewallImpl::Fwnone)
 }
 
+#[cfg(default_fw = "auto")]
+fn get_default_fw_impl(config_dir: Option<&Path>, rootless: bool) -> NetavarkResult<FirewallImpl> {
+    detect_fw_impl(config_dir, rootless)
+}
+
+/// Select the firewall implementation based on the running system.
+fn detect_fw_impl(config_dir: Option<&Path>, rootless: bool) -> NetavarkResult<FirewallImpl> {
+    let recorded = match config_dir {
+        Some(dir) => state::read_fw_driver(dir)?,
+        None => None,
+    };
+    // Never use firewalld for rootless, the system firewalld does not
+    // manage the rootless network namespace.
+    let firewalld_running = !rootless
+        && recorded.is_none()
+        && Connection::system()
+            .map(|conn| firewalld::is_firewalld_running(&conn))
+            .unwrap_or(false);
+
+    let (driver, reason) = choose_fw_driver(
+        recorded.as_deref(),
+        firewalld_running,
+        is_iptables_legacy_in_use(),
+        is_nft_available(),
+    );
+    info!("Automatically selected firewall driver {driver}: {reason}");
+    new_fw_impl(driver)
+}
+
+/// Decide which firewall driver to use, returns the driver name and the reason.
+fn choose_fw_driver(
+    recorded: Option<&str>,
+    firewalld_running: bool,
+    iptables_legacy: bool,
+    nft_available: bool,
+) -> (&str, &'static str) {
+    match recorded {
+        // Keep using the driver that created the rules of the running containers,
+        // otherwise teardown would not find the rules.
+        Some(driver) if driver != AUTO => (driver, "used by the running containers"),
+        _ if firewalld_running => (FIREWALLD, "firewalld is running"),
+        // Mixing legacy and nftables rules breaks the ordering of the rules.
+        _ if iptables_legacy => (IPTABLES, "an iptables-legacy ruleset is in use"),
+        _ if nft_available => (NFTABLES, "nftables is available"),
+        _ => (IPTABLES, "nftables is not available"),
+    }
+}
+
+/// The legacy iptables tables are only listed here once they are used.
+fn is_iptables_legacy_in_use() -> bool {
+    ["/proc/net/ip_tables_names", "/proc/net/ip6_tables_names"]
+        .iter()
+        .any(|f| fs::read_to_string(f).is_ok_and(|names| !names.trim().is_empty()))
+}
+
+/// The nftables driver needs the nft binary.
+fn is_nft_available() -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join("nft").is_file()))
+}
+
 /// Get the preferred firewall implementation for the current system
 /// configuration.
+///
+/// The config_dir is used to look up the driver of the existing rules when
+/// the driver is detected automatically.
 pub fn get_supported_firewall_driver(
     driver_name: Option<String>,
+    config_dir: Option<&Path>,
+    rootless: bool,
 ) -> NetavarkResult<Box<dyn FirewallDriver>> {
-    match get_firewall_impl(driver_name) {
+    match get_firewall_impl(driver_name, config_dir, rootless) {
         Ok(fw) => match fw {
             FirewallImpl::Iptables => {
                 info!("Using iptables firewall driver");
@@ -118,7 +207,7 @@ pub fn get_supported_firewall_driver(
             }
             FirewallImpl::Firewalld(conn) => {
                 info!("Using firewalld firewall driver");
-                firewalld::new(conn)
+                firewalld::new(conn, config_dir)
             }
             FirewallImpl::Nftables => {
                 info!("Using nftables firewall driver");
@@ -132,3 +221,21 @@ pub fn get_supported_firewall_driver(
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_fw_driver() {
+        assert_eq!(
+            choose_fw_driver(Some(NFTABLES), true, true, true).0,
+            NFTABLES
+        );
+        assert_eq!(choose_fw_driver(Some(AUTO), true, true, true).0, FIREWALLD);
+        assert_eq!(choose_fw_driver(None, true, true, true).0, FIREWALLD);
+        assert_eq!(choose_fw_driver(None, false, true, true).0, IPTABLES);
+        assert_eq!(choose_fw_driver(None, false, false, true).0, NFTABLES);
+        assert_eq!(choose_fw_driver(None, false, false, false).0, IPTABLES);
+    }
+}
@@ -1,8 +1,11 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
+use crate::firewall::dump::{
+    merge_drop_counters, DropCounter, FirewallDump, Isolation, IsolationGroup, PortForward,
+};
 use crate::firewall::firewalld;
 use crate::network::internal_types;
-use crate::network::internal_types::IsolateOption;
+use crate::network::internal_types::{DropLog, IsolateOption};
 use crate::network::types::PortMapping;
 use ipnet::IpNet;
 use nftables::batch::Batch;
@@ -11,8 +14,8 @@ use nftables::helper::{self};
 use nftables::schema;
 use nftables::stmt;
 use nftables::types;
-use std::collections::HashSet;
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 const TABLENAME: &str = "netavark";
 
@@ -26,6 +29,7 @@ const MASKCHAIN: &str = "NETAVARK-HOSTPORT-SETMARK";
 const ISOLATION1CHAIN: &str = "NETAVARK-ISOLATION-1";
 const ISOLATION2CHAIN: &str = "NETAVARK-ISOLATION-2";
 const ISOLATION3CHAIN: &str = "NETAVARK-ISOLATION-3";
+const ISOLATION_GROUP_CHAIN_PREFIX: &str = "nv_iso_";
 
 const MASK: u32 = 0x2000;
 
@@ -53,201 +57,8 @@ impl firewall::FirewallDriver for Nftables {
     fn setup_network(&self, network_setup: internal_types::SetupNetwork) -> NetavarkResult<()> {
         let mut batch = Batch::new();
 
-        // Overall table
-        batch.add(schema::NfListObject::Table(schema::Table {
-            family: types::NfFamily::INet,
-            name: TABLENAME.to_string(),
-            ..schema::Table::default()
-        }));
-
-        // Five default chains, one for each hook we have to monitor
-        batch.add(make_complex_chain(
-            INPUTCHAIN,
-            types::NfChainType::Filter,
-            types::NfHook::Input,
-            FILTERPRIO,
-        ));
-        batch.add(make_complex_chain(
-            FORWARDCHAIN,
-            types::NfChainType::Filter,
-            types::NfHook::Forward,
-            FILTERPRIO,
-        ));
-        batch.add(make_complex_chain(
-            POSTROUTINGCHAIN,
-            types::NfChainType::NAT,
-            types::NfHook::Postrouting,
-            SRCNATPRIO,
-        ));
-        batch.add(make_complex_chain(
-            PREROUTINGCHAIN,
-            types::NfChainType::NAT,
-            types::NfHook::Prerouting,
-            DNATPRIO,
-        ));
-        batch.add(make_complex_chain(
-            OUTPUTCHAIN,
-            types::NfChainType::NAT,
-            types::NfHook::Output,
-            DNATPRIO,
-        ));
-
-        // dnat rules. Not used here, but need to be created first, because they have rules that must be first in their chains.
-        // A lot of these are thus conditional on if the rule already exists or not.
         let existing_rules = get_netavark_rules()?;
-
-        // Two extra chains, not hooked to anything, for our NAT pf rules
-        batch.add(make_basic_chain(DNATCHAIN));
-        batch.add(make_basic_chain(MASKCHAIN));
-
-        // Three extra chains, not hooked to anything, for isolation.
-        batch.add(make_basic_chain(ISOLATION1CHAIN));
-        batch.add(make_basic_chain(ISOLATION2CHAIN));
-        batch.add(make_basic_chain(ISOLATION3CHAIN));
-
-        // Postrouting chain needs a single rule to masquerade if mask is set.
-        // But only one copy of that rule. So check if such a rule exists.
-        let match_meta_masq = |r: &schema::Rule| -> bool {
-            // Match on any rule that matches against 0x2000
-            for statement in &r.expr {
-                match statement {
-                    stmt::Statement::Match(m) => match &m.right {
-                        expr::Expression::Number(n) => {
-                            if *n == MASK {
-                                return true;
-                            }
-                        }
-                        _ => continue,
-                    },
-                    _ => continue,
-                }
-            }
-            false
-        };
-        if get_matching_rules_in_chain(&existing_rules, POSTROUTINGCHAIN, match_meta_masq)
-            .is_empty()
-        {
-            // Postrouting: meta mark & 0x2000 == 0x2000 masquerade
-            batch.add(make_rule(
-                POSTROUTINGCHAIN,
-                vec![
-                    stmt::Statement::Match(stmt::Match {
-                        left: expr::Expression::BinaryOperation(expr::BinaryOperation::AND(
-                            Box::new(expr::Expression::Named(expr::NamedExpression::Meta(
-                                expr::Meta {
-                                    key: expr::MetaKey::Mark,
-                                },
-                            ))),
-                            Box::new(expr::Expression::Number(MASK)),
-                        )),
-                        right: expr::Expression::Number(MASK),
-                        op: stmt::Operator::EQ,
-                    }),
-                    stmt::Statement::Masquerade(None),
-                ],
-            ));
-        }
-
-        // Mask chain needs a single rule to apply the mask.
-        // But only one copy of that rule. So check if such a rule exists.
-        let match_meta_mark = |r: &schema::Rule| -> bool {
-            // Match on any mangle rule.
-            for statement in &r.expr {
-                match statement {
-                    stmt::Statement::Mangle(_) => return true,
-                    _ => continue,
-                }
-            }
-            false
-        };
-        if get_matching_rules_in_chain(&existing_rules, MASKCHAIN, match_meta_mark).is_empty() {
-            // Mask chain: mark or 0x2000
-            batch.add(make_rule(
-                MASKCHAIN,
-                vec![stmt::Statement::Mangle(stmt::Mangle {
-                    key: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
-                        key: expr::MetaKey::Mark,
-                    })),
-                    value: expr::Expression::BinaryOperation(expr::BinaryOperation::OR(
-                        Box::new(expr::Expression::Named(expr::NamedExpression::Meta(
-                            expr::Meta {
-                                key: expr::MetaKey::Mark,
-                            },
-                        ))),
-                        Box::new(expr::Expression::Number(MASK)),
-                    )),
-                })],
-            ));
-        }
-
-        // We need rules in Prerouting and Output pointing to our dnat chain.
-        // But only if they do not exist.
-        let match_jump_dnat = get_rule_matcher_jump_to(DNATCHAIN.to_string());
-        // Prerouting: fib daddr type local jump <dnat_chain>
-        // Output: fib daddr type local jump <dnat_chain>
-        let mut rules_hash: HashSet<expr::FibFlag> = HashSet::new();
-        rules_hash.insert(expr::FibFlag::Daddr);
-        let base_conditions: Vec<stmt::Statement> = vec![
-            stmt::Statement::Match(stmt::Match {
-                left: expr::Expression::Named(expr::NamedExpression::Fib(expr::Fib {
-                    result: expr::FibResult::Type,
-                    flags: rules_hash,
-                })),
-                right: expr::Expression::String("local".to_string()),
-                op: stmt::Operator::EQ,
-            }),
-            get_jump_action(DNATCHAIN),
-        ];
-        if get_matching_rules_in_chain(&existing_rules, PREROUTINGCHAIN, &match_jump_dnat)
-            .is_empty()
-        {
-            batch.add(make_rule(PREROUTINGCHAIN, base_conditions.clone()));
-        }
-        if get_matching_rules_in_chain(&existing_rules, OUTPUTCHAIN, &match_jump_dnat).is_empty() {
-            batch.add(make_rule(OUTPUTCHAIN, base_conditions.clone()));
-        }
-
-        // Forward chain: ct state invalid drop
-        let match_deny = |r: &schema::Rule| -> bool {
-            for statement in &r.expr {
-                match statement {
-                    stmt::Statement::Drop(_) => return true,
-                    _ => continue,
-                }
-            }
-            false
-        };
-        if get_matching_rules_in_chain(&existing_rules, FORWARDCHAIN, match_deny).is_empty() {
-            batch.add(make_rule(
-                FORWARDCHAIN,
-                vec![
-                    stmt::Statement::Match(stmt::Match {
-                        left: expr::Expression::Named(expr::NamedExpression::CT(expr::CT {
-                            key: "state".to_string(),
-                            family: None,
-                            dir: None,
-                        })),
-                        right: expr::Expression::String("invalid".to_string()),
-                        op: stmt::Operator::IN,
-                    }),
-                    stmt::Statement::Drop(None),
-                ],
-            ));
-        }
-
-        // Forward chain: jump NETAVARK-ISOLATION-1
-        if get_matching_rules_in_chain(
-            &existing_rules,
-            FORWARDCHAIN,
-            get_rule_matcher_jump_to(ISOLATION1CHAIN.to_string()),
-        )
-        .is_empty()
-        {
-            batch.add(make_rule(
-                FORWARDCHAIN,
-                vec![get_jump_action(ISOLATION1CHAIN)],
-            ));
-        }
+        add_base_objects(&mut batch, &existing_rules);
 
         let match_our_bridge = get_rule_matcher_bridge(&network_setup.bridge_name);
 
@@ -262,7 +73,12 @@ impl firewall::FirewallDriver for Nftables {
             } else {
                 ISOLATION2CHAIN
             };
-            if get_matching_rules_in_chain(&existing_rules, ISOLATION1CHAIN, &match_our_bridge)
+            let match_isolation_jump = |r: &schema::Rule| {
+                match_our_bridge(r)
+                    && !get_rule_jump_target(r)
+                        .is_some_and(|t| t.starts_with(ISOLATION_GROUP_CHAIN_PREFIX))
+            };
+            if get_matching_rules_in_chain(&existing_rules, ISOLATION1CHAIN, match_isolation_jump)
                 .is_empty()
             {
                 batch.add(make_rule(
@@ -291,17 +107,53 @@ impl firewall::FirewallDriver for Nftables {
                 ));
             }
 
+            // Networks sharing an isolation group accept the traffic to each other,
+            // the jumps are inserted in front of the isolation jump above.
+            // Each rule is checked on its own so that missing ones can be re-added.
+            let group_chains = get_isolation_group_chains(&existing_rules);
+            for group in &network_setup.isolation_groups {
+                let group_chain = get_isolation_group_chain_name(group);
+                if !group_chains.iter().any(|c| c.name == group_chain) {
+                    batch.add(make_basic_chain(&group_chain));
+                }
+                // nv_iso_<group>: oifname <bridgename> accept
+                if get_matching_rules_in_chain(&existing_rules, &group_chain, &match_our_bridge)
+                    .is_empty()
+                {
+                    batch.add(make_rule(
+                        &group_chain,
+                        vec![
+                            get_dest_bridge_match(&network_setup.bridge_name),
+                            stmt::Statement::Accept(None),
+                        ],
+                    ));
+                }
+                // NETAVARK-ISOLATION-1: iifname <bridgename> jump nv_iso_<group>
+                let match_group_jump = |r: &schema::Rule| {
+                    match_our_bridge(r) && get_rule_jump_target(r) == Some(group_chain.as_str())
+                };
+                if get_matching_rules_in_chain(&existing_rules, ISOLATION1CHAIN, match_group_jump)
+                    .is_empty()
+                {
+                    batch.add_cmd(schema::NfCmd::Insert(make_rule(
+                        ISOLATION1CHAIN,
+                        vec![
+                            get_src_bridge_match(&network_setup.bridge_name),
+                            get_jump_action(&group_chain),
+                        ],
+                    )));
+                }
+            }
+
             // NETAVARK-ISOLATION-2: oifname == <bridgename> drop
             if get_matching_rules_in_chain(&existing_rules, ISOLATION2CHAIN, match_our_bridge)
                 .is_empty()
             {
-                batch.add(make_rule(
-                    ISOLATION2CHAIN,
-                    vec![
-                        get_dest_bridge_match(&network_setup.bridge_name),
-                        stmt::Statement::Drop(None),
-                    ],
-                ));
+                for rule in
+                    get_isolation_drop_rules(&network_setup.bridge_name, network_setup.log_dropped)
+                {
+                    batch.add(make_rule(ISOLATION2CHAIN, rule));
+                }
             }
         } else {
             // No isolation: insert a rule at position 1 in ISOLATION3 to drop traffic.
@@ -310,13 +162,14 @@ impl firewall::FirewallDriver for Nftables {
             if get_matching_rules_in_chain(&existing_rules, ISOLATION3CHAIN, match_our_bridge)
                 .is_empty()
             {
-                batch.add_cmd(schema::NfCmd::Insert(make_rule(
-                    ISOLATION3CHAIN,
-                    vec![
-                        get_dest_bridge_match(&network_setup.bridge_name),
-                        stmt::Statement::Drop(None),
-                    ],
-                )));
+                // insert in reverse so the rules keep their order
+                for rule in
+                    get_isolation_drop_rules(&network_setup.bridge_name, network_setup.log_dropped)
+                        .into_iter()
+                        .rev()
+                {
+                    batch.add_cmd(schema::NfCmd::Insert(make_rule(ISOLATION3CHAIN, rule)));
+                }
             }
         }
 
@@ -336,8 +189,8 @@ impl firewall::FirewallDriver for Nftables {
         }
 
         // Basic forwarding for all subnets
-        if let Some(nets) = network_setup.subnets {
-            for subnet in nets {
+        if let Some(nets) = &network_setup.subnets {
+            for &subnet in nets {
                 let chain = get_subnet_chain_name(subnet, &network_setup.network_id, false);
 
                 // Add us to firewalld if necessary.
@@ -363,7 +216,19 @@ impl firewall::FirewallDriver for Nftables {
                     ],
                 ));
 
+                // Subnet chain: ip daddr <excluded net> accept
+                for exclude in network_setup.snat_exclude_for(&subnet) {
+                    batch.add(make_rule(
+                        &chain,
+                        vec![
+                            get_subnet_match(exclude, "daddr", stmt::Operator::EQ),
+                            stmt::Statement::Accept(None),
+                        ],
+                    ));
+                }
+
                 // Subnet chain: ip daddr != 224.0.0.0/4 masquerade
+                // or with a fixed source address: ip daddr != 224.0.0.0/4 snat ip to <addr>
                 let multicast_address: IpNet = match subnet {
                     IpNet::V4(_) => "224.0.0.0/4".parse()?,
                     IpNet::V6(_) => "ff::00/8".parse()?,
@@ -372,7 +237,7 @@ impl firewall::FirewallDriver for Nftables {
                     &chain,
                     vec![
                         get_subnet_match(&multicast_address, "daddr", stmt::Operator::NEQ),
-                        stmt::Statement::Masquerade(None),
+                        get_nat_action(network_setup.snat_address(&subnet)),
                     ],
                 ));
 
@@ -524,6 +389,7 @@ impl firewall::FirewallDriver for Nftables {
         let match_our_bridge = get_rule_matcher_bridge(&tear.config.bridge_name);
 
         let mut isolation_rules: Vec<schema::Rule> = Vec::new();
+        let mut group_chains: Vec<schema::Chain> = Vec::new();
         isolation_rules.append(&mut get_matching_rules_in_chain(
             &existing_rules,
             ISOLATION1CHAIN,
@@ -540,6 +406,20 @@ impl firewall::FirewallDriver for Nftables {
             &match_our_bridge,
         ));
 
+        // Remove us from all isolation groups, a group chain without members is removed as well.
+        for chain in get_isolation_group_chains(&existing_rules) {
+            let rules = get_matching_rules_in_chain(&existing_rules, &chain.name, |_| true);
+            let (ours, others): (Vec<_>, Vec<_>) = rules.into_iter().partition(&match_our_bridge);
+            if ours.is_empty() {
+                continue;
+            }
+            isolation_rules.extend(ours);
+            if others.is_empty() {
+                // the chain can only be deleted after the rules, add it at the end
+                group_chains.push(chain);
+            }
+        }
+
         log::debug!(
             "Removing {} isolation rules for network",
             isolation_rules.len()
@@ -547,6 +427,9 @@ impl firewall::FirewallDriver for Nftables {
         for rule in isolation_rules {
             batch.delete(schema::NfListObject::Rule(rule));
         }
+        for chain in group_chains {
+            batch.delete(schema::NfListObject::Chain(chain));
+        }
 
         let rules = batch.to_nftables();
 
@@ -561,6 +444,8 @@ impl firewall::FirewallDriver for Nftables {
         let mut batch = Batch::new();
 
         let existing_rules = get_netavark_rules()?;
+        // networks of plugins may have never set up our table and chains
+        add_base_objects(&mut batch, &existing_rules);
 
         // Need DNAT rules for DNS if Aardvark is not on port 53.
         // Only need one per DNS server IP, so check if they already exist first.
@@ -719,6 +604,434 @@ impl firewall::FirewallDriver for Nftables {
 
         Ok(())
     }
+
+    fn dump(&self) -> NetavarkResult<FirewallDump> {
+        let existing_rules = get_netavark_rules()?;
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&existing_rules, &mut dump);
+        dump.normalize();
+        Ok(dump)
+    }
+
+    fn drop_counters(&self) -> NetavarkResult<Vec<DropCounter>> {
+        let existing_rules = get_netavark_rules()?;
+        Ok(merge_drop_counters(get_drop_counters(&existing_rules)))
+    }
+}
+
+/// Add the table, base chains and shared rules every network relies on,
+/// rules are only added when they do not exist yet.
+fn add_base_objects(batch: &mut Batch, existing_rules: &schema::Nftables) {
+    // Overall table
+    batch.add(schema::NfListObject::Table(schema::Table {
+        family: types::NfFamily::INet,
+        name: TABLENAME.to_string(),
+        ..schema::Table::default()
+    }));
+
+    // Five default chains, one for each hook we have to monitor
+    batch.add(make_complex_chain(
+        INPUTCHAIN,
+        types::NfChainType::Filter,
+        types::NfHook::Input,
+        FILTERPRIO,
+    ));
+    batch.add(make_complex_chain(
+        FORWARDCHAIN,
+        types::NfChainType::Filter,
+        types::NfHook::Forward,
+        FILTERPRIO,
+    ));
+    batch.add(make_complex_chain(
+        POSTROUTINGCHAIN,
+        types::NfChainType::NAT,
+        types::NfHook::Postrouting,
+        SRCNATPRIO,
+    ));
+    batch.add(make_complex_chain(
+        PREROUTINGCHAIN,
+        types::NfChainType::NAT,
+        types::NfHook::Prerouting,
+        DNATPRIO,
+    ));
+    batch.add(make_complex_chain(
+        OUTPUTCHAIN,
+        types::NfChainType::NAT,
+        types::NfHook::Output,
+        DNATPRIO,
+    ));
+
+    // dnat rules. Not used here, but need to be created first, because they have rules that must be first in their chains.
+    // A lot of these are thus conditional on if the rule already exists or not.
+    // Two extra chains, not hooked to anything, for our NAT pf rules
+    batch.add(make_basic_chain(DNATCHAIN));
+    batch.add(make_basic_chain(MASKCHAIN));
+
+    // Three extra chains, not hooked to anything, for isolation.
+    batch.add(make_basic_chain(ISOLATION1CHAIN));
+    batch.add(make_basic_chain(ISOLATION2CHAIN));
+    batch.add(make_basic_chain(ISOLATION3CHAIN));
+
+    // Postrouting chain needs a single rule to masquerade if mask is set.
+    // But only one copy of that rule. So check if such a rule exists.
+    let match_meta_masq = |r: &schema::Rule| -> bool {
+        // Match on any rule that matches against 0x2000
+        for statement in &r.expr {
+            match statement {
+                stmt::Statement::Match(m) => match &m.right {
+                    expr::Expression::Number(n) => {
+                        if *n == MASK {
+                            return true;
+                        }
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            }
+        }
+        false
+    };
+    if get_matching_rules_in_chain(existing_rules, POSTROUTINGCHAIN, match_meta_masq).is_empty() {
+        // Postrouting: meta mark & 0x2000 == 0x2000 masquerade
+        batch.add(make_rule(
+            POSTROUTINGCHAIN,
+            vec![
+                stmt::Statement::Match(stmt::Match {
+                    left: expr::Expression::BinaryOperation(expr::BinaryOperation::AND(
+                        Box::new(expr::Expression::Named(expr::NamedExpression::Meta(
+                            expr::Meta {
+                                key: expr::MetaKey::Mark,
+                            },
+                        ))),
+                        Box::new(expr::Expression::Number(MASK)),
+                    )),
+                    right: expr::Expression::Number(MASK),
+                    op: stmt::Operator::EQ,
+                }),
+                stmt::Statement::Masquerade(None),
+            ],
+        ));
+    }
+
+    // Mask chain needs a single rule to apply the mask.
+    // But only one copy of that rule. So check if such a rule exists.
+    let match_meta_mark = |r: &schema::Rule| -> bool {
+        // Match on any mangle rule.
+        for statement in &r.expr {
+            match statement {
+                stmt::Statement::Mangle(_) => return true,
+                _ => continue,
+            }
+        }
+        false
+    };
+    if get_matching_rules_in_chain(existing_rules, MASKCHAIN, match_meta_mark).is_empty() {
+        // Mask chain: mark or 0x2000
+        batch.add(make_rule(
+            MASKCHAIN,
+            vec![stmt::Statement::Mangle(stmt::Mangle {
+                key: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                    key: expr::MetaKey::Mark,
+                })),
+                value: expr::Expression::BinaryOperation(expr::BinaryOperation::OR(
+                    Box::new(expr::Expression::Named(expr::NamedExpression::Meta(
+                        expr::Meta {
+                            key: expr::MetaKey::Mark,
+                        },
+                    ))),
+                    Box::new(expr::Expression::Number(MASK)),
+                )),
+            })],
+        ));
+    }
+
+    // We need rules in Prerouting and Output pointing to our dnat chain.
+    // But only if they do not exist.
+    let match_jump_dnat = get_rule_matcher_jump_to(DNATCHAIN.to_string());
+    // Prerouting: fib daddr type local jump <dnat_chain>
+    // Output: fib daddr type local jump <dnat_chain>
+    let mut rules_hash: HashSet<expr::FibFlag> = HashSet::new();
+    rules_hash.insert(expr::FibFlag::Daddr);
+    let base_conditions: Vec<stmt::Statement> = vec![
+        stmt::Statement::Match(stmt::Match {
+            left: expr::Expression::Named(expr::NamedExpression::Fib(expr::Fib {
+                result: expr::FibResult::Type,
+                flags: rules_hash,
+            })),
+            right: expr::Expression::String("local".to_string()),
+            op: stmt::Operator::EQ,
+        }),
+        get_jump_action(DNATCHAIN),
+    ];
+    if get_matching_rules_in_chain(existing_rules, PREROUTINGCHAIN, &match_jump_dnat).is_empty() {
+        batch.add(make_rule(PREROUTINGCHAIN, base_conditions.clone()));
+    }
+    if get_matching_rules_in_chain(existing_rules, OUTPUTCHAIN, &match_jump_dnat).is_empty() {
+        batch.add(make_rule(OUTPUTCHAIN, base_conditions.clone()));
+    }
+
+    // Forward chain: ct state invalid drop
+    let match_deny = |r: &schema::Rule| -> bool {
+        for statement in &r.expr {
+            match statement {
+                stmt::Statement::Drop(_) => return true,
+                _ => continue,
+            }
+        }
+        false
+    };
+    if get_matching_rules_in_chain(existing_rules, FORWARDCHAIN, match_deny).is_empty() {
+        batch.add(make_rule(
+            FORWARDCHAIN,
+            vec![
+                stmt::Statement::Match(stmt::Match {
+                    left: expr::Expression::Named(expr::NamedExpression::CT(expr::CT {
+                        key: "state".to_string(),
+                        family: None,
+                        dir: None,
+                    })),
+                    right: expr::Expression::String("invalid".to_string()),
+                    op: stmt::Operator::IN,
+                }),
+                stmt::Statement::Drop(None),
+            ],
+        ));
+    }
+
+    // Forward chain: jump NETAVARK-ISOLATION-1
+    if get_matching_rules_in_chain(
+        existing_rules,
+        FORWARDCHAIN,
+        get_rule_matcher_jump_to(ISOLATION1CHAIN.to_string()),
+    )
+    .is_empty()
+    {
+        batch.add(make_rule(
+            FORWARDCHAIN,
+            vec![get_jump_action(ISOLATION1CHAIN)],
+        ));
+    }
+}
+
+/// Read the counters of the drop rules in the isolation chains.
+fn get_drop_counters(base_rules: &schema::Nftables) -> Vec<DropCounter> {
+    let mut counters = Vec::new();
+    for object in &base_rules.objects {
+        let schema::NfObject::ListObject(obj) = object else {
+            continue;
+        };
+        let schema::NfListObject::Rule(rule) = obj.as_ref() else {
+            continue;
+        };
+        if rule.chain != ISOLATION2CHAIN && rule.chain != ISOLATION3CHAIN {
+            continue;
+        }
+        if !rule
+            .expr
+            .iter()
+            .any(|s| matches!(s, stmt::Statement::Drop(_)))
+        {
+            continue;
+        }
+        let Some(bridge) = get_rule_meta_match(rule, expr::MetaKey::Oifname) else {
+            continue;
+        };
+        for statement in &rule.expr {
+            if let stmt::Statement::Counter(stmt::Counter::Anonymous(Some(c))) = statement {
+                counters.push(DropCounter {
+                    bridge: bridge.to_string(),
+                    packets: c.packets.unwrap_or_default() as u64,
+                    bytes: c.bytes.unwrap_or_default() as u64,
+                });
+            }
+        }
+    }
+    counters
+}
+
+/// Convert the rules of the netavark table into the backend neutral dump.
+fn dump_rules(base_rules: &schema::Nftables, dump: &mut FirewallDump) {
+    let mut chains: HashMap<&str, Vec<&schema::Rule>> = HashMap::new();
+    for object in &base_rules.objects {
+        if let schema::NfObject::ListObject(obj) = object {
+            if let schema::NfListObject::Rule(r) = obj.as_ref() {
+                chains.entry(r.chain.as_str()).or_default().push(r);
+            }
+        }
+    }
+
+    for rule in chains.get(POSTROUTINGCHAIN).into_iter().flatten() {
+        // Postrouting chain: ip saddr <subnet> jump <chain>
+        let (Some(subnet), Some(target)) = (get_rule_prefix(rule), get_rule_jump_target(rule))
+        else {
+            continue;
+        };
+        dump.networks.push(subnet);
+        let masquerade = chains.get(target).into_iter().flatten().any(|r| {
+            r.expr
+                .iter()
+                .any(|s| matches!(s, stmt::Statement::Masquerade(_) | stmt::Statement::SNAT(_)))
+        });
+        if masquerade {
+            dump.masquerade.push(subnet);
+        }
+    }
+
+    for rule in chains.get(ISOLATION1CHAIN).into_iter().flatten() {
+        // NETAVARK-ISOLATION-1: iifname <bridgename> oifname != <bridgename> jump NETAVARK-ISOLATION-{2,3}
+        let isolation = match get_rule_jump_target(rule) {
+            Some(ISOLATION2CHAIN) => IsolateOption::Normal,
+            Some(ISOLATION3CHAIN) => IsolateOption::Strict,
+            // NETAVARK-ISOLATION-1: iifname <bridgename> jump nv_iso_<group>
+            // nv_iso_<group>: oifname <bridgename> accept
+            Some(target) if target.starts_with(ISOLATION_GROUP_CHAIN_PREFIX) => {
+                let Some(bridge) = get_rule_meta_match(rule, expr::MetaKey::Iifname) else {
+                    continue;
+                };
+                let member = chains.get(target).into_iter().flatten().any(|r| {
+                    get_rule_meta_match(r, expr::MetaKey::Oifname) == Some(bridge)
+                        && r.expr
+                            .iter()
+                            .any(|s| matches!(s, stmt::Statement::Accept(_)))
+                });
+                if member {
+                    dump.isolation_groups.push(IsolationGroup {
+                        bridge: bridge.to_string(),
+                        group: target[ISOLATION_GROUP_CHAIN_PREFIX.len()..].to_string(),
+                    });
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(bridge) = get_rule_meta_match(rule, expr::MetaKey::Iifname) {
+            dump.isolation.push(Isolation {
+                bridge: bridge.to_string(),
+                isolation,
+            });
+        }
+    }
+    for rule in chains.get(ISOLATION3CHAIN).into_iter().flatten() {
+        // NETAVARK-ISOLATION-3: oifname == <bridgename> drop
+        if !rule
+            .expr
+            .iter()
+            .any(|s| matches!(s, stmt::Statement::Drop(_)))
+        {
+            continue;
+        }
+        if let Some(bridge) = get_rule_meta_match(rule, expr::MetaKey::Oifname) {
+            dump.isolation.push(Isolation {
+                bridge: bridge.to_string(),
+                isolation: IsolateOption::Never,
+            });
+        }
+    }
+
+    for (chain, rules) in chains {
+        if !chain.starts_with("nv_") || !chain.ends_with("_dnat") {
+            continue;
+        }
+        for rule in rules {
+            if let Some(mut pfs) = get_rule_port_forwards(rule) {
+                dump.port_forwards.append(&mut pfs);
+            }
+        }
+    }
+}
+
+/// Get the subnet of the first prefix match in the rule.
+fn get_rule_prefix(rule: &schema::Rule) -> Option<IpNet> {
+    rule.expr.iter().find_map(|s| match s {
+        stmt::Statement::Match(stmt::Match {
+            right: expr::Expression::Named(expr::NamedExpression::Prefix(p)),
+            op: stmt::Operator::EQ,
+            ..
+        }) => match p.addr.as_ref() {
+            expr::Expression::String(addr) => format!("{}/{}", addr, p.len).parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Get the target chain if the rule contains a jump.
+fn get_rule_jump_target(rule: &schema::Rule) -> Option<&str> {
+    rule.expr.iter().find_map(|s| match s {
+        stmt::Statement::Jump(j) => Some(j.target.as_str()),
+        _ => None,
+    })
+}
+
+/// Get the value of a positive match against the given meta key.
+fn get_rule_meta_match(rule: &schema::Rule, key: expr::MetaKey) -> Option<&str> {
+    rule.expr.iter().find_map(|s| match s {
+        stmt::Statement::Match(stmt::Match {
+            left: expr::Expression::Named(expr::NamedExpression::Meta(m)),
+            right: expr::Expression::String(value),
+            op: stmt::Operator::EQ,
+        }) if m.key == key => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// Convert a container dnat rule into port forwards, a rule matching a dport range
+/// forwards all of them to the same container port.
+/// Container dnat chain: ip daddr <host IP> <proto> dport <port(s)> dnat to <container ip: container port>
+fn get_rule_port_forwards(rule: &schema::Rule) -> Option<Vec<PortForward>> {
+    let mut protocol = None;
+    let mut host_ports = None;
+    let mut host_ip = None;
+    let mut dest = None;
+    for statement in &rule.expr {
+        match statement {
+            stmt::Statement::Match(stmt::Match {
+                left:
+                    expr::Expression::Named(expr::NamedExpression::Payload(
+                        expr::Payload::PayloadField(field),
+                    )),
+                right,
+                op: stmt::Operator::EQ,
+            }) => match (field.field.as_str(), right) {
+                ("dport", expr::Expression::Number(port)) => {
+                    protocol = Some(field.protocol.clone());
+                    let port = u16::try_from(*port).ok()?;
+                    host_ports = Some((port, port));
+                }
+                ("dport", expr::Expression::Range(range)) => {
+                    protocol = Some(field.protocol.clone());
+                    host_ports = match range.range.as_slice() {
+                        [expr::Expression::Number(start), expr::Expression::Number(end)] => {
+                            Some((u16::try_from(*start).ok()?, u16::try_from(*end).ok()?))
+                        }
+                        _ => None,
+                    };
+                }
+                ("daddr", expr::Expression::String(ip)) => host_ip = ip.parse().ok(),
+                _ => {}
+            },
+            stmt::Statement::DNAT(Some(stmt::NAT {
+                addr: Some(expr::Expression::String(addr)),
+                port: Some(port),
+                ..
+            })) => dest = Some((addr.parse().ok()?, u16::try_from(*port).ok()?)),
+            _ => {}
+        }
+    }
+    let (dest_ip, dest_port) = dest?;
+    let protocol = protocol?;
+    let (host_start, host_end) = host_ports?;
+    Some(
+        (host_start..=host_end)
+            .map(|host_port| PortForward {
+                protocol: protocol.clone(),
+                host_ip,
+                host_port,
+                dest_ip,
+                dest_port,
+            })
+            .collect(),
+    )
 }
 
 // compare two rules, we only check the chain name and expr,
@@ -770,6 +1083,30 @@ fn delete_port_rules(
     Ok(())
 }
 
+/// Convert an isolation group into a chain name.
+fn get_isolation_group_chain_name(group: &str) -> String {
+    format!("{ISOLATION_GROUP_CHAIN_PREFIX}{group}")
+}
+
+/// Get all isolation group chains in the netavark table.
+fn get_isolation_group_chains(base_rules: &schema::Nftables) -> Vec<schema::Chain> {
+    base_rules
+        .objects
+        .iter()
+        .filter_map(|object| match object {
+            schema::NfObject::ListObject(obj) => match obj.as_ref() {
+                schema::NfListObject::Chain(c)
+                    if c.name.starts_with(ISOLATION_GROUP_CHAIN_PREFIX) =>
+                {
+                    Some(c.clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
 /// Convert a subnet into a chain name.
 fn get_subnet_chain_name(subnet: IpNet, net_id: &str, dnat: bool) -> String {
     // nftables is very lenient around chain name lengths.
@@ -805,6 +1142,72 @@ fn get_dest_bridge_match(bridge: &str) -> stmt::Statement {
     })
 }
 
+/// Get a statement to match the given source bridge.
+/// Always matches using ==.
+fn get_src_bridge_match(bridge: &str) -> stmt::Statement {
+    stmt::Statement::Match(stmt::Match {
+        left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+            key: expr::MetaKey::Iifname,
+        })),
+        right: expr::Expression::String(bridge.to_string()),
+        op: stmt::Operator::EQ,
+    })
+}
+
+/// Get the rules to drop the traffic to the given bridge in the isolation chains.
+/// The drop rule has a counter, with logging enabled a rate limited log rule comes first.
+fn get_isolation_drop_rules(
+    bridge: &str,
+    log_dropped: Option<DropLog>,
+) -> Vec<Vec<stmt::Statement>> {
+    let drop_rule = vec![
+        get_dest_bridge_match(bridge),
+        stmt::Statement::Counter(stmt::Counter::Anonymous(None)),
+        stmt::Statement::Drop(None),
+    ];
+    let Some(log_dropped) = log_dropped else {
+        return vec![drop_rule];
+    };
+    let mut log = stmt::Log::new(match log_dropped {
+        DropLog::Log => None,
+        DropLog::Nflog => Some(0),
+    });
+    log.prefix = Some(firewall::drop_log_prefix(bridge));
+    vec![
+        vec![
+            get_dest_bridge_match(bridge),
+            stmt::Statement::Limit(stmt::Limit {
+                rate: firewall::DROP_LOG_RATE,
+                rate_unit: None,
+                per: Some("minute".to_string()),
+                burst: None,
+                burst_unit: None,
+                inv: None,
+            }),
+            stmt::Statement::Log(Some(log)),
+        ],
+        drop_rule,
+    ]
+}
+
+/// Get the statement to masquerade the traffic leaving the host, or to
+/// translate it to the given fixed source address.
+fn get_nat_action(snat_address: Option<IpAddr>) -> stmt::Statement {
+    let Some(addr) = snat_address else {
+        return stmt::Statement::Masquerade(None);
+    };
+    let family = match addr {
+        IpAddr::V4(_) => stmt::NATFamily::IP,
+        IpAddr::V6(_) => stmt::NATFamily::IP6,
+    };
+    stmt::Statement::SNAT(Some(stmt::NAT {
+        addr: Some(expr::Expression::String(addr.to_string())),
+        family: Some(family),
+        port: None,
+        flags: None,
+    }))
+}
+
 /// Get a statement to match the given IP address.
 /// Field should be either "saddr" or "daddr" for matching source or destination.
 fn get_ip_match(ip: &IpAddr, field: &str, op: stmt::Operator) -> stmt::Statement {
@@ -918,12 +1321,25 @@ fn get_dnat_port_rules(
 ) -> Vec<schema::NfListObject> {
     let mut rules: Vec<schema::NfListObject> = Vec::new();
 
+    // A host port range forwarded to a single container port only needs one rule
+    // Container dnat chain: ip daddr <host IP> <proto> dport <ports> dnat to <container ip: container port>
+    if port.is_many_to_one() {
+        let mut statements: Vec<stmt::Statement> = Vec::new();
+        if let Some(stmt) = host_ip_cond {
+            statements.push(stmt.clone());
+        }
+        statements.push(get_dport_cond(port));
+        statements.push(get_dnat_action(ip, port.container_port as u32));
+        rules.push(make_rule(dnat_chain, statements));
+        return rules;
+    }
+
     // Container dnat chain: ip daddr <host IP> <proto> dport <port> dnat to <container ip: container port>
     // Unfortunately: We don't have range support in the schema. So we need 1 rule per port.
     let range = if port.range == 0 { 1 } else { port.range };
     for i in 0..range {
         let host_port: u32 = (port.host_port + i) as u32;
-        let ctr_port: u32 = (port.container_port + i) as u32;
+        let ctr_port: u32 = port.container_port_for(i) as u32;
 
         let mut statements: Vec<stmt::Statement> = Vec::new();
         if let Some(stmt) = host_ip_cond {
@@ -939,22 +1355,27 @@ fn get_dnat_port_rules(
             right: expr::Expression::Number(host_port),
             op: stmt::Operator::EQ,
         }));
-        statements.push(stmt::Statement::DNAT(Some(stmt::NAT {
-            addr: Some(expr::Expression::String(ip.to_string())),
-            family: Some(if ip.is_ipv6() {
-                stmt::NATFamily::IP6
-            } else {
-                stmt::NATFamily::IP
-            }),
-            port: Some(ctr_port),
-            flags: None,
-        })));
+        statements.push(get_dnat_action(ip, ctr_port));
         rules.push(make_rule(dnat_chain, statements));
     }
 
     rules
 }
 
+/// DNAT to the given container ip and port.
+fn get_dnat_action(ip: &IpAddr, port: u32) -> stmt::Statement {
+    stmt::Statement::DNAT(Some(stmt::NAT {
+        addr: Some(expr::Expression::String(ip.to_string())),
+        family: Some(if ip.is_ipv6() {
+            stmt::NATFamily::IP6
+        } else {
+            stmt::NATFamily::IP
+        }),
+        port: Some(port),
+        flags: None,
+    }))
+}
+
 fn get_dnat_rules_for_addr_family(
     ip: IpAddr,
     subnet: IpNet,
@@ -1021,15 +1442,17 @@ fn get_dnat_rules_for_addr_family(
             ));
 
             // Container dnat chain: ip saddr <subnet> ip daddr <host IP> <proto> dport <port(s)> jump SETMARKCHAIN
-            rules.push(get_subnet_dport_match(
-                &subnet_dnat_chain,
-                &Some(subnet),
-                &daddr_cond,
-                &dport_cond,
-            ));
+            if setup_portfw.hairpin_masquerade {
+                rules.push(get_subnet_dport_match(
+                    &subnet_dnat_chain,
+                    &Some(subnet),
+                    &daddr_cond,
+                    &dport_cond,
+                ));
+            }
 
             // This rule is only used for v4.
-            if ip.is_ipv4() {
+            if ip.is_ipv4() && setup_portfw.localhost_forwarding {
                 // Container dnat chain: ip saddr 127.0.0.1 ip daddr <host IP> <proto> dport <port(s)> jump SETMARKCHAIN
                 let mut localhost_jump_statements: Vec<stmt::Statement> = Vec::new();
                 localhost_jump_statements.push(get_ip_match(
@@ -1045,11 +1468,28 @@ fn get_dnat_rules_for_addr_family(
                 rules.push(make_rule(&subnet_dnat_chain, localhost_jump_statements));
             }
 
+            // Without localhost forwarding connections to the loopback address are not translated:
+            // Container dnat chain: ip daddr != 127.0.0.0/8 <proto> dport <port> dnat to <container ip: container port>
+            let dnat_daddr_cond = match &daddr_cond {
+                None if !setup_portfw.localhost_forwarding => Some(match ip {
+                    IpAddr::V4(_) => get_subnet_match(
+                        &IpNet::new(IPV4_LOCALHOST, 8)?.trunc(),
+                        "daddr",
+                        stmt::Operator::NEQ,
+                    ),
+                    IpAddr::V6(_) => get_ip_match(
+                        &IpAddr::V6(Ipv6Addr::LOCALHOST),
+                        "daddr",
+                        stmt::Operator::NEQ,
+                    ),
+                }),
+                cond => cond.clone(),
+            };
             rules.append(&mut get_dnat_port_rules(
                 &subnet_dnat_chain,
                 port,
                 &ip,
-                &daddr_cond,
+                &dnat_daddr_cond,
             ));
         }
     }
@@ -1267,3 +1707,373 @@ fn get_netavark_rules() -> Result<schema::Nftables, helper::NftablesError> {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_ruleset(rules: Vec<schema::NfListObject>) -> schema::Nftables {
+        schema::Nftables {
+            objects: rules
+                .into_iter()
+                .map(|r| schema::NfObject::ListObject(Box::new(r)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_dump_rules() {
+        let subnet: IpNet = "10.88.0.0/16".parse().unwrap();
+        let chain = get_subnet_chain_name(subnet, "abcdefghij", false);
+        let dnat_chain = get_subnet_chain_name(subnet, "abcdefghij", true);
+        let container_ip: IpAddr = "10.88.0.2".parse().unwrap();
+        let host_ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let port = PortMapping {
+            container_port: 80,
+            host_ip: host_ip.to_string(),
+            host_port: 8080,
+            protocol: "tcp".to_string(),
+            range: 2,
+            container_range: None,
+        };
+
+        let mut rules = vec![
+            make_rule(
+                POSTROUTINGCHAIN,
+                vec![
+                    get_subnet_match(&subnet, "saddr", stmt::Operator::EQ),
+                    get_jump_action(&chain),
+                ],
+            ),
+            make_rule(
+                &chain,
+                vec![
+                    get_subnet_match(
+                        &"224.0.0.0/4".parse().unwrap(),
+                        "daddr",
+                        stmt::Operator::NEQ,
+                    ),
+                    stmt::Statement::Masquerade(None),
+                ],
+            ),
+            make_rule(
+                ISOLATION3CHAIN,
+                vec![
+                    get_dest_bridge_match("podman0"),
+                    stmt::Statement::Drop(None),
+                ],
+            ),
+            make_rule(ISOLATION3CHAIN, vec![get_jump_action(ISOLATION2CHAIN)]),
+            make_rule(
+                ISOLATION1CHAIN,
+                vec![
+                    stmt::Statement::Match(stmt::Match {
+                        left: expr::Expression::Named(expr::NamedExpression::Meta(expr::Meta {
+                            key: expr::MetaKey::Iifname,
+                        })),
+                        right: expr::Expression::String("podman1".to_string()),
+                        op: stmt::Operator::EQ,
+                    }),
+                    get_jump_action(ISOLATION3CHAIN),
+                ],
+            ),
+            get_subnet_dport_match(&dnat_chain, &Some(subnet), &None, &get_dport_cond(&port)),
+        ];
+        rules.append(&mut get_dnat_port_rules(
+            &dnat_chain,
+            &port,
+            &container_ip,
+            &Some(get_ip_match(&host_ip, "daddr", stmt::Operator::EQ)),
+        ));
+
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&to_ruleset(rules), &mut dump);
+        dump.normalize();
+
+        assert_eq!(dump.networks, vec![subnet]);
+        assert_eq!(dump.masquerade, vec![subnet]);
+        assert_eq!(
+            dump.isolation,
+            vec![
+                Isolation {
+                    bridge: "podman0".to_string(),
+                    isolation: IsolateOption::Never,
+                },
+                Isolation {
+                    bridge: "podman1".to_string(),
+                    isolation: IsolateOption::Strict,
+                }
+            ]
+        );
+        assert_eq!(
+            dump.port_forwards,
+            vec![
+                PortForward {
+                    protocol: "tcp".to_string(),
+                    host_ip: Some(host_ip),
+                    host_port: 8080,
+                    dest_ip: container_ip,
+                    dest_port: 80,
+                },
+                PortForward {
+                    protocol: "tcp".to_string(),
+                    host_ip: Some(host_ip),
+                    host_port: 8081,
+                    dest_ip: container_ip,
+                    dest_port: 81,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_many_to_one_port_rules() {
+        let container_ip: IpAddr = "10.88.0.2".parse().unwrap();
+        let port = PortMapping {
+            container_port: 80,
+            host_ip: String::new(),
+            host_port: 8080,
+            protocol: "udp".to_string(),
+            range: 3,
+            container_range: Some(1),
+        };
+
+        let chain = get_subnet_chain_name("10.88.0.0/16".parse().unwrap(), "abcdefghij", true);
+        let rules = get_dnat_port_rules(&chain, &port, &container_ip, &None);
+        assert_eq!(rules.len(), 1, "one rule for the whole host port range");
+
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&to_ruleset(rules), &mut dump);
+        assert_eq!(
+            dump.port_forwards,
+            (8080..=8082)
+                .map(|host_port| PortForward {
+                    protocol: "udp".to_string(),
+                    host_ip: None,
+                    host_port,
+                    dest_ip: container_ip,
+                    dest_port: 80,
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_drop_counters() {
+        let mut rules: Vec<schema::NfListObject> =
+            get_isolation_drop_rules("podman1", Some(DropLog::Log))
+                .into_iter()
+                .map(|r| make_rule(ISOLATION3CHAIN, r))
+                .collect();
+        assert_eq!(rules.len(), 2, "log and drop rule");
+        rules.push(make_rule(
+            ISOLATION2CHAIN,
+            get_isolation_drop_rules("podman0", None).remove(0),
+        ));
+        // counters are only filled in when listing the ruleset
+        for rule in &mut rules {
+            if let schema::NfListObject::Rule(r) = rule {
+                for s in r.expr.iter_mut() {
+                    if let stmt::Statement::Counter(c) = s {
+                        *c = stmt::Counter::Anonymous(Some(stmt::AnonymousCounter {
+                            packets: Some(3),
+                            bytes: Some(252),
+                        }));
+                    }
+                }
+            }
+        }
+
+        let ruleset = to_ruleset(rules);
+        assert_eq!(
+            get_drop_counters(&ruleset),
+            vec![
+                DropCounter {
+                    bridge: "podman1".to_string(),
+                    packets: 3,
+                    bytes: 252,
+                },
+                DropCounter {
+                    bridge: "podman0".to_string(),
+                    packets: 3,
+                    bytes: 252,
+                }
+            ]
+        );
+
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&ruleset, &mut dump);
+        assert_eq!(
+            dump.isolation,
+            vec![Isolation {
+                bridge: "podman1".to_string(),
+                isolation: IsolateOption::Never,
+            }],
+            "log rule is not an isolation rule"
+        );
+    }
+
+    #[test]
+    fn test_snat_rules() {
+        assert_eq!(get_nat_action(None), stmt::Statement::Masquerade(None));
+        let addr: IpAddr = "fd00::1".parse().unwrap();
+        match get_nat_action(Some(addr)) {
+            stmt::Statement::SNAT(Some(nat)) => {
+                assert_eq!(nat.addr, Some(expr::Expression::String(addr.to_string())));
+                assert_eq!(nat.family, Some(stmt::NATFamily::IP6));
+            }
+            s => panic!("expected snat statement, got {s:?}"),
+        }
+
+        // snat counts as masquerade in the dump
+        let subnet: IpNet = "10.88.0.0/16".parse().unwrap();
+        let chain = get_subnet_chain_name(subnet, "abcdefghij", false);
+        let rules = vec![
+            make_rule(
+                POSTROUTINGCHAIN,
+                vec![
+                    get_subnet_match(&subnet, "saddr", stmt::Operator::EQ),
+                    get_jump_action(&chain),
+                ],
+            ),
+            make_rule(
+                &chain,
+                vec![get_nat_action(Some("192.168.1.10".parse().unwrap()))],
+            ),
+        ];
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&to_ruleset(rules), &mut dump);
+        assert_eq!(dump.masquerade, vec![subnet]);
+    }
+
+    #[test]
+    fn test_port_forward_options() {
+        let subnet: IpNet = "10.88.0.0/16".parse().unwrap();
+        let container_ip: IpAddr = "10.88.0.2".parse().unwrap();
+        let ports = Some(vec![PortMapping {
+            container_port: 80,
+            host_ip: String::new(),
+            host_port: 8080,
+            protocol: "tcp".to_string(),
+            range: 1,
+            container_range: None,
+        }]);
+        let dns_server_ips = vec![];
+        let mut pfwd = internal_types::PortForwardConfig {
+            container_id: "123".to_string(),
+            network_id: "abcdefghij".to_string(),
+            port_mappings: &ports,
+            network_name: "name".to_string(),
+            network_hash_name: "hash".to_string(),
+            container_ip_v4: Some(container_ip),
+            subnet_v4: Some(subnet),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 53,
+            dns_server_ips: &dns_server_ips,
+            localhost_forwarding: true,
+            hairpin_masquerade: true,
+        };
+        let existing = schema::Nftables { objects: vec![] };
+        let count_mark_jumps = |rules: &[schema::NfListObject]| {
+            rules
+                .iter()
+                .filter(|r| match r {
+                    schema::NfListObject::Rule(r) => get_rule_jump_target(r) == Some(MASKCHAIN),
+                    _ => false,
+                })
+                .count()
+        };
+
+        let rules =
+            get_dnat_rules_for_addr_family(container_ip, subnet, "abcdefghij", &existing, &pfwd)
+                .unwrap();
+        // hairpin and localhost mark rules
+        assert_eq!(count_mark_jumps(&rules), 2);
+
+        pfwd.localhost_forwarding = false;
+        pfwd.hairpin_masquerade = false;
+        let rules =
+            get_dnat_rules_for_addr_family(container_ip, subnet, "abcdefghij", &existing, &pfwd)
+                .unwrap();
+        assert_eq!(count_mark_jumps(&rules), 0);
+        let localhost_cond = get_subnet_match(
+            &"127.0.0.0/8".parse().unwrap(),
+            "daddr",
+            stmt::Operator::NEQ,
+        );
+        let dnat_rule = rules
+            .iter()
+            .find_map(|r| match r {
+                schema::NfListObject::Rule(r)
+                    if r.expr.iter().any(|s| matches!(s, stmt::Statement::DNAT(_))) =>
+                {
+                    Some(r)
+                }
+                _ => None,
+            })
+            .expect("dnat rule");
+        assert!(
+            dnat_rule.expr.contains(&localhost_cond),
+            "localhost must be excluded from dnat: {dnat_rule:?}"
+        );
+    }
+
+    #[test]
+    fn test_isolation_group_chains() {
+        let group_chain = get_isolation_group_chain_name("frontend");
+        assert_eq!(group_chain, "nv_iso_frontend");
+        let ruleset = to_ruleset(vec![
+            make_basic_chain(&group_chain),
+            make_basic_chain(&get_subnet_chain_name(
+                "10.88.0.0/16".parse().unwrap(),
+                "abcdefghij",
+                false,
+            )),
+            make_basic_chain(ISOLATION1CHAIN),
+        ]);
+        let chains: Vec<String> = get_isolation_group_chains(&ruleset)
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(chains, vec![group_chain.clone()]);
+
+        // podman1 has lost its accept rule in the group chain
+        let ruleset = to_ruleset(vec![
+            make_rule(
+                ISOLATION1CHAIN,
+                vec![
+                    get_src_bridge_match("podman0"),
+                    get_jump_action(&group_chain),
+                ],
+            ),
+            make_rule(
+                ISOLATION1CHAIN,
+                vec![
+                    get_src_bridge_match("podman1"),
+                    get_jump_action(&group_chain),
+                ],
+            ),
+            make_rule(
+                &group_chain,
+                vec![
+                    get_dest_bridge_match("podman0"),
+                    stmt::Statement::Accept(None),
+                ],
+            ),
+        ]);
+        let mut dump = FirewallDump::new(firewall::NFTABLES);
+        dump_rules(&ruleset, &mut dump);
+        assert_eq!(
+            dump.isolation_groups,
+            vec![IsolationGroup {
+                bridge: "podman0".to_string(),
+                group: "frontend".to_string(),
+            }]
+        );
+        assert!(
+            dump.isolation.is_empty(),
+            "group jumps are no isolation mode"
+        );
+    }
+}
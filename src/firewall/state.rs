@@ -1,7 +1,9 @@
 use std::{
     fs::{self, File, OpenOptions},
     io::{self, ErrorKind, Write},
+    net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use fs2::FileExt;
@@ -9,7 +11,10 @@ use serde::de::DeserializeOwned;
 
 use crate::{
     error::{NetavarkError, NetavarkResult},
-    network::internal_types::{PortForwardConfig, PortForwardConfigOwned, SetupNetwork},
+    network::{
+        internal_types::{PortForwardConfig, PortForwardConfigOwned, SetupNetwork},
+        types::PortMapping,
+    },
     wrap,
 };
 
@@ -125,6 +130,10 @@ fn get_file_paths(
 /// Store the firewall configs on disk.
 /// This should be caller after firewall setup to allow the firewalld reload
 /// service to read the configs later and readd the rules.
+///
+/// Unless allow_port_conflicts is set this fails when a port mapping overlaps
+/// with the port mappings of another container, the check is done under the
+/// firewall lock so two containers cannot race for the same port.
 pub fn write_fw_config(
     config_dir: &Path,
     network_id: &str,
@@ -132,15 +141,16 @@ pub fn write_fw_config(
     fw_driver: &str,
     net_conf: &SetupNetwork,
     port_conf: &PortForwardConfig,
+    allow_port_conflicts: bool,
 ) -> NetavarkResult<()> {
     let paths = get_file_paths(config_dir, network_id, container_id, true)?;
-    fs_err!(
-        File::create,
-        &paths.fw_driver_file,
-        "create firewall-driver file"
-    )?
-    .write_all(fw_driver.as_bytes())
-    .map_err(|err| NetavarkError::wrap("failed to write firewall-driver file", err.into()))?;
+    write_port_conf(
+        config_dir,
+        &paths,
+        fw_driver,
+        port_conf,
+        allow_port_conflicts,
+    )?;
 
     match OpenOptions::new()
         .write(true)
@@ -158,9 +168,46 @@ pub fn write_fw_config(
         }
     };
 
+    Ok(())
+}
+
+/// Store only the port forwarding config, used for networks where netavark
+/// does not own the network rules, e.g. plugins that requested port forwarding.
+/// Remove it again with remove_fw_config() without complete_teardown.
+pub fn write_port_fw_config(
+    config_dir: &Path,
+    network_id: &str,
+    container_id: &str,
+    fw_driver: &str,
+    port_conf: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let paths = get_file_paths(config_dir, network_id, container_id, true)?;
+    write_port_conf(config_dir, &paths, fw_driver, port_conf, false)
+}
+
+fn write_port_conf(
+    config_dir: &Path,
+    paths: &FilePaths,
+    fw_driver: &str,
+    port_conf: &PortForwardConfig,
+    allow_port_conflicts: bool,
+) -> NetavarkResult<()> {
+    if !allow_port_conflicts {
+        check_port_conflicts(
+            &firewall_config_dir(config_dir).join(PORT_CONF_DIR),
+            port_conf,
+        )?;
+    }
+    fs_err!(
+        File::create,
+        &paths.fw_driver_file,
+        "create firewall-driver file"
+    )?
+    .write_all(fw_driver.as_bytes())
+    .map_err(|err| NetavarkError::wrap("failed to write firewall-driver file", err.into()))?;
+
     let ports_file = fs_err!(File::create, &paths.port_conf_file, "create port config")?;
     serde_json::to_writer(ports_file, &port_conf)?;
-
     Ok(())
 }
 
@@ -189,6 +236,16 @@ pub fn remove_fw_config(
     Ok(())
 }
 
+/// Read the network configs without taking the firewall lock, for drivers
+/// which need the other networks while the caller may hold the lock already.
+pub fn read_net_confs(config_dir: &Path) -> NetavarkResult<Vec<SetupNetwork>> {
+    let dir = firewall_config_dir(config_dir).join(NETWORK_CONF_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    read_dir_conf(dir)
+}
+
 pub struct FirewallConfig {
     /// Name of the firewall driver
     pub driver: String,
@@ -197,6 +254,7 @@ pub struct FirewallConfig {
     /// All port forwarding configs
     pub port_confs: Vec<PortForwardConfigOwned>,
 
+    fw_driver_file: PathBuf,
     /// Lock file for the firewall code to prevent us from adding rules while the state files
     /// have been removed in the meantime.
     /// We never do anything with it but we need to keep it open as closing it closes the lock
@@ -223,10 +281,227 @@ pub fn read_fw_config(config_dir: &Path) -> NetavarkResult<Option<FirewallConfig
         driver,
         net_confs,
         port_confs,
+        fw_driver_file: paths.fw_driver_file,
         lock_file: paths.lock_file,
     }))
 }
 
+impl FirewallConfig {
+    /// Record a new firewall driver for the existing configs, used when the
+    /// rules were migrated to another driver. The lock is still held here.
+    pub fn set_driver(&mut self, driver: &str) -> NetavarkResult<()> {
+        fs_err!(
+            File::create,
+            &self.fw_driver_file,
+            "create firewall-driver file"
+        )?
+        .write_all(driver.as_bytes())
+        .map_err(|err| NetavarkError::wrap("failed to write firewall-driver file", err.into()))?;
+        self.driver = driver.to_string();
+        Ok(())
+    }
+}
+
+/// Read the firewall driver which was used to create the rules of the
+/// running containers. Returns None if there are no networks set up.
+pub fn read_fw_driver(config_dir: &Path) -> NetavarkResult<Option<String>> {
+    let path = firewall_config_dir(config_dir);
+    let has_networks = wrap!(
+        ignore_enoent!(fs::read_dir(path.join(NETWORK_CONF_DIR)), return Ok(None)),
+        "read network config dir"
+    )?
+    .next()
+    .is_some();
+    if !has_networks {
+        return Ok(None);
+    }
+    let driver_file = path.join(FIREWALL_DRIVER_FILE);
+    let driver = wrap!(
+        ignore_enoent!(fs::read_to_string(&driver_file), return Ok(None)),
+        format!("read firewall-driver {:?}", driver_file.display())
+    )?;
+    Ok(Some(driver))
+}
+
+/// Number of ports we try before giving up on allocating a host port.
+const HOST_PORT_ALLOC_ATTEMPTS: usize = 64;
+
+/// Replace host_port 0 in the port mappings with a free host port.
+/// A port is considered free when it can be bound on the host for all
+/// protocols of the mapping and no other container maps it already.
+/// Two containers can still race for the same port between allocation and
+/// write_fw_config(), the port conflict check there catches this.
+/// Returns true when at least one host port was allocated.
+pub fn allocate_host_ports(config_dir: &Path, ports: &mut [PortMapping]) -> NetavarkResult<bool> {
+    if !ports.iter().any(|p| p.host_port == 0) {
+        return Ok(false);
+    }
+    let paths = get_file_paths(config_dir, "", "", false)?;
+    let confs: Vec<PortForwardConfigOwned> = match paths.port_conf_file.exists() {
+        true => read_dir_conf(paths.port_conf_file)?,
+        false => Vec::new(),
+    };
+    let mut used: Vec<PortMapping> = confs
+        .into_iter()
+        .flat_map(|conf| conf.port_mappings.unwrap_or_default())
+        .collect();
+    used.extend(ports.iter().filter(|p| p.host_port != 0).cloned());
+
+    for port in ports.iter_mut().filter(|p| p.host_port == 0) {
+        let host_port = (0..HOST_PORT_ALLOC_ATTEMPTS)
+            .filter_map(|_| ephemeral_host_port(port))
+            .find(|&host_port| {
+                let candidate = PortMapping {
+                    host_port,
+                    ..port.clone()
+                };
+                host_port_bindable(&candidate)
+                    && !used.iter().any(|u| port_mappings_overlap(&candidate, u))
+            })
+            .ok_or_else(|| {
+                NetavarkError::msg(format!(
+                    "failed to allocate a free host port for container port {}/{}",
+                    port.container_port,
+                    protocols(port).collect::<Vec<_>>().join(",")
+                ))
+            })?;
+        port.host_port = host_port;
+        used.push(port.clone());
+    }
+    Ok(true)
+}
+
+/// The address used to bind test sockets for the host ip of the port mapping.
+fn bind_addr(port: &PortMapping) -> IpAddr {
+    IpAddr::from_str(&port.host_ip).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// Let the kernel pick an unused port from the ephemeral port range.
+fn ephemeral_host_port(port: &PortMapping) -> Option<u16> {
+    let addr = (bind_addr(port), 0);
+    let local = match protocols(port).next() {
+        Some("udp") => UdpSocket::bind(addr).and_then(|s| s.local_addr()),
+        _ => TcpListener::bind(addr).and_then(|s| s.local_addr()),
+    };
+    local.ok().map(|a| a.port())
+}
+
+/// Check that nothing else is bound to the host port, sctp cannot be checked
+/// without a socket type in std so it is skipped.
+fn host_port_bindable(port: &PortMapping) -> bool {
+    let addr = (bind_addr(port), port.host_port);
+    protocols(port).all(|proto| match proto {
+        "tcp" => TcpListener::bind(addr).is_ok(),
+        "udp" => UdpSocket::bind(addr).is_ok(),
+        _ => true,
+    })
+}
+
+/// Read the port mappings stored for the container on the given network.
+/// Returns None when there is no port config for them.
+pub fn read_port_mappings(
+    config_dir: &Path,
+    network_id: &str,
+    container_id: &str,
+) -> NetavarkResult<Option<Vec<PortMapping>>> {
+    let paths = get_file_paths(config_dir, network_id, container_id, false)?;
+    let content = wrap!(
+        ignore_enoent!(fs::read_to_string(&paths.port_conf_file), return Ok(None)),
+        format!("read port config {:?}", paths.port_conf_file.display())
+    )?;
+    let conf: PortForwardConfigOwned = serde_json::from_str(&content)?;
+    Ok(conf.port_mappings)
+}
+
+/// Check that the port mappings do not overlap with the port mappings of
+/// other containers stored in the port config dir.
+fn check_port_conflicts(port_conf_dir: &Path, port_conf: &PortForwardConfig) -> NetavarkResult<()> {
+    let ports = match port_conf.port_mappings {
+        Some(ports) if !ports.is_empty() => ports,
+        _ => return Ok(()),
+    };
+    let confs: Vec<PortForwardConfigOwned> = read_dir_conf(port_conf_dir.to_path_buf())?;
+    for conf in confs {
+        // the same container can be attached to several networks with the same ports
+        if conf.container_id == port_conf.container_id {
+            continue;
+        }
+        for existing in conf.port_mappings.iter().flatten() {
+            for port in ports {
+                if port_mappings_overlap(port, existing) {
+                    return Err(NetavarkError::msg(format!(
+                        "port mapping {} conflicts with container {} on network {} which already uses {}",
+                        format_port_mapping(port),
+                        conf.container_id,
+                        conf.network_name,
+                        format_port_mapping(existing)
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if both port mappings bind at least one common
+/// (host_ip, protocol, host_port) tuple.
+fn port_mappings_overlap(a: &PortMapping, b: &PortMapping) -> bool {
+    let a_end = u32::from(a.host_port) + u32::from(a.range.max(1));
+    let b_end = u32::from(b.host_port) + u32::from(b.range.max(1));
+    if u32::from(a.host_port) >= b_end || u32::from(b.host_port) >= a_end {
+        return false;
+    }
+    if !protocols(a).any(|p| protocols(b).any(|o| o == p)) {
+        return false;
+    }
+    host_ips_overlap(&a.host_ip, &b.host_ip)
+}
+
+fn protocols(port: &PortMapping) -> impl Iterator<Item = &str> {
+    port.protocol.split(',').map(|p| match p.trim() {
+        "" => "tcp",
+        p => p,
+    })
+}
+
+/// An empty host ip binds on all addresses of both families,
+/// an unspecified address binds on all addresses of its family.
+fn host_ips_overlap(a: &str, b: &str) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return true;
+    }
+    match (IpAddr::from_str(a), IpAddr::from_str(b)) {
+        (Ok(a), Ok(b)) => {
+            a.is_ipv4() == b.is_ipv4() && (a == b || a.is_unspecified() || b.is_unspecified())
+        }
+        // do not guess on invalid addresses, the firewall driver will complain about them
+        _ => a == b,
+    }
+}
+
+fn format_port_mapping(port: &PortMapping) -> String {
+    let host_ip = if port.host_ip.is_empty() {
+        "*"
+    } else {
+        &port.host_ip
+    };
+    let host_port = if port.range > 1 {
+        format!(
+            "{}-{}",
+            port.host_port,
+            u32::from(port.host_port) + u32::from(port.range) - 1
+        )
+    } else {
+        port.host_port.to_string()
+    };
+    let protocol = if port.protocol.is_empty() {
+        "tcp"
+    } else {
+        &port.protocol
+    };
+    format!("{host_ip}:{host_port}/{protocol}")
+}
+
 fn read_dir_conf<T: DeserializeOwned>(dir: PathBuf) -> NetavarkResult<Vec<T>> {
     let mut confs = Vec::new();
     for entry in fs_err!(fs::read_dir, &dir, "read dir")? {
@@ -271,7 +546,13 @@ mod tests {
             bridge_name: "bridge".to_string(),
             network_hash_name: "hash".to_string(),
             isolation: IsolateOption::Never,
+            isolation_groups: vec![],
             dns_port: 53,
+            log_dropped: None,
+            snat_ipv4: None,
+            snat_ipv6: None,
+            snat_exclude: vec![],
+            hairpin_masquerade: true,
         };
         let net_conf_json = r#"{"subnets":["10.0.0.0/24"],"bridge_name":"bridge","network_id":"c2c8a073252874648259997d53b0a1bffa491e21f04bc1bf8609266359931395","network_hash_name":"hash","isolation":"Never","dns_port":53}"#;
 
@@ -288,6 +569,8 @@ mod tests {
             subnet_v6: None,
             dns_port: 53,
             dns_server_ips: &vec![],
+            localhost_forwarding: true,
+            hairpin_masquerade: true,
         };
         let port_conf_json = r#"{"container_id":"123","network_id":"c2c8a073252874648259997d53b0a1bffa491e21f04bc1bf8609266359931395","port_mappings":null,"network_name":"name","network_hash_name":"hash","container_ip_v4":"10.0.0.2","subnet_v4":"10.0.0.0/24","container_ip_v6":null,"subnet_v6":null,"dns_port":53,"dns_server_ips":[]}"#;
 
@@ -298,6 +581,7 @@ mod tests {
             driver,
             &net_conf,
             &port_conf,
+            false,
         );
 
         assert!(res.is_ok(), "write_fw_config failed");
@@ -305,7 +589,7 @@ mod tests {
         let paths = get_file_paths(config_dir, network_id, container_id, false).unwrap();
         drop(paths.lock_file); // unlock to prevent deadlock with other calls
 
-        let res = fs::read_to_string(paths.fw_driver_file).unwrap();
+        let res = fs::read_to_string(&paths.fw_driver_file).unwrap();
         assert_eq!(res, "iptables", "read fw driver");
 
         let res = fs::read_to_string(&paths.net_conf_file).unwrap();
@@ -322,9 +606,16 @@ mod tests {
         let port_confs_ref: Vec<PortForwardConfig> =
             res.port_confs.iter().map(|f| f.into()).collect();
         assert_eq!(port_confs_ref, vec![port_conf], "same port configs");
+
+        let mut res = res;
+        res.set_driver("nftables").unwrap();
+        assert_eq!(res.driver, "nftables", "driver updated");
         // unlock lock file
         drop(res);
 
+        let res = fs::read_to_string(&paths.fw_driver_file).unwrap();
+        assert_eq!(res, "nftables", "read migrated fw driver");
+
         let res = remove_fw_config(config_dir, network_id, container_id, true);
         assert!(res.is_ok(), "remove_fw_config failed");
 
@@ -344,6 +635,155 @@ mod tests {
         assert!(res.is_ok(), "remove_fw_config failed second time");
     }
 
+    fn port(host_ip: &str, host_port: u16, range: u16, protocol: &str) -> PortMapping {
+        PortMapping {
+            container_port: 80,
+            host_ip: host_ip.to_string(),
+            host_port,
+            protocol: protocol.to_string(),
+            range,
+            container_range: None,
+        }
+    }
+
+    #[test]
+    fn test_port_mappings_overlap() {
+        let tests = [
+            (port("", 8080, 1, "tcp"), port("", 8080, 1, "tcp"), true),
+            (port("", 8080, 1, "tcp"), port("", 8081, 1, "tcp"), false),
+            (port("", 8080, 1, "tcp"), port("", 8080, 1, "udp"), false),
+            (port("", 8080, 1, ""), port("", 8080, 1, "udp,tcp"), true),
+            (port("", 8080, 10, "tcp"), port("", 8089, 1, "tcp"), true),
+            (port("", 8080, 10, "tcp"), port("", 8090, 5, "tcp"), false),
+            (
+                port("127.0.0.1", 8080, 1, "tcp"),
+                port("", 8080, 1, "tcp"),
+                true,
+            ),
+            (
+                port("127.0.0.1", 8080, 1, "tcp"),
+                port("0.0.0.0", 8080, 1, "tcp"),
+                true,
+            ),
+            (
+                port("127.0.0.1", 8080, 1, "tcp"),
+                port("127.0.0.2", 8080, 1, "tcp"),
+                false,
+            ),
+            (
+                port("127.0.0.1", 8080, 1, "tcp"),
+                port("::", 8080, 1, "tcp"),
+                false,
+            ),
+            (
+                port("::1", 8080, 1, "tcp"),
+                port("::", 8080, 1, "tcp"),
+                true,
+            ),
+        ];
+        for (i, (a, b, expected)) in tests.iter().enumerate() {
+            assert_eq!(port_mappings_overlap(a, b), *expected, "test case {i}");
+            assert_eq!(
+                port_mappings_overlap(b, a),
+                *expected,
+                "reverse test case {i}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fw_config_port_conflict() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+
+        let net_conf = SetupNetwork {
+            subnets: Some(vec!["10.0.0.0/24".parse().unwrap()]),
+            network_id: "abc".to_string(),
+            bridge_name: "bridge".to_string(),
+            network_hash_name: "hash".to_string(),
+            isolation: IsolateOption::Never,
+            isolation_groups: vec![],
+            dns_port: 53,
+            log_dropped: None,
+            snat_ipv4: None,
+            snat_ipv6: None,
+            snat_exclude: vec![],
+            hairpin_masquerade: true,
+        };
+        let ports = Some(vec![port("", 8080, 1, "tcp")]);
+        let mut port_conf = PortForwardConfig {
+            container_id: "123".to_string(),
+            network_id: "abc".to_string(),
+            port_mappings: &ports,
+            network_name: "name".to_string(),
+            network_hash_name: "hash".to_string(),
+            container_ip_v4: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+            subnet_v4: Some("10.0.0.0/24".parse().unwrap()),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 53,
+            dns_server_ips: &vec![],
+            localhost_forwarding: true,
+            hairpin_masquerade: true,
+        };
+
+        write_fw_config(
+            config_dir, "abc", "123", "iptables", &net_conf, &port_conf, false,
+        )
+        .expect("first container");
+        // same container again must not conflict with itself
+        write_fw_config(
+            config_dir, "abc", "123", "iptables", &net_conf, &port_conf, false,
+        )
+        .expect("same container");
+
+        port_conf.container_id = "456".to_string();
+        port_conf.container_ip_v4 = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)));
+        let err = write_fw_config(
+            config_dir, "abc", "456", "iptables", &net_conf, &port_conf, false,
+        )
+        .expect_err("conflicting port mapping");
+        assert_eq!(
+            err.to_string(),
+            "port mapping *:8080/tcp conflicts with container 123 on network name which already uses *:8080/tcp"
+        );
+
+        let paths = get_file_paths(config_dir, "abc", "456", false).unwrap();
+        drop(paths.lock_file);
+        assert!(
+            !paths.port_conf_file.exists(),
+            "port conf should not be written"
+        );
+
+        write_fw_config(
+            config_dir, "abc", "456", "iptables", &net_conf, &port_conf, true,
+        )
+        .expect("conflict allowed");
+        assert!(paths.port_conf_file.exists(), "port conf should be written");
+    }
+
+    #[test]
+    fn test_allocate_host_ports() {
+        let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
+        let config_dir = tmpdir.path();
+
+        let mut ports = vec![port("", 8080, 1, "tcp")];
+        assert!(!allocate_host_ports(config_dir, &mut ports).unwrap());
+        assert_eq!(ports[0].host_port, 8080);
+
+        let mut ports = vec![
+            port("127.0.0.1", 0, 1, "tcp,udp"),
+            port("127.0.0.1", 0, 1, "udp"),
+        ];
+        assert!(allocate_host_ports(config_dir, &mut ports).unwrap());
+        assert_ne!(ports[0].host_port, 0);
+        assert_ne!(ports[1].host_port, 0);
+        assert!(
+            !port_mappings_overlap(&ports[0], &ports[1]),
+            "allocated ports must not overlap: {ports:?}"
+        );
+    }
+
     #[test]
     fn test_read_fw_config_empty() {
         let tmpdir = Builder::new().prefix("netavark-tests").tempdir().unwrap();
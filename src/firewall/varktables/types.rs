@@ -1,12 +1,18 @@
 use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::dump::{
+    dest_port_for, isolation_group_id, parse_port_range, DropCounter, FirewallDump, Isolation,
+    IsolationGroup, PortForward,
+};
 use crate::firewall::varktables::helpers::{
     add_chain_unique, append_unique, remove_if_rule_exists,
 };
 use crate::firewall::varktables::types::TeardownPolicy::{Never, OnComplete};
-use crate::network::internal_types::{IsolateOption, PortForwardConfig};
+use crate::firewall::{drop_log_prefix, DROP_LOG_RATE, IPTABLES};
+use crate::network::internal_types::{DropLog, IsolateOption, PortForwardConfig, SetupNetwork};
 use ipnet::IpNet;
 use iptables::IPTables;
 use log::debug;
+use std::collections::HashMap;
 use std::net::IpAddr;
 
 //  Chain names
@@ -25,6 +31,7 @@ const NETAVARK_HOSTPORT_DNAT: &str = "NETAVARK-HOSTPORT-DNAT";
 const NETAVARK_HOSTPORT_SETMARK: &str = "NETAVARK-HOSTPORT-SETMARK";
 const NETAVARK_HOSTPORT_MASK: &str = "NETAVARK-HOSTPORT-MASQ";
 const MASQUERADE: &str = "MASQUERADE";
+const SNAT: &str = "SNAT";
 const MARK: &str = "MARK";
 const DNAT: &str = "DNAT";
 const NETAVARK_ISOLATION_1: &str = "NETAVARK_ISOLATION_1";
@@ -32,6 +39,7 @@ const NETAVARK_ISOLATION_2: &str = "NETAVARK_ISOLATION_2";
 const NETAVARK_ISOLATION_3: &str = "NETAVARK_ISOLATION_3";
 
 const CONTAINER_DN_CHAIN: &str = "NETAVARK-DN-";
+const ISOLATION_GROUP_CHAIN: &str = "NETAVARK-ISO-";
 
 const HEXMARK: &str = "0x2000";
 
@@ -192,17 +200,40 @@ pub fn create_network_chains(chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
     Ok(())
 }
 
+/// Rules to drop the traffic to the bridge in the isolation chains.
+/// When the rules are inserted at the same position the log rule ends up
+/// in front of the drop rule.
+fn get_isolation_drop_rules(interface_name: &str, log_dropped: Option<DropLog>) -> Vec<String> {
+    let mut rules = vec![format!("-o {interface_name} -j DROP")];
+    let prefix = drop_log_prefix(interface_name);
+    let limit = format!("-o {interface_name} -m limit --limit {DROP_LOG_RATE}/min");
+    match log_dropped {
+        Some(DropLog::Log) => rules.push(format!("{limit} -j LOG --log-prefix '{prefix}'")),
+        Some(DropLog::Nflog) => rules.push(format!(
+            "{limit} -j NFLOG --nflog-prefix '{prefix}' --nflog-group 0"
+        )),
+        None => {}
+    }
+    rules
+}
+
+/// The chain name of an isolation group, the group name is hashed to stay
+/// within the iptables chain name limit.
+fn get_isolation_group_chain_name(group: &str) -> String {
+    ISOLATION_GROUP_CHAIN.to_owned() + &isolation_group_id(IPTABLES, group)
+}
+
 pub fn get_network_chains<'a>(
     conn: &'a IPTables,
     network: IpNet,
-    network_hash_name: &'a str,
+    network_setup: &SetupNetwork,
     is_ipv6: bool,
-    interface_name: String,
-    isolation: IsolateOption,
-    dns_port: u16,
 ) -> Vec<VarkChain<'a>> {
     let mut chains = Vec::new();
-    let prefixed_network_hash_name = format!("{}-{}", "NETAVARK", network_hash_name);
+    let prefixed_network_hash_name = format!("{}-{}", "NETAVARK", network_setup.network_hash_name);
+    let interface_name = &network_setup.bridge_name;
+    let isolation = network_setup.isolation;
+    let dns_port = network_setup.dns_port;
 
     // NETAVARK-HASH
     let mut hashed_network_chain = VarkChain::new(
@@ -222,8 +253,19 @@ pub fn get_network_chains<'a>(
     if is_ipv6 {
         multicast_dest = MULTICAST_NET_V6;
     }
+    // no source nat for the excluded destinations
+    for exclude in network_setup.snat_exclude_for(&network) {
+        hashed_network_chain.build_rule(VarkRule::new(
+            format!("-d {exclude} -j {ACCEPT}"),
+            Some(TeardownPolicy::OnComplete),
+        ));
+    }
+    let nat_target = match network_setup.snat_address(&network) {
+        Some(ip) => format!("{SNAT} --to-source {ip}"),
+        None => MASQUERADE.to_string(),
+    };
     hashed_network_chain.build_rule(VarkRule::new(
-        format!("! -d {multicast_dest} -j {MASQUERADE}"),
+        format!("! -d {multicast_dest} -j {nat_target}"),
         Some(TeardownPolicy::OnComplete),
     ));
     chains.push(hashed_network_chain);
@@ -248,6 +290,8 @@ pub fn get_network_chains<'a>(
     // used to prepend specific rules
     let mut ind = 1;
 
+    let drop_rules = get_isolation_drop_rules(interface_name, network_setup.log_dropped);
+
     // NETAVARK_ISOLATION_2
     // NETAVARK_ISOLATION_2 chain must always exist,
     // because non-isolation creates DROP rule in NETAVARK_ISOLATION_3
@@ -305,12 +349,40 @@ pub fn get_network_chains<'a>(
             td_policy: Some(TeardownPolicy::OnComplete),
         });
 
+        // Networks sharing an isolation group accept the traffic to each other before it
+        // reaches the isolation drop rules, the jump is inserted above the rule from before.
+        for group in &network_setup.isolation_groups {
+            let group_chain_name = get_isolation_group_chain_name(group);
+            // NETAVARK-ISO-HASH -o bridge_name -j ACCEPT
+            let mut group_chain = VarkChain::new(
+                conn,
+                FILTER.to_string(),
+                group_chain_name.clone(),
+                Some(OnComplete),
+            );
+            group_chain.create = true;
+            group_chain.build_rule(VarkRule::new(
+                format!("-o {interface_name} -j {ACCEPT}"),
+                Some(TeardownPolicy::OnComplete),
+            ));
+            chains.push(group_chain);
+
+            // NETAVARK_ISOLATION_1 -i bridge_name -j NETAVARK-ISO-HASH
+            netavark_isolation_chain_1.build_rule(VarkRule {
+                rule: format!("-i {interface_name} -j {group_chain_name}"),
+                position: Some(ind),
+                td_policy: Some(TeardownPolicy::OnComplete),
+            });
+        }
+
         // NETAVARK_ISOLATION_2 -o bridge_name -j DROP
-        netavark_isolation_chain_2.build_rule(VarkRule {
-            rule: format!("-o {} -j {}", interface_name, "DROP"),
-            position: Some(ind),
-            td_policy: Some(TeardownPolicy::OnComplete),
-        });
+        for rule in &drop_rules {
+            netavark_isolation_chain_2.build_rule(VarkRule {
+                rule: rule.clone(),
+                position: Some(ind),
+                td_policy: Some(TeardownPolicy::OnComplete),
+            });
+        }
 
         // NETAVARK_ISOLATION_3 -j NETAVARK_ISOLATION_2
         netavark_isolation_chain_3.build_rule(VarkRule {
@@ -327,17 +399,19 @@ pub fn get_network_chains<'a>(
         // create DROP rule for non-isolations to enforce strict isolation rules.
 
         // NETAVARK_ISOLATION_3 -o bridge_name -j DROP
-        netavark_isolation_chain_3.build_rule(VarkRule {
-            rule: format!("-o {} -j {}", interface_name, "DROP"),
-            position: Some(ind),
-            td_policy: Some(TeardownPolicy::OnComplete),
-        });
+        for rule in &drop_rules {
+            netavark_isolation_chain_3.build_rule(VarkRule {
+                rule: rule.clone(),
+                position: Some(ind),
+                td_policy: Some(TeardownPolicy::OnComplete),
+            });
+        }
 
         // NETAVARK_ISOLATION_3 -j NETAVARK_ISOLATION_2
         netavark_isolation_chain_3.build_rule(VarkRule {
             rule: format!("-j {NETAVARK_ISOLATION_2}"),
-            // position +1 to place this rule under all of NETAVARK_ISOLATION_3 DROP rules.
-            position: Some(ind + 1),
+            // position +n to place this rule under all of NETAVARK_ISOLATION_3 DROP rules.
+            position: Some(ind + drop_rules.len() as i32),
             td_policy: Some(TeardownPolicy::Never),
         });
     }
@@ -418,8 +492,10 @@ pub fn get_port_forwarding_chains<'a>(
     is_ipv6: bool,
 ) -> NetavarkResult<Vec<VarkChain<'a>>> {
     let mut localhost_ip = "127.0.0.1";
+    let mut localhost_net = "127.0.0.0/8";
     if is_ipv6 {
         localhost_ip = "::1";
+        localhost_net = "::1/128";
     }
     let mut chains = Vec::new();
     // Set up all chains
@@ -611,16 +687,24 @@ pub fn get_port_forwarding_chains<'a>(
                 }
 
                 // dn container (the actual port usages)
-                netavark_hashed_dn_chain.build_rule(VarkRule::new(dn_setmark_rule_localhost, None));
+                if pfwd.hairpin_masquerade {
+                    netavark_hashed_dn_chain
+                        .build_rule(VarkRule::new(dn_setmark_rule_localhost, None));
+                }
 
-                netavark_hashed_dn_chain.build_rule(VarkRule::new(dn_setmark_rule_subnet, None));
+                if pfwd.localhost_forwarding {
+                    netavark_hashed_dn_chain
+                        .build_rule(VarkRule::new(dn_setmark_rule_subnet, None));
+                }
 
                 let mut container_ip_value = container_ip.to_string();
                 if is_ipv6 {
                     container_ip_value = format!("[{container_ip_value}]")
                 }
                 let mut container_port = i.container_port.to_string();
-                if is_range {
+                // a host port range forwarded to a single container port
+                // simply uses the same destination port for all of them
+                if is_range && !i.is_many_to_one() {
                     container_port = format!(
                         "{}-{}/{}",
                         i.container_port,
@@ -637,6 +721,8 @@ pub fn get_port_forwarding_chains<'a>(
                 // the rule a bit
                 if let Some(host_ip) = host_ip {
                     dnat_rule = format!("{dnat_rule} -d {host_ip}")
+                } else if !pfwd.localhost_forwarding {
+                    dnat_rule = format!("{dnat_rule} ! -d {localhost_net}")
                 }
                 netavark_hashed_dn_chain.build_rule(VarkRule::new(dnat_rule, None));
             }
@@ -652,3 +738,343 @@ pub fn get_port_forwarding_chains<'a>(
 
     Ok(chains)
 }
+
+/// Read the netavark rules from the nat and filter table and add them to the dump.
+pub fn dump_rules(conn: &IPTables, dump: &mut FirewallDump) -> NetavarkResult<()> {
+    let mut nat_chains: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+    for rule in list_table_rules(conn, NAT)? {
+        nat_chains.entry(rule[1].clone()).or_default().push(rule);
+    }
+
+    for rule in nat_chains.get(POSTROUTING).into_iter().flatten() {
+        // -A POSTROUTING -s <subnet> -j NETAVARK-<hash>
+        let (Some(source), Some(target)) = (rule_arg(rule, "-s"), rule_arg(rule, "-j")) else {
+            continue;
+        };
+        if !target.starts_with("NETAVARK-") || target == NETAVARK_HOSTPORT_MASK {
+            continue;
+        }
+        let Ok(subnet) = source.parse() else {
+            continue;
+        };
+        dump.networks.push(subnet);
+        let masquerade = nat_chains
+            .get(target)
+            .into_iter()
+            .flatten()
+            .any(|r| matches!(rule_arg(r, "-j"), Some(MASQUERADE) | Some(SNAT)));
+        if masquerade {
+            dump.masquerade.push(subnet);
+        }
+    }
+
+    for (chain, rules) in &nat_chains {
+        if !chain.starts_with(CONTAINER_DN_CHAIN) {
+            continue;
+        }
+        for rule in rules {
+            dump.port_forwards.append(&mut parse_dnat_rule(rule));
+        }
+    }
+
+    let filter_rules = list_table_rules(conn, FILTER)?;
+    for rule in &filter_rules {
+        // -A NETAVARK_ISOLATION_1 -i <bridge> -j NETAVARK-ISO-<hash>
+        // -A NETAVARK-ISO-<hash> -o <bridge> -j ACCEPT
+        if rule[1] != NETAVARK_ISOLATION_1 {
+            continue;
+        }
+        let (Some(bridge), Some(target)) = (rule_arg(rule, "-i"), rule_arg(rule, "-j")) else {
+            continue;
+        };
+        let Some(group) = target.strip_prefix(ISOLATION_GROUP_CHAIN) else {
+            continue;
+        };
+        let member = filter_rules.iter().any(|r| {
+            r[1] == target && rule_arg(r, "-o") == Some(bridge) && rule_arg(r, "-j") == Some(ACCEPT)
+        });
+        if member {
+            dump.isolation_groups.push(IsolationGroup {
+                bridge: bridge.to_string(),
+                group: group.to_string(),
+            });
+        }
+    }
+
+    for rule in filter_rules {
+        let isolation = match (rule[1].as_str(), rule_arg(&rule, "-j")) {
+            // -A NETAVARK_ISOLATION_1 -i <bridge> ! -o <bridge> -j NETAVARK_ISOLATION_{2,3}
+            (NETAVARK_ISOLATION_1, Some(NETAVARK_ISOLATION_2)) => IsolateOption::Normal,
+            (NETAVARK_ISOLATION_1, Some(NETAVARK_ISOLATION_3)) => IsolateOption::Strict,
+            // -A NETAVARK_ISOLATION_3 -o <bridge> -j DROP
+            (NETAVARK_ISOLATION_3, Some("DROP")) => IsolateOption::Never,
+            _ => continue,
+        };
+        let flag = if isolation == IsolateOption::Never {
+            "-o"
+        } else {
+            "-i"
+        };
+        if let Some(bridge) = rule_arg(&rule, flag) {
+            dump.isolation.push(Isolation {
+                bridge: bridge.to_string(),
+                isolation,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the counters of the drop rules in the isolation chains.
+pub fn get_drop_counters(conn: &IPTables) -> NetavarkResult<Vec<DropCounter>> {
+    let mut counters = Vec::new();
+    for chain in [NETAVARK_ISOLATION_2, NETAVARK_ISOLATION_3] {
+        let output = match conn.execute(FILTER, &format!("-S {chain} -v")) {
+            Ok(o) => o,
+            Err(e) => return Err(NetavarkError::Message(e.to_string())),
+        };
+        // the chain does not exist if no network was set up yet
+        if !output.status.success() {
+            continue;
+        }
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(counter) = parse_drop_counter(&split_rule(line)) {
+                counters.push(counter);
+            }
+        }
+    }
+    Ok(counters)
+}
+
+/// Parse the counters of a verbose drop rule.
+/// -A NETAVARK_ISOLATION_3 -o <bridge> -c <packets> <bytes> -j DROP
+fn parse_drop_counter(rule: &[String]) -> Option<DropCounter> {
+    if rule_arg(rule, "-j") != Some("DROP") {
+        return None;
+    }
+    let bridge = rule_arg(rule, "-o")?;
+    let pos = rule.iter().position(|a| a == "-c")?;
+    Some(DropCounter {
+        bridge: bridge.to_string(),
+        packets: rule.get(pos + 1)?.parse().ok()?,
+        bytes: rule.get(pos + 2)?.parse().ok()?,
+    })
+}
+
+/// List all rules of the table split into their arguments.
+/// Only rules (-A) are returned, the first two elements are -A and the chain name.
+fn list_table_rules(conn: &IPTables, table: &str) -> NetavarkResult<Vec<Vec<String>>> {
+    let lines = match conn.list_table(table) {
+        Ok(l) => l,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    Ok(lines
+        .iter()
+        .map(|l| split_rule(l))
+        .filter(|r| r.len() > 1 && r[0] == "-A")
+        .collect())
+}
+
+/// Split an iptables -S line into arguments, double quoted arguments are kept together.
+fn split_rule(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+/// Get the value of the given argument unless the argument is negated.
+fn rule_arg<'a>(rule: &'a [String], flag: &str) -> Option<&'a str> {
+    let pos = rule.iter().position(|a| a == flag)?;
+    if pos > 0 && rule[pos - 1] == "!" {
+        return None;
+    }
+    rule.get(pos + 1).map(|s| s.as_str())
+}
+
+/// Parse a container dnat rule, one entry per port is returned.
+/// -A NETAVARK-DN-<hash> -d <host ip> -p tcp -m tcp --dport 8080:8081 -j DNAT --to-destination 10.88.0.2:80-81/8080
+fn parse_dnat_rule(rule: &[String]) -> Vec<PortForward> {
+    let mut ports = Vec::new();
+    if rule_arg(rule, "-j") != Some(DNAT) {
+        return ports;
+    }
+    let (Some(protocol), Some(dport), Some(dest)) = (
+        rule_arg(rule, "-p"),
+        rule_arg(rule, "--dport"),
+        rule_arg(rule, "--to-destination"),
+    ) else {
+        return ports;
+    };
+    let host_ip = rule_arg(rule, "-d").and_then(|d| {
+        let ip: Option<IpAddr> = d.split('/').next()?.parse().ok();
+        ip
+    });
+    let Some((host_start, host_end)) = parse_port_range(dport) else {
+        return ports;
+    };
+
+    // strip the base port of the range
+    let dest = dest.split('/').next().unwrap_or(dest);
+    let (dest_ip, dest_ports) = match dest.strip_prefix('[') {
+        Some(v6) => match v6.split_once("]:") {
+            Some(d) => d,
+            None => return ports,
+        },
+        None => match dest.rsplit_once(':') {
+            Some(d) => d,
+            None => return ports,
+        },
+    };
+    let (Ok(dest_ip), Some((dest_start, dest_end))) =
+        (dest_ip.parse(), parse_port_range(dest_ports))
+    else {
+        return ports;
+    };
+
+    for (i, host_port) in (host_start..=host_end).enumerate() {
+        ports.push(PortForward {
+            protocol: protocol.to_string(),
+            host_ip,
+            host_port,
+            dest_ip,
+            dest_port: dest_port_for(dest_start, dest_end, i as u16),
+        });
+    }
+    ports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_rule() {
+        assert_eq!(
+            split_rule(
+                r#"-A NETAVARK-DN-1 -p tcp -m comment --comment "dnat name: podman id: 123" -j DNAT"#
+            ),
+            vec![
+                "-A",
+                "NETAVARK-DN-1",
+                "-p",
+                "tcp",
+                "-m",
+                "comment",
+                "--comment",
+                "dnat name: podman id: 123",
+                "-j",
+                "DNAT"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_arg() {
+        let rule =
+            split_rule("-A NETAVARK_ISOLATION_1 -i podman1 ! -o podman1 -j NETAVARK_ISOLATION_2");
+        assert_eq!(rule_arg(&rule, "-i"), Some("podman1"));
+        assert_eq!(rule_arg(&rule, "-o"), None);
+        assert_eq!(rule_arg(&rule, "-j"), Some(NETAVARK_ISOLATION_2));
+    }
+
+    #[test]
+    fn test_parse_dnat_rule() {
+        let rule = split_rule(
+            "-A NETAVARK-DN-1 -d 127.0.0.1/32 -p tcp -m tcp --dport 8080:8081 -j DNAT --to-destination 10.88.0.2:80-81/8080",
+        );
+        let ports = parse_dnat_rule(&rule);
+        assert_eq!(ports.len(), 2);
+        assert_eq!(
+            ports[1],
+            PortForward {
+                protocol: "tcp".to_string(),
+                host_ip: Some("127.0.0.1".parse().unwrap()),
+                host_port: 8081,
+                dest_ip: "10.88.0.2".parse().unwrap(),
+                dest_port: 81,
+            }
+        );
+
+        let rule = split_rule(
+            "-A NETAVARK-DN-1 -p udp -m udp --dport 53 -j DNAT --to-destination [fd00::2]:5353",
+        );
+        assert_eq!(
+            parse_dnat_rule(&rule),
+            vec![PortForward {
+                protocol: "udp".to_string(),
+                host_ip: None,
+                host_port: 53,
+                dest_ip: "fd00::2".parse().unwrap(),
+                dest_port: 5353,
+            }]
+        );
+
+        let rule = split_rule(
+            "-A NETAVARK-DN-1 -p tcp -m tcp --dport 8080:8082 -j DNAT --to-destination 10.88.0.2:80",
+        );
+        let ports = parse_dnat_rule(&rule);
+        assert_eq!(ports.len(), 3);
+        assert!(ports.iter().all(|p| p.dest_port == 80), "{ports:?}");
+
+        let rule = split_rule(
+            "-A NETAVARK-DN-1 -s 10.88.0.0/16 -p tcp -m tcp --dport 8080 -j NETAVARK-HOSTPORT-SETMARK",
+        );
+        assert!(parse_dnat_rule(&rule).is_empty());
+    }
+
+    #[test]
+    fn test_isolation_drop_rules() {
+        assert_eq!(
+            get_isolation_drop_rules("podman1", None),
+            vec!["-o podman1 -j DROP"]
+        );
+        assert_eq!(
+            get_isolation_drop_rules("podman1", Some(DropLog::Log)),
+            vec![
+                "-o podman1 -j DROP",
+                "-o podman1 -m limit --limit 5/min -j LOG --log-prefix 'NETAVARK podman1: '"
+            ]
+        );
+        assert_eq!(
+            get_isolation_drop_rules("podman1", Some(DropLog::Nflog))[1],
+            "-o podman1 -m limit --limit 5/min -j NFLOG --nflog-prefix 'NETAVARK podman1: ' --nflog-group 0"
+        );
+    }
+
+    #[test]
+    fn test_parse_drop_counter() {
+        let rule = split_rule("-A NETAVARK_ISOLATION_3 -o podman1 -c 12 1008 -j DROP");
+        assert_eq!(
+            parse_drop_counter(&rule),
+            Some(DropCounter {
+                bridge: "podman1".to_string(),
+                packets: 12,
+                bytes: 1008,
+            })
+        );
+
+        let rule = split_rule(
+            r#"-A NETAVARK_ISOLATION_3 -o podman1 -m limit --limit 5/min -c 3 252 -j LOG --log-prefix "NETAVARK podman1: ""#,
+        );
+        assert_eq!(parse_drop_counter(&rule), None);
+
+        let rule = split_rule("-A NETAVARK_ISOLATION_3 -c 0 0 -j NETAVARK_ISOLATION_2");
+        assert_eq!(parse_drop_counter(&rule), None);
+    }
+}
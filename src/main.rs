@@ -3,10 +3,15 @@ use std::ffi::OsString;
 use clap::{Parser, Subcommand};
 
 use netavark::commands::dhcp_proxy;
+use netavark::commands::drivers;
+use netavark::commands::firewall;
 use netavark::commands::firewalld_reload;
+use netavark::commands::schema;
 use netavark::commands::setup;
+use netavark::commands::status;
 use netavark::commands::teardown;
 use netavark::commands::update;
+use netavark::commands::validate;
 use netavark::commands::version;
 
 #[derive(Parser, Debug)]
@@ -15,7 +20,7 @@ struct Opts {
     /// Instead of reading from STDIN, read the configuration to be applied from the given file.
     #[clap(short, long)]
     file: Option<OsString>,
-    /// Select netavark's firewall driver
+    /// Select netavark's firewall driver (iptables, nftables, firewalld, none or auto)
     // There is no suitable short argument like -F, so there is no short argument.
     #[clap(long, env = "NETAVARK_FW")]
     firewall_driver: Option<String>,
@@ -44,13 +49,23 @@ enum SubCommand {
     Update(update::Update),
     /// Undo any configuration applied via setup command.
     Teardown(teardown::Teardown),
+    /// Report the live network state of the plugin networks.
+    Status(status::Status),
+    /// Check a network config or setup input without changing the system.
+    Validate(validate::Validate),
     /// Display info about netavark.
     Version(version::Version),
+    /// List the built-in drivers and the plugins in the plugin directories.
+    Drivers(drivers::Drivers),
+    /// Print the JSON Schemas of the netavark input and output types.
+    Schema(schema::Schema),
     /// Start dhcp-proxy
     DHCPProxy(dhcp_proxy::Opts),
     /// Listen for the firewalld reload event and reload fw rules
     #[command(name = "firewalld-reload")]
     FirewallDReload,
+    /// Inspect and repair the netavark firewall rules
+    Firewall(firewall::Firewall),
 }
 
 fn main() {
@@ -80,10 +95,27 @@ fn main() {
             opts.plugin_directories,
             rootless,
         ),
-        SubCommand::Update(mut update) => update.exec(config, aardvark_bin, rootless),
+        SubCommand::Status(status) => status.exec(
+            opts.file,
+            config,
+            opts.firewall_driver,
+            opts.plugin_directories,
+            rootless,
+        ),
+        SubCommand::Update(mut update) => update.exec(
+            opts.file,
+            config,
+            aardvark_bin,
+            opts.plugin_directories,
+            rootless,
+        ),
+        SubCommand::Validate(validate) => validate.exec(opts.file, opts.plugin_directories),
         SubCommand::Version(version) => version.exec(),
+        SubCommand::Drivers(drivers) => drivers.exec(opts.plugin_directories),
+        SubCommand::Schema(schema) => schema.exec(),
         SubCommand::DHCPProxy(proxy) => dhcp_proxy::serve(proxy),
         SubCommand::FirewallDReload => firewalld_reload::listen(config),
+        SubCommand::Firewall(firewall) => firewall.exec(config, opts.firewall_driver),
     };
 
     match result {
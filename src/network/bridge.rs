@@ -1,4 +1,9 @@
-use std::{collections::HashMap, net::IpAddr, os::fd::BorrowedFd, sync::Once};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::BorrowedFd,
+    sync::Once,
+};
 
 use ipnet::IpNet;
 use log::{debug, error};
@@ -11,6 +16,7 @@ use crate::{
     error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
     exec_netns,
     firewall::{
+        self,
         iptables::MAX_HASH_SIZE,
         state::{remove_fw_config, write_fw_config},
     },
@@ -19,18 +25,21 @@ use crate::{
 
 use super::{
     constants::{
-        ISOLATE_OPTION_FALSE, ISOLATE_OPTION_STRICT, ISOLATE_OPTION_TRUE,
-        NO_CONTAINER_INTERFACE_ERROR, OPTION_HOST_INTERFACE_NAME, OPTION_ISOLATE, OPTION_METRIC,
-        OPTION_MTU, OPTION_NO_DEFAULT_ROUTE, OPTION_VRF,
+        ISOLATE_OPTION_FALSE, ISOLATE_OPTION_STRICT, ISOLATE_OPTION_TRUE, LOG_DROPPED_OPTION_LOG,
+        LOG_DROPPED_OPTION_NFLOG, NO_CONTAINER_INTERFACE_ERROR, OPTION_ALLOW_PORT_CONFLICTS,
+        OPTION_HAIRPIN_MASQUERADE, OPTION_HOST_INTERFACE_NAME, OPTION_ISOLATE,
+        OPTION_ISOLATION_GROUPS, OPTION_LOCALHOST_FORWARDING, OPTION_LOG_DROPPED, OPTION_METRIC,
+        OPTION_MTU, OPTION_NO_DEFAULT_ROUTE, OPTION_SNAT_EXCLUDE, OPTION_SNAT_IPV4,
+        OPTION_SNAT_IPV6, OPTION_VRF,
     },
     core_utils::{self, get_ipam_addresses, join_netns, parse_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::{
-        IPAMAddresses, IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork,
+        DropLog, IPAMAddresses, IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork,
         TeardownPortForward,
     },
     netlink,
-    types::StatusBlock,
+    types::{PortMapping, StatusBlock},
 };
 
 const NO_BRIDGE_NAME_ERROR: &str = "no bridge interface name given";
@@ -50,12 +59,16 @@ struct InternalData {
     mtu: u32,
     /// if this network should be isolated from others
     isolate: IsolateOption,
+    /// log packets dropped by the isolation rules
+    log_dropped: Option<DropLog>,
     /// Route metric for any default routes added for the network
     metric: Option<u32>,
     /// if set, no default gateway will be added
     no_default_route: bool,
     /// sef vrf for bridge
     vrf: Option<String>,
+    /// if set, port mappings are not checked against other containers
+    allow_port_conflicts: bool,
     // TODO: add vlan
 }
 
@@ -84,6 +97,19 @@ impl driver::NetworkDriver for Bridge<'_> {
 
         let mtu: u32 = parse_option(&self.info.network.options, OPTION_MTU)?.unwrap_or(0);
         let isolate: IsolateOption = get_isolate_option(&self.info.network.options)?;
+        // only validated here, the firewall config parses them again
+        get_isolation_groups(&self.info.network.options, isolate)?;
+        let log_dropped = get_log_dropped_option(&self.info.network.options)?;
+        // only validated here, the firewall config parses them again
+        let (_, _, snat_exclude) = get_snat_options(&self.info.network.options)?;
+        // checked before anything is set up, a failing firewall setup leaves the state behind
+        if !snat_exclude.is_empty() && self.info.firewall.driver_name() == firewall::FIREWALLD {
+            return Err(NetavarkError::msg(firewall::firewalld::SNAT_EXCLUDE_ERROR));
+        }
+        let (localhost_forwarding, _) = get_port_forward_options(&self.info.network.options)?;
+        if !localhost_forwarding {
+            check_no_localhost_ports(self.info.port_mappings)?;
+        }
         let metric: u32 = parse_option(&self.info.network.options, OPTION_METRIC)?.unwrap_or(100);
         let no_default_route: bool =
             parse_option(&self.info.network.options, OPTION_NO_DEFAULT_ROUTE)?.unwrap_or(false);
@@ -93,6 +119,11 @@ impl driver::NetworkDriver for Bridge<'_> {
             OPTION_HOST_INTERFACE_NAME,
         )?
         .unwrap_or_else(|| "".to_string());
+        let allow_port_conflicts: bool = parse_option(
+            &self.info.per_network_opts.options,
+            OPTION_ALLOW_PORT_CONFLICTS,
+        )?
+        .unwrap_or(false);
 
         let static_mac = match &self.info.per_network_opts.static_mac {
             Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
@@ -107,9 +138,11 @@ impl driver::NetworkDriver for Bridge<'_> {
             ipam,
             mtu,
             isolate,
+            log_dropped,
             metric: Some(metric),
             no_default_route,
             vrf,
+            allow_port_conflicts,
         });
         Ok(())
     }
@@ -154,6 +187,8 @@ impl driver::NetworkDriver for Bridge<'_> {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            ntp_server_ips: None,
+            port_mappings: None,
         };
         // interfaces map, but we only ever expect one, for response
         let mut interfaces: HashMap<String, types::NetInterface> = HashMap::new();
@@ -322,10 +357,14 @@ impl<'a> Bridge<'a> {
         container_addresses: &Vec<IpNet>,
         nameservers: &'a Vec<IpAddr>,
         isolate: IsolateOption,
+        log_dropped: Option<DropLog>,
         bridge_name: String,
     ) -> NetavarkResult<(SetupNetwork, PortForwardConfig)> {
         let id_network_hash =
             CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE);
+        let (snat_ipv4, snat_ipv6, snat_exclude) = get_snat_options(&self.info.network.options)?;
+        let (localhost_forwarding, hairpin_masquerade) =
+            get_port_forward_options(&self.info.network.options)?;
         let sn = SetupNetwork {
             subnets: self
                 .info
@@ -337,7 +376,13 @@ impl<'a> Bridge<'a> {
             network_id: self.info.network.id.clone(),
             network_hash_name: id_network_hash.clone(),
             isolation: isolate,
+            isolation_groups: get_isolation_groups(&self.info.network.options, isolate)?,
             dns_port: self.info.dns_port,
+            log_dropped,
+            snat_ipv4,
+            snat_ipv6,
+            snat_exclude,
+            hairpin_masquerade,
         };
 
         let mut has_ipv4 = false;
@@ -379,6 +424,8 @@ impl<'a> Bridge<'a> {
             subnet_v6: net_v6,
             dns_port: self.info.dns_port,
             dns_server_ips: nameservers,
+            localhost_forwarding,
+            hairpin_masquerade,
         };
         Ok((sn, spf))
     }
@@ -388,6 +435,7 @@ impl<'a> Bridge<'a> {
             &data.ipam.container_addresses,
             &data.ipam.nameservers,
             data.isolate,
+            data.log_dropped,
             data.bridge_interface_name.clone(),
         )?;
 
@@ -399,12 +447,13 @@ impl<'a> Bridge<'a> {
                 self.info.firewall.driver_name(),
                 &sn,
                 &spf,
+                data.allow_port_conflicts,
             )?;
         }
 
         self.info.firewall.setup_network(sn)?;
 
-        if spf.port_mappings.is_some() {
+        if spf.port_mappings.is_some() && spf.localhost_forwarding {
             // Need to enable sysctl localnet so that traffic can pass
             // through localhost to containers
 
@@ -430,14 +479,24 @@ impl<'a> Bridge<'a> {
         // "borrow later used" problems
         let (container_addresses, nameservers);
 
-        let (container_addresses_ref, nameservers_ref, isolate) = match &self.data {
-            Some(d) => (&d.ipam.container_addresses, &d.ipam.nameservers, d.isolate),
+        let (container_addresses_ref, nameservers_ref, isolate, log_dropped) = match &self.data {
+            Some(d) => (
+                &d.ipam.container_addresses,
+                &d.ipam.nameservers,
+                d.isolate,
+                d.log_dropped,
+            ),
             None => {
                 let isolate = get_isolate_option(&self.info.network.options).unwrap_or_else(|e| {
                     // just log we still try to do as much as possible for cleanup
                     error!("failed to parse {} option: {}", OPTION_ISOLATE, e);
                     IsolateOption::Never
                 });
+                let log_dropped = get_log_dropped_option(&self.info.network.options)
+                    .unwrap_or_else(|e| {
+                        error!("failed to parse {} option: {}", OPTION_LOG_DROPPED, e);
+                        None
+                    });
 
                 (container_addresses, nameservers) =
                     match get_ipam_addresses(self.info.per_network_opts, self.info.network) {
@@ -448,7 +507,7 @@ impl<'a> Bridge<'a> {
                             (Vec::new(), Vec::new())
                         }
                     };
-                (&container_addresses, &nameservers, isolate)
+                (&container_addresses, &nameservers, isolate, log_dropped)
             }
         };
 
@@ -456,6 +515,7 @@ impl<'a> Bridge<'a> {
             container_addresses_ref,
             nameservers_ref,
             isolate,
+            log_dropped,
             bridge_name,
         )?;
 
@@ -866,3 +926,91 @@ fn get_isolate_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<
         _ => IsolateOption::Never,
     })
 }
+
+/// Parse the comma separated isolation groups, they are only valid for isolated networks.
+fn get_isolation_groups(
+    opts: &Option<HashMap<String, String>>,
+    isolate: IsolateOption,
+) -> NetavarkResult<Vec<String>> {
+    let Some(groups) = parse_option::<String>(opts, OPTION_ISOLATION_GROUPS)? else {
+        return Ok(vec![]);
+    };
+    if isolate == IsolateOption::Never {
+        return Err(NetavarkError::msg(format!(
+            "{OPTION_ISOLATION_GROUPS} requires the {OPTION_ISOLATE} option to be set"
+        )));
+    }
+    groups
+        .split(',')
+        .map(|group| {
+            let group = group.trim();
+            if group.is_empty()
+                || !group
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(NetavarkError::msg(format!(
+                    "invalid isolation group name \"{group}\", only alphanumeric characters, \"-\" and \"_\" are allowed"
+                )));
+            }
+            Ok(group.to_string())
+        })
+        .collect()
+}
+
+type SnatOptions = (Option<Ipv4Addr>, Option<Ipv6Addr>, Vec<IpNet>);
+
+fn get_snat_options(opts: &Option<HashMap<String, String>>) -> NetavarkResult<SnatOptions> {
+    let snat_ipv4 = parse_option(opts, OPTION_SNAT_IPV4)?;
+    let snat_ipv6 = parse_option(opts, OPTION_SNAT_IPV6)?;
+    let snat_exclude = match parse_option::<String>(opts, OPTION_SNAT_EXCLUDE)? {
+        Some(val) => val
+            .split(',')
+            .map(|net| {
+                net.trim().parse().map_err(|e| {
+                    NetavarkError::msg(format!("unable to parse \"{OPTION_SNAT_EXCLUDE}\": {e}"))
+                })
+            })
+            .collect::<NetavarkResult<_>>()?,
+        None => vec![],
+    };
+    Ok((snat_ipv4, snat_ipv6, snat_exclude))
+}
+
+/// Returns the localhost_forwarding and hairpin_masquerade options, both default to true.
+fn get_port_forward_options(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<(bool, bool)> {
+    let localhost_forwarding = parse_option(opts, OPTION_LOCALHOST_FORWARDING)?.unwrap_or(true);
+    let hairpin_masquerade = parse_option(opts, OPTION_HAIRPIN_MASQUERADE)?.unwrap_or(true);
+    Ok((localhost_forwarding, hairpin_masquerade))
+}
+
+/// Ports explicitly published on a loopback address cannot work without localhost forwarding.
+fn check_no_localhost_ports(port_mappings: &Option<Vec<PortMapping>>) -> NetavarkResult<()> {
+    for port in port_mappings.iter().flatten() {
+        if let Ok(ip) = port.host_ip.parse::<IpAddr>() {
+            if ip.is_loopback() {
+                return Err(NetavarkError::msg(format!(
+                    "port {} is published on {} but {OPTION_LOCALHOST_FORWARDING} is disabled",
+                    port.host_port, port.host_ip
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_log_dropped_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<DropLog>> {
+    let log_dropped: Option<String> = parse_option(opts, OPTION_LOG_DROPPED)?;
+    match log_dropped.as_deref() {
+        None | Some("false") => Ok(None),
+        Some(LOG_DROPPED_OPTION_LOG) | Some("true") => Ok(Some(DropLog::Log)),
+        Some(LOG_DROPPED_OPTION_NFLOG) => Ok(Some(DropLog::Nflog)),
+        Some(val) => Err(NetavarkError::msg(format!(
+            "invalid {OPTION_LOG_DROPPED} option value \"{val}\", must be one of \"log\", \"nflog\" or \"false\""
+        ))),
+    }
+}
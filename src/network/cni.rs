@@ -0,0 +1,606 @@
+//! Compatibility driver to run CNI plugins, see
+//! https://github.com/containernetworking/cni/blob/main/SPEC.md
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ipnet::IpNet;
+use log::debug;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+    wrap,
+};
+
+use super::{
+    constants::{CNI_RESULT_DIR, NO_CONTAINER_INTERFACE_ERROR, OPTION_CNI_CONFIG},
+    core_utils::parse_option,
+    driver::{DriverInfo, NetworkDriver},
+    netlink,
+    plugin::{find_plugin, get_plugin_timeout, run_plugin},
+    types::{NetAddress, NetInterface, PortMapping, StatusBlock},
+};
+
+const CNI_COMMAND_ADD: &str = "ADD";
+const CNI_COMMAND_DEL: &str = "DEL";
+
+/// CNI versions whose results we can parse, older results have no interfaces.
+const CNI_VERSIONS: &[&str] = &["0.3.0", "0.3.1", "0.4.0", "1.0.0", "1.1.0"];
+
+/// A single plugin of the CNI network config list.
+struct CniPlugin {
+    path: PathBuf,
+    /// the plugin config as given in the config list
+    conf: Map<String, Value>,
+}
+
+struct InternalData {
+    name: String,
+    cni_version: String,
+    plugins: Vec<CniPlugin>,
+}
+
+/// CNI error result printed by failed plugins.
+#[derive(Debug, Deserialize)]
+struct CniError {
+    code: u32,
+    msg: String,
+    #[serde(default)]
+    details: String,
+}
+
+/// The parts of the CNI result we translate into the status block.
+#[derive(Debug, Default, Deserialize)]
+struct CniResult {
+    #[serde(default)]
+    interfaces: Vec<CniInterface>,
+    #[serde(default)]
+    ips: Vec<CniIp>,
+    #[serde(default)]
+    dns: CniDns,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniInterface {
+    name: String,
+    #[serde(default)]
+    mac: String,
+    /// only set for interfaces in the container namespace
+    #[serde(default)]
+    sandbox: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniIp {
+    address: IpNet,
+    gateway: Option<IpAddr>,
+    interface: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CniDns {
+    #[serde(default)]
+    nameservers: Vec<IpAddr>,
+    #[serde(default)]
+    search: Vec<String>,
+}
+
+pub struct Cni<'a> {
+    info: DriverInfo<'a>,
+    plugin_directories: Option<Vec<OsString>>,
+    data: Option<InternalData>,
+}
+
+impl<'a> Cni<'a> {
+    pub fn new(info: DriverInfo<'a>, plugin_directories: &Option<Vec<OsString>>) -> Self {
+        Cni {
+            info,
+            plugin_directories: plugin_directories.clone(),
+            data: None,
+        }
+    }
+}
+
+impl NetworkDriver for Cni<'_> {
+    fn network_name(&self) -> String {
+        self.info.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        if self.info.per_network_opts.interface_name.is_empty() {
+            return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
+        }
+        self.data = Some(self.load_config()?);
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        _netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let data = self.get_data()?;
+
+        let mut prev_result: Option<Value> = None;
+        for (i, plugin) in data.plugins.iter().enumerate() {
+            match self.exec_cni_plugin(data, plugin, CNI_COMMAND_ADD, prev_result.as_ref()) {
+                Ok(result) => prev_result = Some(result),
+                Err(err) => {
+                    self.rollback(data, i + 1, prev_result.as_ref());
+                    return Err(err);
+                }
+            }
+        }
+
+        // DEL needs the result of the ADD, like the result cache of libcni
+        if let Some(result) = &prev_result {
+            if let Err(err) = write_cni_result(&self.result_file(), result) {
+                self.rollback(data, data.plugins.len(), prev_result.as_ref());
+                return Err(err);
+            }
+        }
+
+        let result: CniResult = match prev_result {
+            Some(result) => wrap!(serde_json::from_value(result), "parse cni result")?,
+            None => CniResult::default(),
+        };
+        Ok((
+            cni_result_to_status_block(result, &self.info.per_network_opts.interface_name),
+            None,
+        ))
+    }
+
+    fn teardown(
+        &self,
+        _netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<()> {
+        // teardown does not call validate()
+        let data = self.load_config()?;
+
+        let result_file = self.result_file();
+        let prev_result = read_cni_result(&result_file)?;
+
+        // CNI wants the plugins deleted in reverse order, try to delete
+        // all of them even if one fails
+        let mut error_list = NetavarkErrorList::new();
+        for plugin in data.plugins.iter().rev() {
+            if let Err(err) =
+                self.exec_cni_plugin(&data, plugin, CNI_COMMAND_DEL, prev_result.as_ref())
+            {
+                error_list.push(err);
+            }
+        }
+        if !error_list.is_empty() {
+            // keep the result for the next teardown attempt
+            return Err(NetavarkError::List(error_list));
+        }
+        match fs::remove_file(&result_file) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(NetavarkError::wrap("remove cni result file", e.into())),
+        }
+    }
+}
+
+impl Cni<'_> {
+    /// Read the CNI config list from the cni_config option and look up the plugins.
+    fn load_config(&self) -> NetavarkResult<InternalData> {
+        let config_path: PathBuf = parse_option(&self.info.network.options, OPTION_CNI_CONFIG)?
+            .ok_or_else(|| {
+                NetavarkError::msg(format!(
+                    "{OPTION_CNI_CONFIG} option is required for the cni driver"
+                ))
+            })?;
+        let content = wrap!(
+            fs::read_to_string(&config_path),
+            format!("read cni config {}", config_path.display())
+        )?;
+        let config: Value = wrap!(
+            serde_json::from_str(&content),
+            format!("parse cni config {}", config_path.display())
+        )?;
+        let (name, cni_version, confs) = parse_cni_config(config)?;
+
+        let mut plugins = Vec::with_capacity(confs.len());
+        for conf in confs {
+            // parse_cni_config() made sure every plugin has a type
+            let plugin_type = conf["type"].as_str().unwrap_or_default();
+            let path = find_plugin(plugin_type, &self.plugin_directories).ok_or_else(|| {
+                NetavarkError::msg(format!(
+                    "cni plugin \"{plugin_type}\" not found in the plugin directories"
+                ))
+            })?;
+            plugins.push(CniPlugin { path, conf });
+        }
+
+        Ok(InternalData {
+            name,
+            cni_version,
+            plugins,
+        })
+    }
+
+    /// Delete the first count plugins in reverse order after a failed setup,
+    /// like the other drivers we must not leave anything behind on errors.
+    fn rollback(&self, data: &InternalData, count: usize, prev_result: Option<&Value>) {
+        for plugin in data.plugins.iter().take(count).rev() {
+            if let Err(e) = self.exec_cni_plugin(data, plugin, CNI_COMMAND_DEL, prev_result) {
+                debug!("failed to delete cni plugin after setup error: {e}");
+            }
+        }
+    }
+
+    fn result_file(&self) -> PathBuf {
+        cni_result_file(
+            self.info.config_dir,
+            &self.info.network.id,
+            self.info.container_id,
+        )
+    }
+
+    fn get_data(&self) -> NetavarkResult<&InternalData> {
+        self.data
+            .as_ref()
+            .ok_or_else(|| NetavarkError::msg("must call validate() before setup()"))
+    }
+
+    fn exec_cni_plugin(
+        &self,
+        data: &InternalData,
+        plugin: &CniPlugin,
+        command: &str,
+        prev_result: Option<&Value>,
+    ) -> NetavarkResult<Value> {
+        let plugin_type = plugin.conf["type"].as_str().unwrap_or_default();
+        // DEL only gets the result of the ADD since CNI 0.4.0
+        let conf_prev_result = match command {
+            CNI_COMMAND_DEL if data.cni_version.starts_with("0.3.") => None,
+            _ => prev_result,
+        };
+        let conf = make_plugin_conf(
+            &plugin.conf,
+            &data.name,
+            &data.cni_version,
+            conf_prev_result,
+            self.info.port_mappings,
+        );
+        let input = serde_json::to_vec(&conf)?;
+
+        let cni_path = self
+            .plugin_directories
+            .iter()
+            .flatten()
+            .map(|dir| dir.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(":");
+        let mut cmd = Command::new(&plugin.path);
+        cmd.env("CNI_COMMAND", command)
+            .env("CNI_CONTAINERID", self.info.container_id)
+            .env("CNI_NETNS", self.info.netns_path)
+            .env("CNI_IFNAME", &self.info.per_network_opts.interface_name)
+            .env("CNI_PATH", cni_path);
+
+        let timeout = get_plugin_timeout(&self.info.network.options)?;
+        let (rc, buffer) = run_plugin(&mut cmd, Some(&input), timeout)
+            .wrap(format!("cni plugin \"{plugin_type}\" failed"))?;
+        if rc != 0 {
+            return Err(match serde_json::from_slice::<CniError>(&buffer) {
+                Ok(err) if err.details.is_empty() => NetavarkError::msg(format!(
+                    "cni plugin \"{plugin_type}\" failed: {} (code {})",
+                    err.msg, err.code
+                )),
+                Ok(err) => NetavarkError::msg(format!(
+                    "cni plugin \"{plugin_type}\" failed: {}: {} (code {})",
+                    err.msg, err.details, err.code
+                )),
+                Err(_) => NetavarkError::msg(format!(
+                    "cni plugin \"{plugin_type}\" failed: exit code {rc}"
+                )),
+            });
+        }
+
+        // DEL does not return a result, ADD must but be lenient with plugins
+        // that print nothing and pass the previous result on
+        if command == CNI_COMMAND_DEL || buffer.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(prev_result.cloned().unwrap_or(Value::Null));
+        }
+        Ok(wrap!(
+            serde_json::from_slice(&buffer),
+            format!("parse result of cni plugin \"{plugin_type}\"")
+        )?)
+    }
+}
+
+/// The network name, cni version and plugin configs of a CNI config list.
+type CniConfig = (String, String, Vec<Map<String, Value>>);
+
+/// Split a CNI config list, or a single plugin config, into the network name,
+/// cni version and the plugin configs.
+fn parse_cni_config(config: Value) -> NetavarkResult<CniConfig> {
+    let Value::Object(mut config) = config else {
+        return Err(NetavarkError::msg("cni config must be a JSON object"));
+    };
+    let name = match config.get("name") {
+        Some(Value::String(name)) => name.clone(),
+        _ => return Err(NetavarkError::msg("cni config has no name")),
+    };
+    let cni_version = match config.get("cniVersion") {
+        Some(Value::String(version)) => version.clone(),
+        _ => return Err(NetavarkError::msg("cni config has no cniVersion")),
+    };
+    if !CNI_VERSIONS.contains(&cni_version.as_str()) {
+        return Err(NetavarkError::msg(format!(
+            "cni version {cni_version} is not supported, netavark supports {}",
+            CNI_VERSIONS.join(", ")
+        )));
+    }
+
+    let confs = match config.remove("plugins") {
+        Some(Value::Array(plugins)) => plugins
+            .into_iter()
+            .map(|plugin| match plugin {
+                Value::Object(conf) => Ok(conf),
+                _ => Err(NetavarkError::msg(
+                    "cni plugin config must be a JSON object",
+                )),
+            })
+            .collect::<NetavarkResult<Vec<_>>>()?,
+        Some(_) => return Err(NetavarkError::msg("cni config plugins must be a list")),
+        // a single plugin config
+        None => vec![config],
+    };
+    if confs.is_empty() {
+        return Err(NetavarkError::msg("cni config has no plugins"));
+    }
+    for conf in &confs {
+        if !matches!(conf.get("type"), Some(Value::String(t)) if !t.is_empty()) {
+            return Err(NetavarkError::msg("cni plugin config has no type"));
+        }
+    }
+    Ok((name, cni_version, confs))
+}
+
+fn cni_result_file(config_dir: &Path, network_id: &str, container_id: &str) -> PathBuf {
+    config_dir
+        .join(CNI_RESULT_DIR)
+        .join(format!("{network_id}_{container_id}"))
+}
+
+fn read_cni_result(path: &Path) -> NetavarkResult<Option<Value>> {
+    match File::open(path) {
+        Ok(f) => Ok(Some(wrap!(
+            serde_json::from_reader(f),
+            "parse cni result file"
+        )?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(NetavarkError::wrap(
+            format!("open cni result file {path:?}"),
+            e.into(),
+        )),
+    }
+}
+
+fn write_cni_result(path: &Path, result: &Value) -> NetavarkResult<()> {
+    if let Some(dir) = path.parent() {
+        wrap!(fs::create_dir_all(dir), "create cni result directory")?;
+    }
+    let f = wrap!(File::create(path), "create cni result file")?;
+    serde_json::to_writer(f, result)?;
+    Ok(())
+}
+
+/// Build the stdin config of a single plugin invocation.
+fn make_plugin_conf(
+    conf: &Map<String, Value>,
+    name: &str,
+    cni_version: &str,
+    prev_result: Option<&Value>,
+    port_mappings: &Option<Vec<PortMapping>>,
+) -> Value {
+    let mut conf = conf.clone();
+    conf.insert("name".to_string(), json!(name));
+    conf.insert("cniVersion".to_string(), json!(cni_version));
+    if let Some(result) = prev_result {
+        conf.insert("prevResult".to_string(), result.clone());
+    }
+
+    // only plugins which declare the capability get the port mappings
+    let wants_ports = conf
+        .get("capabilities")
+        .and_then(|caps| caps.get("portMappings"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if let (true, Some(ports)) = (wants_ports, port_mappings) {
+        let mut mappings = Vec::new();
+        for port in ports {
+            for protocol in port.protocol.split(',') {
+                for i in 0..port.range.max(1) {
+                    let mut mapping = json!({
+                        "hostPort": port.host_port + i,
+                        "containerPort": port.container_port_for(i),
+                        "protocol": protocol,
+                    });
+                    if !port.host_ip.is_empty() {
+                        mapping["hostIP"] = json!(port.host_ip);
+                    }
+                    mappings.push(mapping);
+                }
+            }
+        }
+        conf.insert(
+            "runtimeConfig".to_string(),
+            json!({ "portMappings": mappings }),
+        );
+    }
+    Value::Object(conf)
+}
+
+/// Translate the CNI result into our status block, only container interfaces are
+/// reported. Addresses without an interface index belong to the container interface.
+fn cni_result_to_status_block(result: CniResult, interface_name: &str) -> StatusBlock {
+    let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
+    for iface in result.interfaces.iter().filter(|i| !i.sandbox.is_empty()) {
+        interfaces.insert(
+            iface.name.clone(),
+            NetInterface {
+                mac_address: iface.mac.clone(),
+                subnets: None,
+            },
+        );
+    }
+
+    for ip in result.ips {
+        let name = match ip.interface.and_then(|i| result.interfaces.get(i)) {
+            Some(iface) if iface.sandbox.is_empty() => continue,
+            Some(iface) => iface.name.clone(),
+            None => interface_name.to_string(),
+        };
+        let iface = interfaces.entry(name).or_insert_with(|| NetInterface {
+            mac_address: String::new(),
+            subnets: None,
+        });
+        iface.subnets.get_or_insert_with(Vec::new).push(NetAddress {
+            gateway: ip.gateway,
+            ipnet: ip.address,
+        });
+    }
+
+    StatusBlock {
+        dns_search_domains: Some(result.dns.search),
+        dns_server_ips: Some(result.dns.nameservers),
+        interfaces: Some(interfaces),
+        ntp_server_ips: None,
+        port_mappings: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cni_config() {
+        let config = json!({
+            "cniVersion": "1.0.0",
+            "name": "net",
+            "plugins": [
+                {"type": "bridge", "bridge": "cni0"},
+                {"type": "bandwidth", "capabilities": {"bandwidth": true}}
+            ]
+        });
+        let (name, version, confs) = parse_cni_config(config).unwrap();
+        assert_eq!(name, "net");
+        assert_eq!(version, "1.0.0");
+        assert_eq!(confs.len(), 2);
+        assert_eq!(confs[1]["type"], "bandwidth");
+
+        // single plugin config
+        let config = json!({"cniVersion": "0.4.0", "name": "net", "type": "tuning"});
+        let (_, _, confs) = parse_cni_config(config).unwrap();
+        assert_eq!(confs.len(), 1);
+        assert_eq!(confs[0]["type"], "tuning");
+
+        let config = json!({"cniVersion": "1.0.0", "name": "net", "plugins": [{"mtu": 1}]});
+        let err = parse_cni_config(config).unwrap_err();
+        assert_eq!(err.to_string(), "cni plugin config has no type");
+        let config = json!({"name": "net", "type": "tuning"});
+        let err = parse_cni_config(config).unwrap_err();
+        assert_eq!(err.to_string(), "cni config has no cniVersion");
+        let config = json!({"cniVersion": "0.2.0", "name": "net", "type": "tuning"});
+        let err = parse_cni_config(config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cni version 0.2.0 is not supported, netavark supports 0.3.0, 0.3.1, 0.4.0, 1.0.0, 1.1.0"
+        );
+    }
+
+    #[test]
+    fn test_cni_result_cache() {
+        let tmpdir = tempfile::Builder::new()
+            .prefix("cni-result")
+            .tempdir()
+            .unwrap();
+        let path = cni_result_file(tmpdir.path(), "net1", "ctr1");
+        assert!(read_cni_result(&path).unwrap().is_none());
+
+        let result = json!({"cniVersion": "1.0.0", "ips": [{"address": "10.22.0.5/16"}]});
+        write_cni_result(&path, &result).unwrap();
+        assert_eq!(read_cni_result(&path).unwrap(), Some(result));
+    }
+
+    #[test]
+    fn test_make_plugin_conf() {
+        let conf = json!({"type": "portmap", "capabilities": {"portMappings": true}});
+        let Value::Object(conf) = conf else {
+            unreachable!()
+        };
+        let ports = Some(vec![PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port: 8080,
+            protocol: "tcp,udp".to_string(),
+            range: 2,
+            container_range: None,
+        }]);
+        let prev = json!({"cniVersion": "1.0.0", "ips": []});
+        let conf = make_plugin_conf(&conf, "net", "1.0.0", Some(&prev), &ports);
+        assert_eq!(conf["name"], "net");
+        assert_eq!(conf["cniVersion"], "1.0.0");
+        assert_eq!(conf["prevResult"], prev);
+        let mappings = conf["runtimeConfig"]["portMappings"].as_array().unwrap();
+        assert_eq!(mappings.len(), 4);
+        assert_eq!(
+            mappings[1],
+            json!({"hostPort": 8081, "containerPort": 81, "protocol": "tcp"})
+        );
+        assert_eq!(mappings[2]["protocol"], "udp");
+
+        // no port mappings without the capability
+        let Value::Object(conf) = json!({"type": "tuning"}) else {
+            unreachable!()
+        };
+        let conf = make_plugin_conf(&conf, "net", "1.0.0", None, &ports);
+        assert!(conf.get("runtimeConfig").is_none());
+        assert!(conf.get("prevResult").is_none());
+    }
+
+    #[test]
+    fn test_cni_result_to_status_block() {
+        let result: CniResult = serde_json::from_value(json!({
+            "cniVersion": "1.0.0",
+            "interfaces": [
+                {"name": "cni0", "mac": "aa:bb:cc:dd:ee:00"},
+                {"name": "eth0", "mac": "aa:bb:cc:dd:ee:01", "sandbox": "/run/netns/ctr"}
+            ],
+            "ips": [
+                {"address": "10.22.0.1/16", "interface": 0},
+                {"address": "10.22.0.5/16", "gateway": "10.22.0.1", "interface": 1},
+                {"address": "fd00::5/64"}
+            ],
+            "dns": {"nameservers": ["10.22.0.1"], "search": ["example.com"]}
+        }))
+        .unwrap();
+        let status = cni_result_to_status_block(result, "eth0");
+        let interfaces = status.interfaces.unwrap();
+        assert_eq!(interfaces.len(), 1);
+        let eth0 = &interfaces["eth0"];
+        assert_eq!(eth0.mac_address, "aa:bb:cc:dd:ee:01");
+        let subnets = eth0.subnets.as_ref().unwrap();
+        assert_eq!(subnets.len(), 2);
+        assert_eq!(subnets[0].ipnet, "10.22.0.5/16".parse::<IpNet>().unwrap());
+        assert_eq!(subnets[0].gateway, Some("10.22.0.1".parse().unwrap()));
+        assert_eq!(subnets[1].ipnet, "fd00::5/64".parse::<IpNet>().unwrap());
+        assert_eq!(
+            status.dns_server_ips,
+            Some(vec!["10.22.0.1".parse().unwrap()])
+        );
+        assert_eq!(status.dns_search_domains, Some(vec!["example.com".into()]));
+    }
+}
@@ -11,6 +11,9 @@ pub const IPAM_NONE: &str = "none";
 pub const DRIVER_BRIDGE: &str = "bridge";
 pub const DRIVER_IPVLAN: &str = "ipvlan";
 pub const DRIVER_MACVLAN: &str = "macvlan";
+pub const DRIVER_CNI: &str = "cni";
+// drivers implemented by netavark, plugins with these names are never used
+pub const BUILTIN_DRIVERS: [&str; 4] = [DRIVER_BRIDGE, DRIVER_CNI, DRIVER_IPVLAN, DRIVER_MACVLAN];
 
 pub const OPTION_ISOLATE: &str = "isolate";
 pub const ISOLATE_OPTION_TRUE: &str = "true";
@@ -23,6 +26,31 @@ pub const OPTION_NO_DEFAULT_ROUTE: &str = "no_default_route";
 pub const OPTION_BCLIM: &str = "bclim";
 pub const OPTION_VRF: &str = "vrf";
 pub const OPTION_HOST_INTERFACE_NAME: &str = "host_interface_name";
+pub const OPTION_DHCP_SEND_HOSTNAME: &str = "dhcp_send_hostname";
+pub const OPTION_DHCP_CLIENT_ID: &str = "dhcp_client_id";
+pub const OPTION_ALLOW_PORT_CONFLICTS: &str = "allow_port_conflicts";
+pub const OPTION_LOG_DROPPED: &str = "log_dropped";
+pub const LOG_DROPPED_OPTION_LOG: &str = "log";
+pub const LOG_DROPPED_OPTION_NFLOG: &str = "nflog";
+pub const OPTION_SNAT_IPV4: &str = "snat_ipv4";
+pub const OPTION_SNAT_IPV6: &str = "snat_ipv6";
+pub const OPTION_SNAT_EXCLUDE: &str = "snat_exclude";
+pub const OPTION_LOCALHOST_FORWARDING: &str = "localhost_forwarding";
+pub const OPTION_HAIRPIN_MASQUERADE: &str = "hairpin_masquerade";
+pub const OPTION_ISOLATION_GROUPS: &str = "isolation_groups";
+pub const OPTION_PLUGIN_TIMEOUT: &str = "plugin_timeout";
+pub const OPTION_CNI_CONFIG: &str = "cni_config";
+
+// plugin timeout in seconds used when the network does not set plugin_timeout
+pub const PLUGIN_TIMEOUT_ENV: &str = "NETAVARK_PLUGIN_TIMEOUT";
+// exit code of netavark when a plugin got killed because it timed out
+pub const PLUGIN_TIMEOUT_EXIT_CODE: i32 = 124;
+// extension of the drop-in files in the plugin directories which register plugin daemon sockets
+pub const PLUGIN_SOCKET_DROP_IN_EXT: &str = "socket";
+// directory in the config dir which stores the services netavark set up for plugins
+pub const PLUGIN_STATE_DIR: &str = "plugins";
+// directory in the config dir which caches the CNI results of the cni driver
+pub const CNI_RESULT_DIR: &str = "cni-results";
 
 /// 100 is the default metric for most Linux networking tools.
 pub const DEFAULT_METRIC: u32 = 100;
@@ -116,8 +116,14 @@ pub fn get_ipam_addresses<'a>(
                 }
 
                 // Build up response information
+                let static_ip = static_ips.get(idx).ok_or_else(|| {
+                    Error::new(
+                        std::io::ErrorKind::Other,
+                        format!("no static ip provided for subnet {}", subnet.subnet),
+                    )
+                })?;
                 let container_address: ipnet::IpNet =
-                    match format!("{}/{}", static_ips[idx], subnet_mask_cidr).parse() {
+                    match format!("{}/{}", static_ip, subnet_mask_cidr).parse() {
                         Ok(i) => i,
                         Err(e) => {
                             return Err(Error::new(std::io::ErrorKind::Other, e));
@@ -310,7 +316,23 @@ pub fn open_netlink_sockets(
 ) -> NetavarkResult<(NamespaceOptions, NamespaceOptions)> {
     let netns = open_netlink_socket(netns_path).wrap("open container netns")?;
     let hostns = open_netlink_socket("/proc/self/ns/net").wrap("open host netns")?;
+    open_netlink_sockets_in(hostns, netns)
+}
+
+/// Like open_netlink_sockets() but with the given host netns instead of the
+/// one of the process.
+pub fn open_netlink_sockets_from(
+    hostns: File,
+    netns_path: &str,
+) -> NetavarkResult<(NamespaceOptions, NamespaceOptions)> {
+    let netns = open_netlink_socket(netns_path).wrap("open container netns")?;
+    open_netlink_sockets_in(hostns, netns)
+}
 
+fn open_netlink_sockets_in(
+    hostns: File,
+    netns: File,
+) -> NetavarkResult<(NamespaceOptions, NamespaceOptions)> {
     let host_socket = netlink::Socket::new().wrap("host netlink socket")?;
     exec_netns!(
         hostns.as_fd(),
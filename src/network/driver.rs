@@ -8,13 +8,16 @@ use std::{ffi::OsString, net::IpAddr, os::fd::BorrowedFd, path::Path};
 
 use super::{
     bridge::Bridge,
-    constants, netlink,
-    plugin::PluginDriver,
+    cni::Cni,
+    constants,
+    hooks::HookDriver,
+    netlink,
+    plugin::{PluginDriver, PluginTransport},
     types::{Network, PerNetworkOptions, PortMapping, StatusBlock},
     vlan::Vlan,
 };
-use std::os::unix::fs::PermissionsExt;
 
+#[derive(Clone, Copy)]
 pub struct DriverInfo<'a> {
     pub firewall: &'a dyn FirewallDriver,
     pub container_id: &'a String,
@@ -44,6 +47,11 @@ pub trait NetworkDriver {
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
     ) -> NetavarkResult<()>;
+    /// report the live state of the network configuration,
+    /// None if the driver cannot report it
+    fn status(&self) -> NetavarkResult<Option<StatusBlock>> {
+        Ok(None)
+    }
 
     /// return the network name
     fn network_name(&self) -> String;
@@ -53,26 +61,27 @@ pub fn get_network_driver<'a>(
     info: DriverInfo<'a>,
     plugins_directories: &Option<Vec<OsString>>,
 ) -> NetavarkResult<Box<dyn NetworkDriver + 'a>> {
-    match info.network.driver.as_str() {
-        constants::DRIVER_BRIDGE => Ok(Box::new(Bridge::new(info))),
-        constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => Ok(Box::new(Vlan::new(info))),
+    let driver: Box<dyn NetworkDriver + 'a> = match info.network.driver.as_str() {
+        constants::DRIVER_BRIDGE => Box::new(Bridge::new(info)),
+        constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => Box::new(Vlan::new(info)),
+        constants::DRIVER_CNI => Box::new(Cni::new(info, plugins_directories)),
 
-        name => {
-            if let Some(dirs) = plugins_directories {
-                for path in dirs.iter() {
-                    let path = Path::new(path).join(name);
-                    if let Ok(meta) = path.metadata() {
-                        if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
-                            return Ok(Box::new(PluginDriver::new(path, info)));
-                        }
-                    }
-                }
+        name => match PluginTransport::find(name, plugins_directories)? {
+            Some(transport) => Box::new(PluginDriver::new(transport, info)),
+            None => {
+                return Err(NetavarkError::Message(format!(
+                    "unknown network driver \"{}\"",
+                    info.network.driver
+                )))
             }
+        },
+    };
 
-            Err(NetavarkError::Message(format!(
-                "unknown network driver \"{}\"",
-                info.network.driver
-            )))
+    // hooks run around any driver
+    match &info.network.hooks {
+        Some(hooks) if !hooks.is_empty() => {
+            Ok(Box::new(HookDriver::new(driver, info, plugins_directories)))
         }
+        _ => Ok(driver),
     }
 }
@@ -0,0 +1,159 @@
+//! Hooks are executables which run after the setup of any network driver,
+//! they use the same protocol as plugins.
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use log::error;
+
+use crate::{
+    dns::aardvark::AardvarkEntry,
+    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+};
+
+use super::{
+    driver::{DriverInfo, NetworkDriver},
+    netlink,
+    plugin::{exec_plugin_command, find_plugin, get_plugin_timeout},
+    types::{self, StatusBlock},
+};
+
+pub struct HookDriver<'a> {
+    driver: Box<dyn NetworkDriver + 'a>,
+    info: DriverInfo<'a>,
+    plugin_directories: Option<Vec<OsString>>,
+    hooks: Vec<PathBuf>,
+}
+
+impl<'a> HookDriver<'a> {
+    pub fn new(
+        driver: Box<dyn NetworkDriver + 'a>,
+        info: DriverInfo<'a>,
+        plugin_directories: &Option<Vec<OsString>>,
+    ) -> Self {
+        HookDriver {
+            driver,
+            info,
+            plugin_directories: plugin_directories.clone(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl NetworkDriver for HookDriver<'_> {
+    fn validate(&mut self) -> NetavarkResult<()> {
+        self.hooks = self.find_hooks()?;
+        self.driver.validate()
+    }
+
+    fn setup(
+        &self,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let (host, netns) = netlink_sockets;
+        let (status, aardvark_entry) = self.driver.setup((&mut *host, &mut *netns))?;
+
+        for (i, hook) in self.hooks.iter().enumerate() {
+            if let Err(err) = self.exec_hook(hook, "setup", Some(&status)) {
+                // roll back like a failed driver setup so the caller
+                // only has to tear down the previous networks
+                for hook in self.hooks.iter().take(i).rev() {
+                    if let Err(e) = self.exec_hook(hook, "teardown", None) {
+                        error!("failed to teardown hook after setup failed: {e}");
+                    }
+                }
+                if let Err(e) = self.driver.teardown((host, netns)) {
+                    error!("failed to teardown network after hook failed: {e}");
+                }
+                return Err(err);
+            }
+        }
+        Ok((status, aardvark_entry))
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<()> {
+        let mut error_list = NetavarkErrorList::new();
+        // teardown does not call validate()
+        match self.find_hooks() {
+            Ok(hooks) => {
+                for hook in hooks.iter().rev() {
+                    if let Err(err) = self.exec_hook(hook, "teardown", None) {
+                        error_list.push(err);
+                    }
+                }
+            }
+            Err(err) => error_list.push(err),
+        }
+        if let Err(err) = self.driver.teardown(netlink_sockets) {
+            error_list.push(err);
+        }
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> NetavarkResult<Option<StatusBlock>> {
+        self.driver.status()
+    }
+
+    fn network_name(&self) -> String {
+        self.driver.network_name()
+    }
+}
+
+impl HookDriver<'_> {
+    /// Hooks are either absolute paths or searched in the plugin directories.
+    fn find_hooks(&self) -> NetavarkResult<Vec<PathBuf>> {
+        self.info
+            .network
+            .hooks
+            .iter()
+            .flatten()
+            .map(|hook| {
+                if Path::new(hook).is_absolute() {
+                    return Ok(PathBuf::from(hook));
+                }
+                find_plugin(hook, &self.plugin_directories).ok_or_else(|| {
+                    NetavarkError::msg(format!(
+                        "hook \"{hook}\" not found in the plugin directories"
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    fn exec_hook(
+        &self,
+        hook: &Path,
+        subcommand: &str,
+        status: Option<&StatusBlock>,
+    ) -> NetavarkResult<()> {
+        let input = types::NetworkHookExec {
+            exec: types::NetworkPluginExec {
+                container_name: self.info.container_name.clone(),
+                container_id: self.info.container_id.clone(),
+                port_mappings: self.info.port_mappings.clone(),
+                network: self.info.network.clone(),
+                network_options: self.info.per_network_opts.clone(),
+            },
+            status: status.cloned(),
+        };
+        let timeout = get_plugin_timeout(&self.info.network.options)?;
+        exec_plugin_command(
+            hook,
+            &[subcommand, self.info.netns_path],
+            Some(&input),
+            timeout,
+        )
+        .wrap(format!(
+            "hook {:?} failed",
+            hook.file_name().unwrap_or_default()
+        ))?;
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use super::netlink;
 use crate::network::types;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 /// Teardown contains options for tearing down behind a container
 #[derive(Debug)]
@@ -11,7 +11,7 @@ pub struct TeardownPortForward<'a> {
 }
 
 /// SetupNetwork contains options for setting up a container
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SetupNetwork {
     /// subnets used for this network
     pub subnets: Option<Vec<ipnet::IpNet>>,
@@ -24,8 +24,47 @@ pub struct SetupNetwork {
     pub network_hash_name: String,
     /// isolation determines whether the network can communicate with others outside of its interface
     pub isolation: IsolateOption,
+    /// isolated networks which share one of these groups can still communicate with each other
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub isolation_groups: Vec<String>,
     /// port used for the dns server
     pub dns_port: u16,
+    /// log packets dropped by the isolation rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dropped: Option<DropLog>,
+    /// source address used instead of masquerade for outgoing ipv4 traffic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snat_ipv4: Option<Ipv4Addr>,
+    /// source address used instead of masquerade for outgoing ipv6 traffic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snat_ipv6: Option<Ipv6Addr>,
+    /// destinations for which the source address is not translated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snat_exclude: Vec<ipnet::IpNet>,
+    /// masquerade connections from the network to its own published ports
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub hairpin_masquerade: bool,
+}
+
+impl SetupNetwork {
+    /// Get the SNAT source address for traffic from the subnet,
+    /// None means the traffic is masqueraded.
+    pub fn snat_address(&self, subnet: &ipnet::IpNet) -> Option<IpAddr> {
+        match subnet {
+            ipnet::IpNet::V4(_) => self.snat_ipv4.map(IpAddr::V4),
+            ipnet::IpNet::V6(_) => self.snat_ipv6.map(IpAddr::V6),
+        }
+    }
+
+    /// Get the excluded destinations with the same address family as the subnet.
+    pub fn snat_exclude_for<'a>(
+        &'a self,
+        subnet: &'a ipnet::IpNet,
+    ) -> impl Iterator<Item = &'a ipnet::IpNet> {
+        self.snat_exclude
+            .iter()
+            .filter(move |net| net.addr().is_ipv4() == subnet.addr().is_ipv4())
+    }
 }
 
 #[derive(Debug)]
@@ -68,6 +107,20 @@ pub struct PortForwardConfigGeneric<Ports, IpAddresses> {
     pub dns_port: u16,
     /// dns servers IPs where forwarding rule to port 53 from dns_port are necessary
     pub dns_server_ips: IpAddresses,
+    /// forward the ports for connections to the localhost address
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub localhost_forwarding: bool,
+    /// masquerade connections from the network to its own published ports
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub hairpin_masquerade: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
 }
 
 // Some trickery to define two struct one with references and one with owned data,
@@ -92,6 +145,8 @@ impl<'a> From<&'a PortForwardConfigOwned> for PortForwardConfig<'a> {
             subnet_v6: p.subnet_v6,
             dns_port: p.dns_port,
             dns_server_ips: &p.dns_server_ips,
+            localhost_forwarding: p.localhost_forwarding,
+            hairpin_masquerade: p.hairpin_masquerade,
         }
     }
 }
@@ -117,3 +172,12 @@ pub enum IsolateOption {
     Normal,
     Never,
 }
+
+// DropLog selects how packets dropped by the isolation rules are logged
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DropLog {
+    /// log to the kernel log
+    Log,
+    /// send to nflog group 0
+    Nflog,
+}
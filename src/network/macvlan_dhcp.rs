@@ -7,7 +7,23 @@ use std::str::FromStr;
 use crate::dhcp_proxy::lib::g_rpc::NetworkConfig;
 use crate::dhcp_proxy::proxy_conf::DEFAULT_UDS_PATH;
 
-pub type DhcpLeaseInfo = (Vec<NetAddress>, Option<Vec<IpAddr>>, Option<Vec<String>>);
+/// Network information from the DHCP lease that is returned in the StatusBlock.
+#[derive(Debug)]
+pub struct DhcpLeaseInfo {
+    pub subnets: Vec<NetAddress>,
+    pub dns_servers: Option<Vec<IpAddr>>,
+    pub domain_name: Option<Vec<String>>,
+    pub ntp_servers: Option<Vec<IpAddr>>,
+}
+
+/// Optional client information sent to the dhcp server, empty strings are not sent.
+#[derive(Debug, Default)]
+pub struct DhcpClientOptions {
+    /// hostname of the container (option 12)
+    pub host_name: String,
+    /// client identifier (option 61), the server uses the mac address if it is empty
+    pub client_id: String,
+}
 
 /// dhcp performs the connection to the nv-proxy over grpc where it
 /// requests it to perform a lease via the host's network interface
@@ -20,6 +36,8 @@ pub type DhcpLeaseInfo = (Vec<NetAddress>, Option<Vec<IpAddr>>, Option<Vec<Strin
 /// * `container_network_interface`: container network interface (eth0)
 /// * `ns_path`: path to the container netns
 /// * `container_macvlan_mac`: mac address of the container network interface above.
+/// * `client_options`: hostname and client identifier to send.
+/// * `mtu`: mtu option of the network, the mtu of the lease is only used if it is 0.
 ///
 /// returns: Result<Vec<NetAddress, Global>, NetavarkError>
 ///
@@ -33,17 +51,20 @@ pub fn get_dhcp_lease(
     container_network_interface: &str,
     ns_path: &str,
     container_macvlan_mac: &str,
+    client_options: &DhcpClientOptions,
+    mtu: u32,
 ) -> NetavarkResult<DhcpLeaseInfo> {
     let nvp_config = NetworkConfig {
         host_iface: host_network_interface.to_string(),
-        // TODO add in domain name support
+        // only overrides the domain name of the lease, the FQDN option (81) is not sent
         domain_name: "".to_string(),
-        //  TODO add in host name support
-        host_name: "".to_string(),
+        host_name: client_options.host_name.clone(),
         version: 0,
         ns_path: ns_path.to_string(),
         container_iface: container_network_interface.to_string(),
         container_mac_addr: container_macvlan_mac.to_string(),
+        client_id: client_options.client_id.clone(),
+        mtu,
     };
     let lease = match tokio::task::LocalSet::new().block_on(
         match &tokio::runtime::Builder::new_current_thread()
@@ -76,19 +97,8 @@ pub fn get_dhcp_lease(
         None
     };
 
-    let dns_servers = if !lease.dns_servers.is_empty() {
-        let servers = lease
-            .dns_servers
-            .into_iter()
-            .map(|d| match IpAddr::from_str(&d) {
-                Ok(d) => Ok(d),
-                Err(e) => Err(NetavarkError::msg(format!("bad dns address: {e}"))),
-            })
-            .collect::<Result<Vec<IpAddr>, NetavarkError>>()?;
-        Some(servers)
-    } else {
-        None
-    };
+    let dns_servers = parse_ip_list(lease.dns_servers, "dns")?;
+    let ntp_servers = parse_ip_list(lease.ntp_servers, "ntp")?;
     let domain_name = if !lease.domain_name.is_empty() {
         Some(vec![lease.domain_name])
     } else {
@@ -114,7 +124,27 @@ pub fn get_dhcp_lease(
         ipnet: ip,
     };
 
-    Ok((vec![ns], dns_servers, domain_name))
+    Ok(DhcpLeaseInfo {
+        subnets: vec![ns],
+        dns_servers,
+        domain_name,
+        ntp_servers,
+    })
+}
+
+/// Parse a list of ip addresses from the lease, returns None if the list is empty.
+fn parse_ip_list(ips: Vec<String>, kind: &str) -> NetavarkResult<Option<Vec<IpAddr>>> {
+    if ips.is_empty() {
+        return Ok(None);
+    }
+    let ips = ips
+        .into_iter()
+        .map(|d| match IpAddr::from_str(&d) {
+            Ok(d) => Ok(d),
+            Err(e) => Err(NetavarkError::msg(format!("bad {kind} address: {e}"))),
+        })
+        .collect::<Result<Vec<IpAddr>, NetavarkError>>()?;
+    Ok(Some(ips))
 }
 
 pub fn release_dhcp_lease(
@@ -122,17 +152,19 @@ pub fn release_dhcp_lease(
     container_network_interface: &str,
     ns_path: &str,
     container_macvlan_mac: &str,
+    client_options: &DhcpClientOptions,
 ) -> NetavarkResult<()> {
     let nvp_config = NetworkConfig {
         host_iface: host_network_interface.to_string(),
-        // TODO add in domain name support
+        // only overrides the domain name of the lease, the FQDN option (81) is not sent
         domain_name: "".to_string(),
-        //  TODO add in host name support
-        host_name: "".to_string(),
+        host_name: client_options.host_name.clone(),
         version: 0,
         ns_path: ns_path.to_string(),
         container_iface: container_network_interface.to_string(),
         container_mac_addr: container_macvlan_mac.to_string(),
+        client_id: client_options.client_id.clone(),
+        mtu: 0,
     };
     match tokio::task::LocalSet::new().block_on(
         match &tokio::runtime::Builder::new_current_thread()
@@ -11,13 +11,16 @@ use crate::{
     wrap,
 };
 pub mod bridge;
+pub mod cni;
 pub mod constants;
 pub mod core_utils;
 pub mod driver;
+pub mod hooks;
 pub mod internal_types;
 mod macvlan_dhcp;
 pub mod netlink;
 pub mod plugin;
+pub mod schema;
 pub mod vlan;
 
 impl types::NetworkOptions {
@@ -422,6 +422,22 @@ impl Socket {
         Ok(())
     }
 
+    pub fn set_mtu(&mut self, id: LinkID, mtu: u32) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+
+        match id {
+            LinkID::ID(id) => msg.header.index = id,
+            LinkID::Name(name) => msg.attributes.push(LinkAttribute::IfName(name)),
+        }
+
+        msg.attributes.push(LinkAttribute::Mtu(mtu));
+
+        let result = self.make_netlink_request(RouteNetlinkMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     fn make_netlink_request(
         &mut self,
         msg: RouteNetlinkMessage,
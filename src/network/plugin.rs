@@ -1,70 +1,233 @@
 use std::{
-    io::Read,
-    path::PathBuf,
-    process::{Command, Stdio},
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown},
+    os::unix::fs::PermissionsExt,
+    os::unix::net::UnixStream,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
+use ipnet::IpNet;
+use log::error;
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use serde::Serialize;
+
 use crate::{
     dns::aardvark::AardvarkEntry,
-    error::{ErrorWrap, JsonError, NetavarkError, NetavarkResult},
+    error::{ErrorWrap, JsonError, NetavarkError, NetavarkErrorList, NetavarkResult},
+    firewall::{
+        iptables::MAX_HASH_SIZE,
+        state::{remove_fw_config, write_port_fw_config},
+    },
+    plugin::{API_VERSION, CAPABILITY_STATUS, CAPABILITY_UPDATE},
     wrap,
 };
 
 use super::{
+    constants::{
+        OPTION_PLUGIN_TIMEOUT, PLUGIN_SOCKET_DROP_IN_EXT, PLUGIN_STATE_DIR, PLUGIN_TIMEOUT_ENV,
+        PLUGIN_TIMEOUT_EXIT_CODE, PODMAN_DEFAULT_SEARCH_DOMAIN,
+    },
+    core_utils::{parse_option, CoreUtils},
     driver::{DriverInfo, NetworkDriver},
+    internal_types::{PortForwardConfig, TeardownPortForward},
     types,
 };
 
+/// Oldest plugin API major version netavark still talks to.
+const MIN_API_MAJOR_VERSION: u64 = 1;
+
+/// Used for port forwarding when the plugin did not request dns.
+static NO_DNS_SERVERS: Vec<IpAddr> = Vec::new();
+
+/// How often we check if a plugin with a timeout exited.
+const PLUGIN_WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The info reported by a plugin.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PluginInfo {
+    pub version: String,
+    pub api_version: String,
+    /// Optional subcommands implemented by the plugin, API version 1 plugins have none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    /// Any other fields the plugin reports.
+    #[serde(flatten)]
+    pub extra_info: HashMap<String, serde_json::Value>,
+}
+
+impl PluginInfo {
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// How netavark talks to a plugin.
+#[derive(Clone, Debug)]
+pub enum PluginTransport {
+    /// Exec the plugin binary for every command.
+    Exec(PathBuf),
+    /// Send the commands to a plugin daemon listening on this unix socket.
+    Socket(PathBuf),
+}
+
+impl PluginTransport {
+    /// Find the plugin for the driver name in the plugin directories. In each
+    /// directory an executable with the name wins over a `<name>.socket` drop-in
+    /// file, which contains the socket path of a plugin daemon.
+    pub fn find(
+        name: &str,
+        plugins_directories: &Option<Vec<OsString>>,
+    ) -> NetavarkResult<Option<Self>> {
+        for dir in plugins_directories.iter().flatten() {
+            let dir = Path::new(dir);
+            if let Some(path) = plugin_executable(dir, name) {
+                return Ok(Some(PluginTransport::Exec(path)));
+            }
+            let drop_in = dir.join(format!("{name}.{PLUGIN_SOCKET_DROP_IN_EXT}"));
+            let content = match fs::read_to_string(&drop_in) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(NetavarkError::wrap(
+                        format!("read plugin socket file {drop_in:?}"),
+                        e.into(),
+                    ))
+                }
+            };
+            let socket = PathBuf::from(content.trim());
+            if !socket.is_absolute() {
+                return Err(NetavarkError::msg(format!(
+                    "plugin socket file {drop_in:?} must contain an absolute path"
+                )));
+            }
+            return Ok(Some(PluginTransport::Socket(socket)));
+        }
+        Ok(None)
+    }
+
+    /// Run the plugin command, args are the arguments of the plugin
+    /// executable starting with the subcommand.
+    pub fn call<T: Serialize>(
+        &self,
+        args: &[&str],
+        input: Option<&T>,
+        timeout: Option<Duration>,
+    ) -> NetavarkResult<Vec<u8>> {
+        match self {
+            PluginTransport::Exec(path) => exec_plugin_command(path, args, input, timeout),
+            PluginTransport::Socket(path) => socket_plugin_command(path, args, input, timeout),
+        }
+    }
+}
+
 pub struct PluginDriver<'a> {
-    path: PathBuf,
+    transport: PluginTransport,
     info: DriverInfo<'a>,
+    plugin_info: Option<PluginInfo>,
 }
 
 impl<'a> PluginDriver<'a> {
-    pub fn new(path: PathBuf, info: DriverInfo<'a>) -> Self {
-        PluginDriver { path, info }
+    pub fn new(transport: PluginTransport, info: DriverInfo<'a>) -> Self {
+        PluginDriver {
+            transport,
+            info,
+            plugin_info: None,
+        }
     }
 }
 
 impl NetworkDriver for PluginDriver<'_> {
     fn validate(&mut self) -> NetavarkResult<()> {
-        // Note the the plugin API does not implement validate().
-        // This would just add an extra fork()/exec() overhead which seems
-        // undesirable since most times it will work without errors.
+        // Note the the plugin API does not implement validate(), instead we use
+        // the info handshake to make sure we speak the API version of the plugin
+        // and to learn which optional subcommands it supports.
+        let timeout = get_plugin_timeout(&self.info.network.options)?;
+        let plugin_info = get_plugin_info(&self.transport, timeout).wrap(self.error_context())?;
+        self.plugin_info = Some(plugin_info);
         Ok(())
     }
 
     fn setup(
         &self,
-        _netlink_sockets: (&mut super::netlink::Socket, &mut super::netlink::Socket),
-    ) -> NetavarkResult<(types::StatusBlock, Option<AardvarkEntry>)> {
-        let result = self.exec_plugin(true, self.info.netns_path).wrap(format!(
-            "plugin {:?} failed",
-            &self.path.file_name().unwrap_or_default()
-        ))?;
-        // The unwrap should be safe, only if the exec_plugin has a bug this
-        // could fail, in which case the test should catch it.
-        Ok((result.unwrap(), None))
+        netlink_sockets: (&mut super::netlink::Socket, &mut super::netlink::Socket),
+    ) -> NetavarkResult<(types::StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let buffer = self
+            .exec_plugin("setup", self.info.netns_path)
+            .wrap(self.error_context())?;
+        let plugin_status: types::PluginStatusBlock = serde_json::from_slice(&buffer)?;
+        let mut status = plugin_status.status;
+        let services = match plugin_status.netavark {
+            Some(services) if services.dns || services.port_forward => services,
+            _ => return Ok((status, None)),
+        };
+
+        match self.setup_services(&services, &mut status) {
+            Ok(aardvark_entry) => Ok((status, aardvark_entry)),
+            Err(err) => {
+                // the plugin did its setup so we must undo it
+                if let Err(e) = self.teardown(netlink_sockets) {
+                    error!("failed to teardown plugin after netavark services failed: {e}");
+                }
+                Err(err)
+            }
+        }
     }
 
     fn teardown(
         &self,
         _netlink_sockets: (&mut super::netlink::Socket, &mut super::netlink::Socket),
     ) -> NetavarkResult<()> {
-        self.exec_plugin(false, self.info.netns_path).wrap(format!(
-            "plugin {:?} failed",
-            &self.path.file_name().unwrap_or_default()
-        ))?;
+        let mut error_list = NetavarkErrorList::new();
+        if let Err(err) = self.teardown_services() {
+            error_list.push(err);
+        }
+        if let Err(err) = self
+            .exec_plugin("teardown", self.info.netns_path)
+            .wrap(self.error_context())
+        {
+            error_list.push(err);
+        }
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
         Ok(())
     }
 
+    fn status(&self) -> NetavarkResult<Option<types::StatusBlock>> {
+        match &self.plugin_info {
+            Some(plugin_info) if plugin_info.supports(CAPABILITY_STATUS) => {}
+            _ => return Ok(None),
+        }
+        let buffer = self
+            .exec_plugin("status", self.info.netns_path)
+            .wrap(self.error_context())?;
+        let status = serde_json::from_slice(&buffer)?;
+        Ok(Some(status))
+    }
+
     fn network_name(&self) -> String {
         self.info.network.name.clone()
     }
 }
 
 impl PluginDriver<'_> {
-    fn exec_plugin(&self, setup: bool, netns: &str) -> NetavarkResult<Option<types::StatusBlock>> {
+    fn error_context(&self) -> String {
+        format!("plugin {:?} failed", self.info.network.driver)
+    }
+
+    fn exec_plugin(&self, subcommand: &str, netns: &str) -> NetavarkResult<Vec<u8>> {
         // problem we always need to clone since you can only deserialize owned data,
         // it is not a problem here but for the plugin it is required.
         // If performance becomes a concern we could use two types for it but the
@@ -77,52 +240,846 @@ impl PluginDriver<'_> {
             network_options: self.info.per_network_opts.clone(),
         };
 
-        let mut child = Command::new(&self.path)
-            .arg(if setup { "setup" } else { "teardown" })
-            .arg(netns)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?;
+        let timeout = get_plugin_timeout(&self.info.network.options)?;
+        self.transport
+            .call(&[subcommand, netns], Some(&input), timeout)
+    }
+
+    /// Set up the netavark services requested by the plugin for the addresses
+    /// in its status block, the state is stored so teardown can undo them.
+    fn setup_services(
+        &self,
+        services: &types::PluginServices,
+        status: &mut types::StatusBlock,
+    ) -> NetavarkResult<Option<AardvarkEntry<'_>>> {
+        let mut container_addresses = Vec::new();
+        let mut gateways = Vec::new();
+        for interface in status.interfaces.iter().flat_map(|i| i.values()) {
+            for subnet in interface.subnets.iter().flatten() {
+                container_addresses.push(subnet.ipnet);
+                if let Some(gw) = subnet.gateway {
+                    gateways.push(gw);
+                }
+            }
+        }
+
+        if services.dns {
+            if !self.info.network.dns_enabled {
+                return Err(NetavarkError::msg(
+                    "plugin requested dns but the network does not have dns enabled",
+                ));
+            }
+            if gateways.is_empty() {
+                return Err(NetavarkError::msg(
+                    "plugin requested dns but did not report a gateway for aardvark-dns to listen on",
+                ));
+            }
+        }
+
+        let state = PluginServiceState {
+            dns: services.dns,
+            port_forward: services.port_forward,
+            container_addresses,
+            gateways,
+        };
+        state.write(&self.state_file())?;
+
+        if state.port_forward {
+            let spf = self.get_port_forward_conf(&state);
+            if !self.info.rootless {
+                write_port_fw_config(
+                    self.info.config_dir,
+                    &self.info.network.id,
+                    self.info.container_id,
+                    self.info.firewall.driver_name(),
+                    &spf,
+                )?;
+            }
+            self.info.firewall.setup_port_forward(spf)?;
+        }
+
+        if !state.dns {
+            return Ok(None);
+        }
+
+        let _ = status.dns_server_ips.insert(state.gateways.clone());
+        let _ = status
+            .dns_search_domains
+            .insert(vec![PODMAN_DEFAULT_SEARCH_DOMAIN.to_string()]);
+
+        let mut ipv4 = Vec::new();
+        let mut ipv6 = Vec::new();
+        for ipnet in &state.container_addresses {
+            match ipnet.addr() {
+                IpAddr::V4(v4) => ipv4.push(v4),
+                IpAddr::V6(v6) => ipv6.push(v6),
+            }
+        }
+        let mut names = vec![self.info.container_name.to_string()];
+        if let Some(aliases) = &self.info.per_network_opts.aliases {
+            names.extend(aliases.clone());
+        }
+        Ok(Some(AardvarkEntry {
+            network_name: &self.info.network.name,
+            container_id: self.info.container_id,
+            network_gateways: state.gateways,
+            network_dns_servers: &self.info.network.network_dns_servers,
+            container_ips_v4: ipv4,
+            container_ips_v6: ipv6,
+            container_names: names,
+            container_dns_servers: self.info.container_dns_servers,
+            is_internal: self.info.network.internal,
+        }))
+    }
+
+    /// Undo the netavark services set up for the plugin, a missing state file
+    /// means the plugin did not request any.
+    fn teardown_services(&self) -> NetavarkResult<()> {
+        let state_file = self.state_file();
+        let state = match PluginServiceState::read(&state_file)? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        match fs::remove_file(&state_file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("remove plugin state file {state_file:?}"),
+                    e.into(),
+                ))
+            }
+        }
+
+        if state.port_forward {
+            if !self.info.rootless {
+                remove_fw_config(
+                    self.info.config_dir,
+                    &self.info.network.id,
+                    self.info.container_id,
+                    false,
+                )?;
+            }
+            let spf = self.get_port_forward_conf(&state);
+            let tpf = TeardownPortForward {
+                config: spf,
+                complete_teardown: !network_has_plugin_state(
+                    self.info.config_dir,
+                    &self.info.network.id,
+                )?,
+            };
+            self.info.firewall.teardown_port_forward(tpf)?;
+        }
+        Ok(())
+    }
+
+    fn get_port_forward_conf<'b>(&'b self, state: &'b PluginServiceState) -> PortForwardConfig<'b> {
+        let mut spf = PortForwardConfig {
+            container_id: self.info.container_id.clone(),
+            network_id: self.info.network.id.clone(),
+            port_mappings: self.info.port_mappings,
+            network_name: self.info.network.name.clone(),
+            network_hash_name: CoreUtils::create_network_hash(
+                &self.info.network.name,
+                MAX_HASH_SIZE,
+            ),
+            container_ip_v4: None,
+            subnet_v4: None,
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: self.info.dns_port,
+            dns_server_ips: if state.dns {
+                &state.gateways
+            } else {
+                &NO_DNS_SERVERS
+            },
+            // the plugin owns the interfaces so we cannot set route_localnet on them
+            localhost_forwarding: false,
+            hairpin_masquerade: true,
+        };
+        // like bridge only the first address of each family gets the ports
+        for net in &state.container_addresses {
+            match net {
+                IpNet::V4(_) if spf.container_ip_v4.is_none() => {
+                    spf.container_ip_v4 = Some(net.addr());
+                    spf.subnet_v4 = Some(net.trunc());
+                }
+                IpNet::V6(_) if spf.container_ip_v6.is_none() => {
+                    spf.container_ip_v6 = Some(net.addr());
+                    spf.subnet_v6 = Some(net.trunc());
+                }
+                _ => {}
+            }
+        }
+        spf
+    }
+
+    fn state_file(&self) -> PathBuf {
+        plugin_state_file(
+            self.info.config_dir,
+            &self.info.network.id,
+            self.info.container_id,
+        )
+    }
+}
+
+/// The netavark services set up for a plugin network of a container.
+#[derive(Debug, Serialize, Deserialize)]
+struct PluginServiceState {
+    dns: bool,
+    port_forward: bool,
+    container_addresses: Vec<IpNet>,
+    gateways: Vec<IpAddr>,
+}
+
+impl PluginServiceState {
+    fn read(path: &Path) -> NetavarkResult<Option<Self>> {
+        match File::open(path) {
+            Ok(f) => Ok(Some(wrap!(
+                serde_json::from_reader(f),
+                "parse plugin state file"
+            )?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(NetavarkError::wrap(
+                format!("open plugin state file {path:?}"),
+                e.into(),
+            )),
+        }
+    }
+
+    fn write(&self, path: &Path) -> NetavarkResult<()> {
+        if let Some(dir) = path.parent() {
+            wrap!(fs::create_dir_all(dir), "create plugin state directory")?;
+        }
+        let f = wrap!(File::create(path), "create plugin state file")?;
+        serde_json::to_writer(f, self)?;
+        Ok(())
+    }
+}
+
+fn plugin_state_file(config_dir: &Path, network_id: &str, container_id: &str) -> PathBuf {
+    config_dir
+        .join(PLUGIN_STATE_DIR)
+        .join(format!("{network_id}_{container_id}"))
+}
+
+/// Check if other containers still have netavark services on the plugin network.
+fn network_has_plugin_state(config_dir: &Path, network_id: &str) -> NetavarkResult<bool> {
+    let dir = match fs::read_dir(config_dir.join(PLUGIN_STATE_DIR)) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(NetavarkError::wrap("read plugin state directory", e.into())),
+    };
+    let prefix = format!("{network_id}_");
+    for entry in dir {
+        let entry = wrap!(entry, "read plugin state directory")?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true when the plugin of the network requested dns for the container,
+/// must be called before the plugin teardown which removes the state.
+pub fn plugin_requested_dns(config_dir: &Path, network_id: &str, container_id: &str) -> bool {
+    match PluginServiceState::read(&plugin_state_file(config_dir, network_id, container_id)) {
+        Ok(state) => state.is_some_and(|s| s.dns),
+        Err(e) => {
+            error!("failed to read plugin state: {e}");
+            false
+        }
+    }
+}
+
+/// Find the executable for the plugin driver name in the plugin directories.
+pub fn find_plugin(name: &str, plugins_directories: &Option<Vec<OsString>>) -> Option<PathBuf> {
+    plugins_directories
+        .iter()
+        .flatten()
+        .find_map(|dir| plugin_executable(Path::new(dir), name))
+}
+
+fn plugin_executable(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    let meta = path.metadata().ok()?;
+    (meta.is_file() && meta.permissions().mode() & 0o111 != 0).then_some(path)
+}
+
+/// Get the time a plugin may run from the network options or the environment,
+/// None or 0 mean the plugin may run forever.
+pub fn get_plugin_timeout(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<Duration>> {
+    let seconds = match parse_option::<u64>(opts, OPTION_PLUGIN_TIMEOUT)? {
+        Some(seconds) => seconds,
+        None => match env::var(PLUGIN_TIMEOUT_ENV) {
+            Ok(value) => value.parse().map_err(|e| {
+                NetavarkError::msg(format!("invalid {PLUGIN_TIMEOUT_ENV} {value}: {e}"))
+            })?,
+            Err(_) => 0,
+        },
+    };
+    Ok((seconds > 0).then(|| Duration::from_secs(seconds)))
+}
+
+/// Call the info subcommand of the plugin and make sure we support its API version.
+pub fn get_plugin_info(
+    transport: &PluginTransport,
+    timeout: Option<Duration>,
+) -> NetavarkResult<PluginInfo> {
+    let plugin_info = read_plugin_info(transport, timeout)?;
+    check_api_version(&plugin_info.api_version)?;
+    Ok(plugin_info)
+}
+
+/// Call the info subcommand of the plugin without checking its API version.
+pub fn read_plugin_info(
+    transport: &PluginTransport,
+    timeout: Option<Duration>,
+) -> NetavarkResult<PluginInfo> {
+    let buffer = transport.call::<()>(&["info"], None, timeout)?;
+    wrap!(serde_json::from_slice(&buffer), "parse plugin info")
+}
+
+/// Names of all plugins in the plugin directories, executables and socket
+/// drop-in files, sorted and without duplicates.
+pub fn list_plugins(plugins_directories: &Option<Vec<OsString>>) -> NetavarkResult<Vec<String>> {
+    let mut names = Vec::new();
+    for dir in plugins_directories.iter().flatten() {
+        let dir = Path::new(dir);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("read plugin directory {dir:?}"),
+                    e.into(),
+                ))
+            }
+        };
+        for entry in entries {
+            let entry = wrap!(entry, format!("read plugin directory {dir:?}"))?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let drop_in_suffix = format!(".{PLUGIN_SOCKET_DROP_IN_EXT}");
+            match file_name.strip_suffix(&drop_in_suffix) {
+                Some(name) if !name.is_empty() => names.push(name.to_string()),
+                _ => {
+                    if plugin_executable(dir, &file_name).is_some() {
+                        names.push(file_name);
+                    }
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Send the changed network config to the plugin, returns false if the
+/// plugin does not implement the update subcommand.
+pub fn update_plugin(
+    transport: &PluginTransport,
+    network: &types::Network,
+) -> NetavarkResult<bool> {
+    let timeout = get_plugin_timeout(&network.options)?;
+    let plugin_info = get_plugin_info(transport, timeout)?;
+    if !plugin_info.supports(CAPABILITY_UPDATE) {
+        return Ok(false);
+    }
+    transport.call(&["update"], Some(network), timeout)?;
+    Ok(true)
+}
+
+fn api_major_version(version: &str) -> NetavarkResult<u64> {
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse().ok())
+        .ok_or_else(|| NetavarkError::msg(format!("invalid plugin API version \"{version}\"")))
+}
+
+pub fn check_api_version(version: &str) -> NetavarkResult<()> {
+    let major = api_major_version(version)?;
+    let max = api_major_version(API_VERSION)?;
+    if !(MIN_API_MAJOR_VERSION..=max).contains(&major) {
+        return Err(NetavarkError::msg(format!(
+            "plugin API version {version} is not supported, netavark supports API versions {MIN_API_MAJOR_VERSION}.x to {max}.x"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn exec_plugin_command<T: Serialize>(
+    path: &Path,
+    args: &[&str],
+    input: Option<&T>,
+    timeout: Option<Duration>,
+) -> NetavarkResult<Vec<u8>> {
+    let input = match input {
+        Some(input) => Some(serde_json::to_vec(input)?),
+        None => None,
+    };
+    let mut command = Command::new(path);
+    command.args(args);
+    let (rc, buffer) = run_plugin(&mut command, input.as_deref(), timeout)?;
+    if rc == 0 {
+        return Ok(buffer);
+    }
+    // exit code not 0 => error
+    let err: JsonError = serde_json::from_slice(&buffer)?;
+    Err(NetavarkError::msg(format!(
+        "exit code {}, message: {}",
+        rc, err.error
+    )))
+}
+
+/// Send the plugin command to a plugin daemon, the reply has the exit code
+/// and output a plugin executable would have. The timeout covers the whole
+/// exchange with the daemon.
+fn socket_plugin_command<T: Serialize>(
+    path: &Path,
+    args: &[&str],
+    input: Option<&T>,
+    timeout: Option<Duration>,
+) -> NetavarkResult<Vec<u8>> {
+    let request = types::PluginSocketRequest {
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        input: input.map(serde_json::to_value).transpose()?,
+    };
+    let request = serde_json::to_vec(&request)?;
+    let deadline = timeout.map(|t| Instant::now() + t);
+
+    let mut stream = wrap!(
+        UnixStream::connect(path),
+        format!("connect to plugin socket {path:?}")
+    )?;
+
+    let result = set_socket_deadline(&stream, deadline, UnixStream::set_write_timeout)
+        .and_then(|_| stream.write_all(&request))
+        // the daemon reads until EOF like a plugin reads stdin
+        .and_then(|_| stream.shutdown(Shutdown::Write))
+        .and_then(|_| {
+            let mut buffer = Vec::new();
+            let mut chunk = [0; 4096];
+            loop {
+                set_socket_deadline(&stream, deadline, UnixStream::set_read_timeout)?;
+                match stream.read(&mut chunk) {
+                    Ok(0) => return Ok(buffer),
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        });
+    let buffer = match result {
+        Ok(buffer) => buffer,
+        // a daemon cannot be killed like a plugin process, only give up on it
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Err(NetavarkError::ExitCode(
+                format!(
+                    "timed out after {}s waiting for plugin socket",
+                    timeout.unwrap_or_default().as_secs()
+                ),
+                PLUGIN_TIMEOUT_EXIT_CODE,
+            ));
+        }
+        Err(e) => return Err(NetavarkError::wrap("plugin socket", e.into())),
+    };
+
+    let reply: types::PluginSocketReply = serde_json::from_slice(&buffer)?;
+    if reply.exit_code == 0 {
+        return Ok(match reply.output {
+            Some(output) => serde_json::to_vec(&output)?,
+            None => Vec::new(),
+        });
+    }
+    let err: JsonError = serde_json::from_value(reply.output.unwrap_or_default())?;
+    Err(NetavarkError::msg(format!(
+        "exit code {}, message: {}",
+        reply.exit_code, err.error
+    )))
+}
+
+/// Set the socket timeout to the time left until the deadline, the socket
+/// timeouts only apply to a single read or write.
+fn set_socket_deadline(
+    stream: &UnixStream,
+    deadline: Option<Instant>,
+    set_timeout: fn(&UnixStream, Option<Duration>) -> io::Result<()>,
+) -> io::Result<()> {
+    let Some(deadline) = deadline else {
+        return Ok(());
+    };
+    let left = deadline.saturating_duration_since(Instant::now());
+    // a zero timeout is rejected, it would mean no timeout
+    if left.is_zero() {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
+    set_timeout(stream, Some(left))
+}
+
+/// Run the plugin command with the input on stdin and return the exit code
+/// and stdout, the plugin gets killed when it runs longer than the timeout.
+pub(crate) fn run_plugin(
+    command: &mut Command,
+    input: Option<&[u8]>,
+    timeout: Option<Duration>,
+) -> NetavarkResult<(i32, Vec<u8>)> {
+    command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if timeout.is_some() {
+        // Own process group so children forked by the plugin get killed with it.
+        command.process_group(0);
+    }
+    let mut child = command.spawn()?;
+
+    // Note: We need to buffer the output and then deserialize into the correct type after
+    // the plugin exits, Since the plugin can return two different json types depending on
+    // the exit code.
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take().unwrap();
+    let (exit_status, result) = match timeout {
+        None => {
+            // Do not handle error here, we have to wait for the child first.
+            let result = write_read_plugin(stdin, input, stdout);
+            (wrap!(child.wait(), "wait for plugin to exit")?, result)
+        }
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            // Write and read in a thread, a hung plugin never reads stdin or
+            // closes stdout so we could not enforce the timeout while blocked on them.
+            let input = input.map(|i| i.to_vec());
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                // the receiver is gone when the plugin timed out
+                let _ = tx.send(write_read_plugin(stdin, input.as_deref(), stdout));
+            });
+            let exit_status = wait_with_timeout(&mut child, deadline, timeout)?;
+            // a child forked by the plugin can keep stdout open after the plugin exited
+            let result = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(result) => result,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    kill_process_group(&child);
+                    return Err(plugin_timeout_error(timeout));
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    Err(io::Error::other("plugin output reader panicked"))
+                }
+            };
+            (exit_status, result)
+        }
+    };
 
-        let stdin = child.stdin.take().unwrap();
-        serde_json::to_writer(&stdin, &input)?;
+    if let Some(rc) = exit_status.code() {
+        // make sure the buffer is correct
+        let buffer = wrap!(result, "read into buffer")?;
+        return Ok((rc, buffer));
+    }
+    // If we could not get the exit code then the process was killed by a signal.
+    // I don't think it is necessary to read and return the signal so we just return a generic error.
+    Err(NetavarkError::msg("plugin killed by signal"))
+}
+
+/// Write the input to the plugin stdin and read its stdout until it is closed.
+fn write_read_plugin(
+    stdin: Option<ChildStdin>,
+    input: Option<&[u8]>,
+    mut stdout: ChildStdout,
+) -> io::Result<Vec<u8>> {
+    if let (Some(mut stdin), Some(input)) = (stdin, input) {
+        stdin.write_all(input)?;
         // Close stdin here to avoid that the plugin waits forever for an EOF.
         // And then we would wait for the child to exit which would cause a hang.
         drop(stdin);
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    stdout.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Wait for the plugin to exit, kill it once the deadline passed.
+fn wait_with_timeout(
+    child: &mut Child,
+    deadline: Instant,
+    timeout: Duration,
+) -> NetavarkResult<ExitStatus> {
+    loop {
+        if let Some(exit_status) = wrap!(child.try_wait(), "wait for plugin to exit")? {
+            return Ok(exit_status);
+        }
+        if Instant::now() >= deadline {
+            kill_process_group(child);
+            // errors only mean the plugin exited in the meantime, it is gone either way
+            let _ = child.wait();
+            return Err(plugin_timeout_error(timeout));
+        }
+        thread::sleep(PLUGIN_WAIT_INTERVAL);
+    }
+}
+
+/// Kill the plugin and all processes it forked, the plugin runs in its own
+/// process group with its pid as group id.
+fn kill_process_group(child: &Child) {
+    // errors only mean the processes exited in the meantime, they are gone either way
+    let _ = signal::killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+}
+
+fn plugin_timeout_error(timeout: Duration) -> NetavarkError {
+    NetavarkError::ExitCode(
+        format!("timed out after {}s, plugin killed", timeout.as_secs()),
+        PLUGIN_TIMEOUT_EXIT_CODE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_api_version() {
+        assert!(check_api_version("1.0.0").is_ok());
+        assert!(check_api_version(API_VERSION).is_ok());
+        assert!(check_api_version("2.1").is_ok());
+
+        let err = check_api_version("3.0.0").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "plugin API version 3.0.0 is not supported, netavark supports API versions 1.x to 2.x"
+        );
+        let err = check_api_version("0.9.0").unwrap_err();
+        assert!(err.to_string().contains("is not supported"));
+        let err = check_api_version("abc").unwrap_err();
+        assert_eq!(err.to_string(), "invalid plugin API version \"abc\"");
+    }
+
+    #[test]
+    fn test_exec_plugin_command_timeout() {
+        let timeout = Some(Duration::from_millis(100));
+        let start = Instant::now();
+        let err =
+            exec_plugin_command::<()>(Path::new("/bin/sleep"), &["10"], None, timeout).unwrap_err();
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "plugin was not killed"
+        );
+        assert_eq!(err.to_string(), "timed out after 0s, plugin killed");
+        assert_eq!(err.get_exit_code(), PLUGIN_TIMEOUT_EXIT_CODE);
+
+        let buffer =
+            exec_plugin_command::<()>(Path::new("/bin/echo"), &["ok"], None, timeout).unwrap();
+        assert_eq!(buffer, b"ok\n");
+
+        // a forked child keeps stdout open after the plugin exited
+        let start = Instant::now();
+        let err = exec_plugin_command::<()>(
+            Path::new("/bin/sh"),
+            &["-c", "sleep 10 & echo ok"],
+            None,
+            timeout,
+        )
+        .unwrap_err();
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "plugin child was not killed"
+        );
+        assert_eq!(err.get_exit_code(), PLUGIN_TIMEOUT_EXIT_CODE);
+
+        // the plugin never reads its input, more than fits into the pipe
+        let start = Instant::now();
+        let input = "x".repeat(1024 * 1024);
+        let err = exec_plugin_command(Path::new("/bin/sleep"), &["10"], Some(&input), timeout)
+            .unwrap_err();
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "plugin input was not written under the timeout"
+        );
+        assert_eq!(err.get_exit_code(), PLUGIN_TIMEOUT_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_plugin_service_state() {
+        let tmpdir = tempfile::Builder::new()
+            .prefix("plugin-state")
+            .tempdir()
+            .unwrap();
+        let config_dir = tmpdir.path();
+        assert!(!plugin_requested_dns(config_dir, "net1", "ctr1"));
+        assert!(!network_has_plugin_state(config_dir, "net1").unwrap());
+
+        let state = PluginServiceState {
+            dns: true,
+            port_forward: false,
+            container_addresses: vec!["10.0.0.2/24".parse().unwrap()],
+            gateways: vec!["10.0.0.1".parse().unwrap()],
+        };
+        state
+            .write(&plugin_state_file(config_dir, "net1", "ctr1"))
+            .unwrap();
+        assert!(plugin_requested_dns(config_dir, "net1", "ctr1"));
+        assert!(!plugin_requested_dns(config_dir, "net1", "ctr2"));
+        assert!(network_has_plugin_state(config_dir, "net1").unwrap());
+        assert!(!network_has_plugin_state(config_dir, "net2").unwrap());
+
+        let read = PluginServiceState::read(&plugin_state_file(config_dir, "net1", "ctr1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.container_addresses, state.container_addresses);
+        assert_eq!(read.gateways, state.gateways);
+    }
+
+    #[test]
+    fn test_plugin_transport_find() {
+        let tmpdir = tempfile::Builder::new()
+            .prefix("plugin-dir")
+            .tempdir()
+            .unwrap();
+        let dirs = Some(vec![tmpdir.path().as_os_str().to_owned()]);
+        assert!(PluginTransport::find("daemon", &dirs).unwrap().is_none());
 
-        // Note: We need to buffer the output and then deserialize into the correct type after
-        // the plugin exits, Since the plugin can return two different json types depending on
-        // the exit code.
-        let mut buffer: Vec<u8> = Vec::new();
-
-        let mut stdout = child.stdout.take().unwrap();
-        // Do not handle error here, we have to wait for the child first.
-        let result = stdout.read_to_end(&mut buffer);
-
-        let exit_status = wrap!(child.wait(), "wait for plugin to exit")?;
-        if let Some(rc) = exit_status.code() {
-            // make sure the buffer is correct
-            wrap!(result, "read into buffer")?;
-            if rc == 0 {
-                // read status block and setup
-                if setup {
-                    let status = serde_json::from_slice(&buffer)?;
-                    return Ok(Some(status));
-                } else {
-                    return Ok(None);
+        fs::write(tmpdir.path().join("daemon.socket"), "/run/daemon.sock\n").unwrap();
+        match PluginTransport::find("daemon", &dirs).unwrap() {
+            Some(PluginTransport::Socket(path)) => assert_eq!(path, Path::new("/run/daemon.sock")),
+            t => panic!("unexpected transport {t:?}"),
+        }
+
+        fs::write(tmpdir.path().join("relative.socket"), "daemon.sock").unwrap();
+        let err = PluginTransport::find("relative", &dirs).unwrap_err();
+        assert!(err.to_string().contains("must contain an absolute path"));
+    }
+
+    #[test]
+    fn test_list_plugins() {
+        let dir1 = tempfile::Builder::new()
+            .prefix("plugins")
+            .tempdir()
+            .unwrap();
+        let dir2 = tempfile::Builder::new()
+            .prefix("plugins")
+            .tempdir()
+            .unwrap();
+        for (dir, name, mode) in [
+            (&dir1, "b-plugin", 0o755),
+            (&dir1, "not-executable", 0o644),
+            (&dir2, "b-plugin", 0o755),
+            (&dir2, "a-plugin", 0o700),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        fs::write(dir2.path().join("daemon.socket"), "/run/daemon.sock").unwrap();
+        fs::write(dir2.path().join(".socket"), "/run/daemon.sock").unwrap();
+
+        let dirs = Some(vec![
+            dir1.path().as_os_str().to_owned(),
+            OsString::from("/does/not/exist"),
+            dir2.path().as_os_str().to_owned(),
+        ]);
+        assert_eq!(
+            list_plugins(&dirs).unwrap(),
+            ["a-plugin", "b-plugin", "daemon"]
+        );
+    }
+
+    #[test]
+    fn test_socket_plugin_command() {
+        let tmpdir = tempfile::Builder::new()
+            .prefix("plugin-socket")
+            .tempdir()
+            .unwrap();
+        let path = tmpdir.path().join("plugin.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let daemon = thread::spawn(move || {
+            for reply in [
+                r#"{"exit_code":0,"output":{"dns_search_domains":["ok"]}}"#,
+                // an info reply with an error key is no error
+                r#"{"exit_code":0,"output":{"version":"1.0.0","error":"none"}}"#,
+                r#"{"exit_code":1,"output":{"error":"setup error"}}"#,
+                r#"{"exit_code":0}"#,
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = Vec::new();
+                stream.read_to_end(&mut buffer).unwrap();
+                let request: types::PluginSocketRequest = serde_json::from_slice(&buffer).unwrap();
+                assert_eq!(request.args, ["setup", "/run/netns/test"]);
+                assert_eq!(request.input, Some(serde_json::json!({"key": "value"})));
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+            // never reply to trigger the timeout
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            drop(stream);
+            // keep sending data, the timeout is not per read
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..10 {
+                if stream.write_all(b" ").is_err() {
+                    break;
                 }
-            } else {
-                // exit code not 0 => error
-                let err: JsonError = serde_json::from_slice(&buffer)?;
-                return Err(NetavarkError::msg(format!(
-                    "exit code {}, message: {}",
-                    rc, err.error
-                )));
+                thread::sleep(Duration::from_millis(50));
             }
-        }
-        // If we could not get the exit code then the process was killed by a signal.
-        // I don't think it is necessary to read and return the signal so we just return a generic error.
-        Err(NetavarkError::msg("plugin killed by signal"))
+        });
+
+        let input = HashMap::from([("key", "value")]);
+        let args = ["setup", "/run/netns/test"];
+        let buffer = socket_plugin_command(&path, &args, Some(&input), None).unwrap();
+        assert_eq!(buffer, br#"{"dns_search_domains":["ok"]}"#);
+
+        let buffer = socket_plugin_command(&path, &args, Some(&input), None).unwrap();
+        assert_eq!(buffer, br#"{"error":"none","version":"1.0.0"}"#);
+
+        let err = socket_plugin_command(&path, &args, Some(&input), None).unwrap_err();
+        assert_eq!(err.to_string(), "exit code 1, message: setup error");
+
+        let buffer = socket_plugin_command(&path, &args, Some(&input), None).unwrap();
+        assert!(buffer.is_empty(), "no output");
+
+        let timeout = Some(Duration::from_millis(100));
+        let err = socket_plugin_command(&path, &args, Some(&input), timeout).unwrap_err();
+        assert_eq!(err.get_exit_code(), PLUGIN_TIMEOUT_EXIT_CODE);
+
+        let start = Instant::now();
+        let timeout = Some(Duration::from_millis(150));
+        let err = socket_plugin_command(&path, &args, Some(&input), timeout).unwrap_err();
+        assert_eq!(err.get_exit_code(), PLUGIN_TIMEOUT_EXIT_CODE);
+        assert!(
+            start.elapsed() < Duration::from_millis(400),
+            "overall timeout"
+        );
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_plugin_timeout() {
+        let opts = Some(HashMap::from([(
+            OPTION_PLUGIN_TIMEOUT.to_string(),
+            "30".to_string(),
+        )]));
+        assert_eq!(
+            get_plugin_timeout(&opts).unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        let opts = Some(HashMap::from([(
+            OPTION_PLUGIN_TIMEOUT.to_string(),
+            "0".to_string(),
+        )]));
+        assert_eq!(get_plugin_timeout(&opts).unwrap(), None);
+        let opts = Some(HashMap::from([(
+            OPTION_PLUGIN_TIMEOUT.to_string(),
+            "soon".to_string(),
+        )]));
+        assert!(get_plugin_timeout(&opts).is_err());
     }
 }
@@ -0,0 +1,211 @@
+//! JSON Schemas for the option maps of the network types. All option values
+//! are strings, the schemas describe which strings the drivers accept.
+use schemars::{gen::SchemaGenerator, schema::Schema};
+use serde_json::{json, Map, Value};
+
+use super::constants::{
+    IPAM_DHCP, IPAM_HOSTLOCAL, IPAM_NONE, ISOLATE_OPTION_FALSE, ISOLATE_OPTION_STRICT,
+    ISOLATE_OPTION_TRUE, LOG_DROPPED_OPTION_LOG, LOG_DROPPED_OPTION_NFLOG,
+    OPTION_ALLOW_PORT_CONFLICTS, OPTION_BCLIM, OPTION_CNI_CONFIG, OPTION_DHCP_CLIENT_ID,
+    OPTION_DHCP_SEND_HOSTNAME, OPTION_HAIRPIN_MASQUERADE, OPTION_HOST_INTERFACE_NAME,
+    OPTION_ISOLATE, OPTION_ISOLATION_GROUPS, OPTION_LOCALHOST_FORWARDING, OPTION_LOG_DROPPED,
+    OPTION_METRIC, OPTION_MODE, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE, OPTION_PLUGIN_TIMEOUT,
+    OPTION_SNAT_EXCLUDE, OPTION_SNAT_IPV4, OPTION_SNAT_IPV6, OPTION_VRF,
+};
+
+fn unsigned(description: &str) -> Value {
+    json!({"type": "string", "pattern": "^[0-9]+$", "description": description})
+}
+
+fn signed(description: &str) -> Value {
+    json!({"type": "string", "pattern": "^-?[0-9]+$", "description": description})
+}
+
+fn boolean(description: &str) -> Value {
+    json!({"type": "string", "enum": ["true", "false"], "description": description})
+}
+
+fn string(description: &str) -> Value {
+    json!({"type": "string", "description": description})
+}
+
+fn one_of(values: &[&str], description: &str) -> Value {
+    json!({"type": "string", "enum": values, "description": description})
+}
+
+fn ip(format: &str, description: &str) -> Value {
+    json!({"type": "string", "format": format, "description": description})
+}
+
+/// The options of a network, other keys are allowed for plugins.
+pub fn network_options(_: &mut SchemaGenerator) -> Schema {
+    string_map([
+        (
+            OPTION_MTU,
+            unsigned("MTU of the interfaces, 0 uses the kernel default."),
+        ),
+        (
+            OPTION_METRIC,
+            unsigned("Metric of the default routes, defaults to 100."),
+        ),
+        (
+            OPTION_NO_DEFAULT_ROUTE,
+            boolean("Do not add default routes via the gateways."),
+        ),
+        (
+            OPTION_MODE,
+            one_of(
+                &[
+                    "", "bridge", "private", "vepa", "passthru", "source", "l2", "l3", "l3s",
+                ],
+                "The macvlan mode, defaults to bridge, or the ipvlan mode, defaults to l2.",
+            ),
+        ),
+        (
+            OPTION_BCLIM,
+            signed("Broadcast queue threshold of the macvlan interface."),
+        ),
+        (
+            OPTION_DHCP_SEND_HOSTNAME,
+            boolean("Send the container name as hostname to the DHCP server."),
+        ),
+        (
+            OPTION_ISOLATE,
+            one_of(
+                &[
+                    ISOLATE_OPTION_TRUE,
+                    ISOLATE_OPTION_FALSE,
+                    ISOLATE_OPTION_STRICT,
+                ],
+                "Isolate the network, strict also isolates it from non isolated networks.",
+            ),
+        ),
+        (
+            OPTION_ISOLATION_GROUPS,
+            json!({
+                "type": "string",
+                "pattern": "^[A-Za-z0-9_-]+(,[A-Za-z0-9_-]+)*$",
+                "description": "Comma separated groups of networks which can reach each other.",
+            }),
+        ),
+        (
+            OPTION_LOG_DROPPED,
+            one_of(
+                &[
+                    LOG_DROPPED_OPTION_LOG,
+                    LOG_DROPPED_OPTION_NFLOG,
+                    "true",
+                    "false",
+                ],
+                "Log the packets dropped by the isolation rules, true is the same as log.",
+            ),
+        ),
+        (
+            OPTION_VRF,
+            string("Name of the VRF device the bridge is attached to."),
+        ),
+        (
+            OPTION_SNAT_IPV4,
+            ip("ipv4", "Source address used instead of masquerading."),
+        ),
+        (
+            OPTION_SNAT_IPV6,
+            ip("ipv6", "Source address used instead of masquerading."),
+        ),
+        (
+            OPTION_SNAT_EXCLUDE,
+            string("Comma separated CIDRs which are not masqueraded."),
+        ),
+        (
+            OPTION_LOCALHOST_FORWARDING,
+            boolean("Forward ports published on a localhost address, defaults to true."),
+        ),
+        (
+            OPTION_HAIRPIN_MASQUERADE,
+            boolean("Masquerade traffic of containers to their own ports, defaults to true."),
+        ),
+        (
+            OPTION_CNI_CONFIG,
+            string("Path of the CNI config list, required by the cni driver."),
+        ),
+        (
+            OPTION_PLUGIN_TIMEOUT,
+            unsigned("Seconds the plugin may run, 0 means no timeout."),
+        ),
+    ])
+}
+
+/// The options of a container for one network, other keys are allowed for plugins.
+pub fn per_network_options(_: &mut SchemaGenerator) -> Schema {
+    string_map([
+        (
+            OPTION_HOST_INTERFACE_NAME,
+            string("Name of the host side veth interface of the bridge driver."),
+        ),
+        (
+            OPTION_ALLOW_PORT_CONFLICTS,
+            boolean("Do not fail when a host port is already used by another container."),
+        ),
+        (
+            OPTION_DHCP_CLIENT_ID,
+            string("Client identifier sent to the DHCP server."),
+        ),
+    ])
+}
+
+/// The ipam options of a network.
+pub fn ipam_options(_: &mut SchemaGenerator) -> Schema {
+    string_map([(
+        "driver",
+        one_of(
+            &[IPAM_HOSTLOCAL, IPAM_DHCP, IPAM_NONE],
+            "The ipam driver, defaults to host-local.",
+        ),
+    )])
+}
+
+fn string_map<const N: usize>(properties: [(&str, Value); N]) -> Schema {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(key, schema)| (key.to_string(), schema))
+        .collect();
+    serde_json::from_value(json!({
+        "type": ["object", "null"],
+        "properties": properties,
+        "additionalProperties": {"type": "string"},
+    }))
+    .expect("valid option schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use schemars::schema_for;
+
+    use super::*;
+    use crate::network::types;
+
+    #[test]
+    fn test_network_options_schema() {
+        let schema = serde_json::to_value(schema_for!(types::Network)).unwrap();
+        let required = schema["required"].as_array().unwrap();
+        assert!(!required.contains(&json!("options")));
+        assert!(!required.contains(&json!("ipam_options")));
+
+        let options = &schema["properties"]["options"];
+        assert_eq!(options["additionalProperties"], json!({"type": "string"}));
+        let properties = &options["properties"];
+        assert_eq!(properties[OPTION_MTU]["pattern"], json!("^[0-9]+$"));
+        assert_eq!(properties[OPTION_BCLIM]["pattern"], json!("^-?[0-9]+$"));
+        assert_eq!(
+            properties[OPTION_ISOLATE]["enum"],
+            json!(["true", "false", "strict"])
+        );
+        assert_eq!(properties[OPTION_VRF]["type"], json!("string"));
+
+        let schema = serde_json::to_value(schema_for!(types::PerNetworkOptions)).unwrap();
+        assert_eq!(
+            schema["properties"]["options"]["properties"][OPTION_ALLOW_PORT_CONFLICTS]["enum"],
+            json!(["true", "false"])
+        );
+    }
+}
@@ -1,11 +1,12 @@
 // Crate contains the types which are accepted by netavark.
 
 use ipnet::IpNet;
+use schemars::JsonSchema;
 use std::collections::HashMap;
 use std::net::IpAddr;
 
 // Network describes the Network attributes.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Network {
     /// Set up dns for this network
     #[serde(rename = "dns_enabled")]
@@ -39,11 +40,13 @@ pub struct Network {
     /// Options is a set of key-value options that have been applied to
     /// the Network.
     #[serde(rename = "options")]
+    #[schemars(schema_with = "super::schema::network_options", default)]
     pub options: Option<HashMap<String, String>>,
 
     /// IPAM options is a set of key-value options that have been applied to
     /// the Network.
     #[serde(rename = "ipam_options")]
+    #[schemars(schema_with = "super::schema::ipam_options", default)]
     pub ipam_options: Option<HashMap<String, String>>,
 
     /// Subnets to use for this network.
@@ -57,10 +60,15 @@ pub struct Network {
     /// Network DNS servers for aardvark-dns.
     #[serde(rename = "network_dns_servers")]
     pub network_dns_servers: Option<Vec<IpAddr>>,
+
+    /// Hook executables to run after the driver setup, and in reverse
+    /// order before the driver teardown. Optional
+    #[serde(rename = "hooks", default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Vec<String>>,
 }
 
 /// NetworkOptions for a given container.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct NetworkOptions {
     /// The container id, used for iptables comments and ipam allocation.
     #[serde(rename = "container_id")]
@@ -92,7 +100,7 @@ pub struct NetworkOptions {
 }
 
 /// PerNetworkOptions are options which should be set on a per network basis
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct PerNetworkOptions {
     /// Aliases contains a list of names which the dns server should resolve
     /// to this container. Should only be set when DNSEnabled is true on the Network.
@@ -115,11 +123,12 @@ pub struct PerNetworkOptions {
 
     /// Driver-specific options for this container.
     #[serde(rename = "options")]
+    #[schemars(schema_with = "super::schema::per_network_options", default)]
     pub options: Option<HashMap<String, String>>,
 }
 
 /// PortMapping is one or more ports that will be mapped into the container.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct PortMapping {
     /// ContainerPort is the port number that will be exposed from the
     /// container.
@@ -153,11 +162,39 @@ pub struct PortMapping {
     /// 65536.
     #[serde(rename = "range")]
     pub range: u16,
+
+    /// ContainerRange is the number of container ports the host ports are
+    /// forwarded to, starting at ContainerPort.
+    /// If unset, assumed to be the same as Range. The only other allowed
+    /// value is 1 which forwards all ports of the host port range to the
+    /// single ContainerPort.
+    #[serde(
+        rename = "container_range",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub container_range: Option<u16>,
+}
+
+impl PortMapping {
+    /// Returns true if the host port range is forwarded to a single container port.
+    pub fn is_many_to_one(&self) -> bool {
+        self.range > 1 && self.container_range == Some(1)
+    }
+
+    /// Returns the container port the host port at the given offset is forwarded to.
+    pub fn container_port_for(&self, offset: u16) -> u16 {
+        if self.is_many_to_one() {
+            self.container_port
+        } else {
+            self.container_port + offset
+        }
+    }
 }
 
 /// StatusBlock contains the network information about a container
 /// connected to one Network.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct StatusBlock {
     /// Aardvark supports resolving queries with
     /// having fewer than ndots dots. So we dont
@@ -174,10 +211,27 @@ pub struct StatusBlock {
     /// The map key is the interface name.
     #[serde(rename = "interfaces")]
     pub interfaces: Option<HashMap<String, NetInterface>>,
+
+    /// NTP servers, only set when they were provided by a DHCP server
+    #[serde(
+        rename = "ntp_server_ips",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ntp_server_ips: Option<Vec<IpAddr>>,
+
+    /// Port mappings of the container, only set when netavark allocated
+    /// at least one host port because it was given as 0.
+    #[serde(
+        rename = "port_mappings",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub port_mappings: Option<Vec<PortMapping>>,
 }
 
 /// NetInterface contains the settings for a given network interface.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct NetInterface {
     /// MacAddress for this Interface.
     #[serde(rename = "mac_address")]
@@ -189,7 +243,7 @@ pub struct NetInterface {
 }
 
 /// NetAddress contains the ip address, subnet and gateway.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct NetAddress {
     /// Gateway for the network. This can be empty if there is no gateway, e.g. internal network.
     #[serde(rename = "gateway")]
@@ -198,11 +252,12 @@ pub struct NetAddress {
     /// IPNet of this NetAddress. Note that this is a subnet but it has to contain the
     /// actual ip of the network interface and not the network address.
     #[serde(rename = "ipnet")]
+    #[schemars(with = "String")]
     pub ipnet: IpNet,
 }
 
 /// Subnet for a network.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Subnet {
     /// Gateway IP for this Network.
     #[serde(rename = "gateway")]
@@ -214,11 +269,12 @@ pub struct Subnet {
 
     /// Subnet for this Network in CIDR form.
     #[serde(rename = "subnet")]
+    #[schemars(with = "String")]
     pub subnet: IpNet,
 }
 
 /// Static routes for a network.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Route {
     /// Gateway IP for this route.
     #[serde(rename = "gateway")]
@@ -226,6 +282,7 @@ pub struct Route {
 
     /// Destination for this route in CIDR form.
     #[serde(rename = "destination")]
+    #[schemars(with = "String")]
     pub destination: IpNet,
 
     /// Route Metric
@@ -234,7 +291,7 @@ pub struct Route {
 }
 
 /// LeaseRange contains the range where IP are leased.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct LeaseRange {
     /// EndIP last IP in the subnet which should be used to assign ips.
     #[serde(rename = "end_ip")]
@@ -246,7 +303,7 @@ pub struct LeaseRange {
 }
 
 /// Type used for the plugin setup and teardown command
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct NetworkPluginExec {
     /// The id for the container
     #[serde(rename = "container_id")]
@@ -264,3 +321,54 @@ pub struct NetworkPluginExec {
     /// The special network options for this specific container
     pub network_options: PerNetworkOptions,
 }
+
+/// Netavark services a plugin can request for the container it set up
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PluginServices {
+    /// Add the container addresses to aardvark-dns, the network must have
+    /// dns_enabled set and the status block must contain a gateway
+    #[serde(rename = "dns", default)]
+    pub dns: bool,
+    /// Create the firewall rules for the port mappings of the container
+    #[serde(rename = "port_forward", default)]
+    pub port_forward: bool,
+}
+
+/// Type returned by the plugin setup command
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PluginStatusBlock {
+    #[serde(flatten)]
+    pub status: StatusBlock,
+    /// The netavark services requested by the plugin. Optional
+    #[serde(rename = "netavark", default, skip_serializing_if = "Option::is_none")]
+    pub netavark: Option<PluginServices>,
+}
+
+/// Request sent to plugin daemons on their unix socket, the args are the same
+/// as for plugin executables and the input is what they would get on stdin
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PluginSocketRequest {
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+}
+
+/// Reply of plugin daemons, the exit code and output are what a plugin
+/// executable would exit with and print on stdout
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PluginSocketReply {
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<serde_json::Value>,
+}
+
+/// Type used for the hook setup and teardown command
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NetworkHookExec {
+    /// The same input as plugins get
+    #[serde(flatten)]
+    pub exec: NetworkPluginExec,
+    /// The status block returned by the driver, only set on setup
+    #[serde(rename = "status")]
+    pub status: Option<StatusBlock>,
+}
@@ -1,6 +1,11 @@
-use crate::error::NetavarkResult;
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::constants::IPAM_HOSTLOCAL;
+use crate::network::types::{Network, PerNetworkOptions, PortMapping, Subnet};
+use ipnet::IpNet;
 use log::debug;
+use std::collections::HashMap;
 use std::fs::File;
+use std::net::IpAddr;
 
 pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     debug!("Validating network namespace...");
@@ -8,3 +13,341 @@ pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     let _ = File::open(file)?.metadata()?;
     Ok(())
 }
+
+/// Validate the port mappings before any host port gets allocated or
+/// firewall rule is added.
+pub fn validate_port_mappings(ports: &Option<Vec<PortMapping>>) -> NetavarkResult<()> {
+    for port in ports.iter().flatten() {
+        let range = port.range.max(1);
+        if port.host_port == 0 && range > 1 {
+            return Err(NetavarkError::msg(format!(
+                "host port 0 cannot be used with a port range (container port {})",
+                port.container_port
+            )));
+        }
+        match port.container_range {
+            None | Some(1) => {}
+            Some(container_range) if container_range == range => {}
+            Some(container_range) => {
+                return Err(NetavarkError::msg(format!(
+                    "container range {} of container port {} must be 1 or equal to the range {}",
+                    container_range, port.container_port, range
+                )));
+            }
+        }
+        if u32::from(port.host_port) + u32::from(range) > 65536 {
+            return Err(NetavarkError::msg(format!(
+                "host port range starting at {} exceeds the maximum port 65535",
+                port.host_port
+            )));
+        }
+        if !port.is_many_to_one() && u32::from(port.container_port) + u32::from(range) > 65536 {
+            return Err(NetavarkError::msg(format!(
+                "container port range starting at {} exceeds the maximum port 65535",
+                port.container_port
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Static checks of the network subnets which the drivers do not do,
+/// returns every problem found.
+pub fn check_network_subnets(network: &Network) -> Vec<NetavarkError> {
+    let mut errors = Vec::new();
+    let subnets = network.subnets.as_deref().unwrap_or_default();
+    for subnet in subnets {
+        errors.extend(check_subnet(subnet));
+    }
+    for (i, a) in subnets.iter().enumerate() {
+        for b in &subnets[i + 1..] {
+            if subnets_overlap(&a.subnet, &b.subnet) {
+                errors.push(NetavarkError::msg(format!(
+                    "subnet {} overlaps with subnet {}",
+                    a.subnet, b.subnet
+                )));
+            }
+        }
+    }
+    errors
+}
+
+fn check_subnet(subnet: &Subnet) -> Vec<NetavarkError> {
+    let mut errors = Vec::new();
+    let net = subnet.subnet;
+    if net.addr() != net.network() {
+        errors.push(NetavarkError::msg(format!(
+            "subnet {net} has host bits set, use {}",
+            net.trunc()
+        )));
+    }
+    if let Some(gw) = subnet.gateway {
+        if !net.contains(&gw) {
+            errors.push(NetavarkError::msg(format!(
+                "gateway {gw} is not in subnet {net}"
+            )));
+        } else if gw == net.network() {
+            errors.push(NetavarkError::msg(format!(
+                "gateway {gw} is the network address of subnet {net}"
+            )));
+        }
+    }
+    if let Some(range) = &subnet.lease_range {
+        let mut parse = |name: &str, ip: &Option<String>| -> Option<IpAddr> {
+            let ip = ip.as_deref()?;
+            match ip.parse::<IpAddr>() {
+                Ok(ip) if net.contains(&ip) => Some(ip),
+                Ok(ip) => {
+                    errors.push(NetavarkError::msg(format!(
+                        "lease range {name} {ip} is not in subnet {net}"
+                    )));
+                    None
+                }
+                Err(_) => {
+                    errors.push(NetavarkError::msg(format!(
+                        "invalid lease range {name} \"{ip}\" of subnet {net}"
+                    )));
+                    None
+                }
+            }
+        };
+        let start = parse("start_ip", &range.start_ip);
+        let end = parse("end_ip", &range.end_ip);
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                errors.push(NetavarkError::msg(format!(
+                    "lease range start_ip {start} is after end_ip {end} in subnet {net}"
+                )));
+            }
+        }
+    }
+    errors
+}
+
+/// Check that the static ips of the container match the subnets of the
+/// network, host-local ipam assigns them by the subnet order.
+pub fn check_static_ips(network: &Network, opts: &PerNetworkOptions) -> Vec<NetavarkError> {
+    let ipam_driver = network
+        .ipam_options
+        .as_ref()
+        .and_then(|map| map.get("driver"))
+        .map(String::as_str);
+    if !matches!(ipam_driver, None | Some(IPAM_HOSTLOCAL)) {
+        return Vec::new();
+    }
+    let subnets = network.subnets.as_deref().unwrap_or_default();
+    let static_ips = opts.static_ips.as_deref().unwrap_or_default();
+    let mut errors = Vec::new();
+    if static_ips.len() != subnets.len() {
+        errors.push(NetavarkError::msg(format!(
+            "{} static ips given for {} subnets",
+            static_ips.len(),
+            subnets.len()
+        )));
+    }
+    for (ip, subnet) in static_ips.iter().zip(subnets) {
+        if !subnet.subnet.contains(ip) {
+            errors.push(NetavarkError::msg(format!(
+                "static ip {ip} is not in subnet {}",
+                subnet.subnet
+            )));
+        } else if subnet.gateway == Some(*ip) {
+            errors.push(NetavarkError::msg(format!(
+                "static ip {ip} is the gateway of subnet {}",
+                subnet.subnet
+            )));
+        }
+    }
+    errors
+}
+
+/// Check that the networks of a container do not have overlapping subnets,
+/// the routes in the container would conflict.
+pub fn check_overlapping_networks(networks: &HashMap<String, Network>) -> Vec<NetavarkError> {
+    let mut names: Vec<&String> = networks.keys().collect();
+    names.sort();
+    let mut errors = Vec::new();
+    for (i, a) in names.iter().enumerate() {
+        for b in &names[i + 1..] {
+            for subnet_a in networks[*a].subnets.iter().flatten() {
+                for subnet_b in networks[*b].subnets.iter().flatten() {
+                    if subnets_overlap(&subnet_a.subnet, &subnet_b.subnet) {
+                        errors.push(NetavarkError::msg(format!(
+                            "subnet {} of network {a} overlaps with subnet {} of network {b}",
+                            subnet_a.subnet, subnet_b.subnet
+                        )));
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+fn subnets_overlap(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(&b.network()) || b.contains(&a.network())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(
+        host_port: u16,
+        container_port: u16,
+        range: u16,
+        container_range: Option<u16>,
+    ) -> PortMapping {
+        PortMapping {
+            container_port,
+            host_ip: String::new(),
+            host_port,
+            protocol: "tcp".to_string(),
+            range,
+            container_range,
+        }
+    }
+
+    fn network(name: &str, subnets: serde_json::Value) -> Network {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "id": name,
+            "driver": "bridge",
+            "ipv6_enabled": false,
+            "internal": false,
+            "dns_enabled": false,
+            "subnets": subnets,
+        }))
+        .unwrap()
+    }
+
+    fn messages(errors: Vec<NetavarkError>) -> Vec<String> {
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check_network_subnets() {
+        let net = network(
+            "ok",
+            serde_json::json!([
+                {"subnet": "10.88.0.0/16", "gateway": "10.88.0.1",
+                 "lease_range": {"start_ip": "10.88.1.0", "end_ip": "10.88.2.0"}},
+                {"subnet": "fd00::/64", "gateway": "fd00::1"},
+            ]),
+        );
+        assert!(check_network_subnets(&net).is_empty());
+
+        let net = network(
+            "bad",
+            serde_json::json!([
+                {"subnet": "10.88.0.1/16", "gateway": "10.89.0.1"},
+                {"subnet": "10.88.1.0/24", "gateway": "10.88.1.0",
+                 "lease_range": {"start_ip": "10.88.1.200", "end_ip": "10.88.1.100"}},
+                {"subnet": "10.90.0.0/24",
+                 "lease_range": {"start_ip": "10.91.0.1", "end_ip": "abc"}},
+            ]),
+        );
+        assert_eq!(
+            messages(check_network_subnets(&net)),
+            [
+                "subnet 10.88.0.1/16 has host bits set, use 10.88.0.0/16",
+                "gateway 10.89.0.1 is not in subnet 10.88.0.1/16",
+                "gateway 10.88.1.0 is the network address of subnet 10.88.1.0/24",
+                "lease range start_ip 10.88.1.200 is after end_ip 10.88.1.100 in subnet 10.88.1.0/24",
+                "lease range start_ip 10.91.0.1 is not in subnet 10.90.0.0/24",
+                "invalid lease range end_ip \"abc\" of subnet 10.90.0.0/24",
+                "subnet 10.88.0.1/16 overlaps with subnet 10.88.1.0/24",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_static_ips() {
+        let net = network(
+            "net",
+            serde_json::json!([{"subnet": "10.88.0.0/16", "gateway": "10.88.0.1"}]),
+        );
+        let mut opts = PerNetworkOptions {
+            aliases: None,
+            interface_name: "eth0".to_string(),
+            static_ips: Some(vec!["10.88.0.2".parse().unwrap()]),
+            static_mac: None,
+            options: None,
+        };
+        assert!(check_static_ips(&net, &opts).is_empty());
+
+        opts.static_ips = Some(vec![
+            "10.88.0.1".parse().unwrap(),
+            "10.89.0.2".parse().unwrap(),
+        ]);
+        assert_eq!(
+            messages(check_static_ips(&net, &opts)),
+            [
+                "2 static ips given for 1 subnets",
+                "static ip 10.88.0.1 is the gateway of subnet 10.88.0.0/16",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_overlapping_networks() {
+        let networks = HashMap::from([
+            (
+                "a".to_string(),
+                network("a", serde_json::json!([{"subnet": "10.88.0.0/16"}])),
+            ),
+            (
+                "b".to_string(),
+                network("b", serde_json::json!([{"subnet": "10.88.5.0/24"}])),
+            ),
+            (
+                "c".to_string(),
+                network("c", serde_json::json!([{"subnet": "10.89.0.0/24"}])),
+            ),
+        ]);
+        assert_eq!(
+            messages(check_overlapping_networks(&networks)),
+            ["subnet 10.88.0.0/16 of network a overlaps with subnet 10.88.5.0/24 of network b"]
+        );
+    }
+
+    #[test]
+    fn test_validate_port_mappings() {
+        for ok in [
+            port(8080, 80, 1, None),
+            port(0, 80, 1, None),
+            port(0, 80, 0, None),
+            port(8080, 80, 10, None),
+            port(8080, 80, 10, Some(10)),
+            port(8080, 80, 10, Some(1)),
+            port(65526, 65535, 10, Some(1)),
+        ] {
+            assert!(
+                validate_port_mappings(&Some(vec![ok.clone()])).is_ok(),
+                "{ok:?} should be valid"
+            );
+        }
+
+        for (bad, msg) in [
+            (
+                port(0, 80, 2, None),
+                "host port 0 cannot be used with a port range (container port 80)",
+            ),
+            (
+                port(8080, 80, 10, Some(5)),
+                "container range 5 of container port 80 must be 1 or equal to the range 10",
+            ),
+            (
+                port(65535, 80, 2, None),
+                "host port range starting at 65535 exceeds the maximum port 65535",
+            ),
+            (
+                port(8080, 65535, 2, None),
+                "container port range starting at 65535 exceeds the maximum port 65535",
+            ),
+        ] {
+            let err = validate_port_mappings(&Some(vec![bad])).expect_err("invalid port");
+            assert_eq!(err.to_string(), msg);
+        }
+    }
+}
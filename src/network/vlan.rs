@@ -7,7 +7,7 @@ use netlink_packet_route::link::{
 };
 use rand::distributions::{Alphanumeric, DistString};
 
-use crate::network::macvlan_dhcp::{get_dhcp_lease, release_dhcp_lease};
+use crate::network::macvlan_dhcp::{get_dhcp_lease, release_dhcp_lease, DhcpClientOptions};
 use crate::{
     dns::aardvark::AardvarkEntry,
     error::{ErrorWrap, NetavarkError, NetavarkResult},
@@ -17,8 +17,8 @@ use crate::{
 
 use super::{
     constants::{
-        NO_CONTAINER_INTERFACE_ERROR, OPTION_BCLIM, OPTION_METRIC, OPTION_MODE, OPTION_MTU,
-        OPTION_NO_DEFAULT_ROUTE,
+        NO_CONTAINER_INTERFACE_ERROR, OPTION_BCLIM, OPTION_DHCP_CLIENT_ID,
+        OPTION_DHCP_SEND_HOSTNAME, OPTION_METRIC, OPTION_MODE, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE,
     },
     core_utils::{self, get_ipam_addresses, parse_option, CoreUtils},
     driver::{self, DriverInfo},
@@ -67,6 +67,8 @@ struct InternalData {
     kind: KindData,
     /// if set, no default gateway will be added
     no_default_route: bool,
+    /// client information sent to the dhcp server
+    dhcp_client_options: DhcpClientOptions,
     // TODO: add vlan
 }
 
@@ -82,6 +84,23 @@ impl<'a> Vlan<'a> {
             data: None::<InternalData>,
         }
     }
+
+    /// Collect the hostname and client identifier that should be sent to the dhcp
+    /// server. The hostname is only sent when the network asks for it.
+    fn get_dhcp_client_options(&self) -> NetavarkResult<DhcpClientOptions> {
+        let send_hostname: bool =
+            parse_option(&self.info.network.options, OPTION_DHCP_SEND_HOSTNAME)?.unwrap_or(false);
+        let host_name = if send_hostname {
+            self.info.container_name.clone()
+        } else {
+            String::new()
+        };
+        Ok(DhcpClientOptions {
+            host_name,
+            client_id: parse_option(&self.info.per_network_opts.options, OPTION_DHCP_CLIENT_ID)?
+                .unwrap_or_default(),
+        })
+    }
 }
 
 impl driver::NetworkDriver for Vlan<'_> {
@@ -137,6 +156,7 @@ impl driver::NetworkDriver for Vlan<'_> {
                 other => return Err(NetavarkError::msg(format!("unsupported VLAN type {other}"))),
             },
             no_default_route,
+            dhcp_client_options: self.get_dhcp_client_options()?,
         });
         Ok(())
     }
@@ -174,6 +194,8 @@ impl driver::NetworkDriver for Vlan<'_> {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            ntp_server_ips: None,
+            port_mappings: None,
         };
 
         // interfaces map, but we only ever expect one, for response
@@ -183,20 +205,23 @@ impl driver::NetworkDriver for Vlan<'_> {
         // a dhcp lease.  it will also perform the IP address assignment
         // to the macvlan interface.
         let subnets = if data.ipam.dhcp_enabled {
-            let (subnets, dns_servers, domain_name) = get_dhcp_lease(
+            let lease = get_dhcp_lease(
                 &data.host_interface_name,
                 &data.container_interface_name,
                 self.info.netns_path,
                 &container_vlan_mac,
+                &data.dhcp_client_options,
+                data.mtu,
             )?;
             // do not overwrite dns servers set by dns podman flag
             if !self.info.container_dns_servers.is_some() {
-                response.dns_server_ips = dns_servers;
+                response.dns_server_ips = lease.dns_servers;
             }
-            if domain_name.is_some() {
-                response.dns_search_domains = domain_name;
+            if lease.domain_name.is_some() {
+                response.dns_search_domains = lease.domain_name;
             }
-            subnets
+            response.ntp_server_ips = lease.ntp_servers;
+            lease.subnets
         } else {
             data.ipam.net_addresses.clone()
         };
@@ -241,6 +266,7 @@ impl driver::NetworkDriver for Vlan<'_> {
                 &self.info.per_network_opts.interface_name,
                 self.info.netns_path,
                 &container_mac_address,
+                &self.get_dhcp_client_options()?,
             )?
         }
 
@@ -0,0 +1,202 @@
+//! Helpers for the netns and netlink work most plugins have to do, these are
+//! the same functions the built-in drivers use.
+use std::{cell::RefCell, collections::HashMap, fs::File, os::fd::AsFd};
+
+use netlink_packet_route::link::{LinkAttribute, LinkMessage};
+
+use crate::{
+    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    exec_netns,
+    network::{
+        core_utils::{
+            self, join_netns, open_netlink_sockets, open_netlink_sockets_from, CoreUtils,
+            NamespaceOptions,
+        },
+        internal_types::IPAMAddresses,
+        netlink::LinkID,
+        types,
+    },
+    wrap,
+};
+
+thread_local! {
+    /// Host netns of the plugin test harness, the test thread does not run in
+    /// the netns of the process.
+    static TEST_HOST_NETNS: RefCell<Option<File>> = const { RefCell::new(None) };
+}
+
+/// Run the function with Namespaces::open() using the given host netns.
+pub(crate) fn with_test_host_netns<T>(host: &File, exec: impl FnOnce() -> T) -> NetavarkResult<T> {
+    let host = wrap!(host.try_clone(), "clone test host netns")?;
+    TEST_HOST_NETNS.with(|h| *h.borrow_mut() = Some(host));
+    let result = exec();
+    TEST_HOST_NETNS.with(|h| *h.borrow_mut() = None);
+    Ok(result)
+}
+
+/// The host and container netns of a plugin call with a netlink socket in each.
+pub struct Namespaces {
+    pub host: NamespaceOptions,
+    pub container: NamespaceOptions,
+}
+
+impl Namespaces {
+    /// Open the container netns given to the plugin subcommand, the host netns
+    /// is the one the plugin runs in.
+    pub fn open(netns_path: &str) -> NetavarkResult<Self> {
+        let test_host = TEST_HOST_NETNS.with(|host| match host.borrow().as_ref() {
+            Some(file) => file.try_clone().map(Some),
+            None => Ok(None),
+        });
+        if let Some(host) = wrap!(test_host, "clone test host netns")? {
+            return Self::open_from(host, netns_path);
+        }
+        let (host, container) = open_netlink_sockets(netns_path)?;
+        Ok(Namespaces { host, container })
+    }
+
+    /// Open the container netns with the given host netns instead of the one
+    /// of the process.
+    pub fn open_from(host: File, netns_path: &str) -> NetavarkResult<Self> {
+        let (host, container) = open_netlink_sockets_from(host, netns_path)?;
+        Ok(Namespaces { host, container })
+    }
+
+    /// Run the function in the container netns, e.g. to set sysctls.
+    /// The netlink sockets work without it as they are bound to their netns.
+    pub fn exec_in_container<T>(
+        &self,
+        exec: impl FnOnce() -> NetavarkResult<T>,
+    ) -> NetavarkResult<T> {
+        exec_netns!(
+            self.host.file.as_fd(),
+            self.container.file.as_fd(),
+            result,
+            exec()
+        );
+        result
+    }
+
+    /// Move the host link into the container and give it the container
+    /// interface name, returns the link index in the container.
+    pub fn move_link_to_container(
+        &mut self,
+        host_name: &str,
+        container_name: &str,
+    ) -> NetavarkResult<u32> {
+        let link = self
+            .host
+            .netlink
+            .get_link(LinkID::Name(host_name.to_string()))
+            .wrap(format!("get host link {host_name}"))?;
+        self.host
+            .netlink
+            .set_link_ns(link.header.index, self.container.file.as_fd())
+            .wrap(format!("move link {host_name} into the container"))?;
+
+        // the index can change when it is already used in the container
+        let link = self
+            .container
+            .netlink
+            .get_link(LinkID::Name(host_name.to_string()))?;
+        if host_name != container_name {
+            self.container
+                .netlink
+                .set_link_name(link.header.index, container_name.to_string())
+                .wrap(format!("rename link {host_name} to {container_name}"))?;
+        }
+        Ok(link.header.index)
+    }
+
+    /// Move the container link back to the host, the counterpart of
+    /// move_link_to_container() for teardown.
+    pub fn move_link_to_host(
+        &mut self,
+        container_name: &str,
+        host_name: &str,
+    ) -> NetavarkResult<()> {
+        let link = self
+            .container
+            .netlink
+            .get_link(LinkID::Name(container_name.to_string()))
+            .wrap(format!("get container link {container_name}"))?;
+        if host_name != container_name {
+            // rename first, the name could be used on the host
+            self.container
+                .netlink
+                .set_link_name(link.header.index, host_name.to_string())
+                .wrap(format!("rename link {container_name} to {host_name}"))?;
+        }
+        self.container
+            .netlink
+            .set_link_ns(link.header.index, self.host.file.as_fd())
+            .wrap(format!("move link {host_name} to the host"))?;
+        Ok(())
+    }
+
+    /// Add the ipam addresses to the container link, set it up and add the
+    /// default routes via the gateways.
+    pub fn configure_container_link(
+        &mut self,
+        link_index: u32,
+        ipam: &IPAMAddresses,
+        metric: Option<u32>,
+    ) -> NetavarkResult<()> {
+        for addr in &ipam.container_addresses {
+            self.container
+                .netlink
+                .add_addr(link_index, addr)
+                .wrap(format!("add ip address {addr} to the container link"))?;
+        }
+        self.container.netlink.set_up(LinkID::ID(link_index))?;
+        core_utils::add_default_routes(
+            &mut self.container.netlink,
+            &ipam.gateway_addresses,
+            metric,
+        )?;
+        for route in &ipam.routes {
+            self.container
+                .netlink
+                .add_route(route)
+                .wrap(format!("add route {route}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the container addresses from the network subnets and the static ips
+/// of the container like the built-in drivers with host-local ipam do.
+pub fn get_ipam_addresses(opts: &types::NetworkPluginExec) -> NetavarkResult<IPAMAddresses> {
+    core_utils::get_ipam_addresses(&opts.network_options, &opts.network)
+        .map_err(|e| NetavarkError::wrap("get ipam addresses", e.into()))
+}
+
+/// The mac address of the link in the format used by the status block.
+pub fn link_mac_address(link: &LinkMessage) -> String {
+    link.attributes
+        .iter()
+        .find_map(|nla| match nla {
+            LinkAttribute::Address(addr) => Some(CoreUtils::encode_address_to_hex(addr)),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Build the status block for a plugin which set up a single container interface.
+pub fn interface_status_block(
+    interface_name: String,
+    mac_address: String,
+    subnets: Vec<types::NetAddress>,
+) -> types::StatusBlock {
+    let interface = types::NetInterface {
+        mac_address,
+        subnets: Some(subnets),
+    };
+    types::StatusBlock {
+        dns_server_ips: None,
+        dns_search_domains: None,
+        interfaces: Some(HashMap::from([(interface_name, interface)])),
+        ntp_server_ips: None,
+        port_mappings: None,
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{error, network::types};
+
+pub mod helpers;
+pub mod testing;
+
+pub const API_VERSION: &str = "2.0.0";
+
+/// The plugin implements the status subcommand.
+pub const CAPABILITY_STATUS: &str = "status";
+/// The plugin implements the update subcommand.
+pub const CAPABILITY_UPDATE: &str = "update";
+
+// create new boxed error with string error message, also accepts format!() style arguments
+#[macro_export]
+macro_rules! new_error {
+    ($msg:ident) => {
+        Box::new(std::io::Error::new(std::io::ErrorKind::Other, $msg))
+    };
+    ($($arg:tt)*) => {{
+        Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!($($arg)*)))
+    }};
+}
+
+/// Contains info about this plugin
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "PluginInfo")]
+pub struct Info {
+    /// The version of this plugin.
+    version: String,
+    /// The api version for the netavark plugin API.
+    api_version: String,
+    /// The optional subcommands this plugin implements, see the CAPABILITY_* constants.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    capabilities: Vec<String>,
+    /// Optional fields you want to be displayed for the info command
+    #[serde(flatten)]
+    extra_info: Option<HashMap<String, String>>,
+}
+
+impl Info {
+    pub fn new(
+        version: String,
+        api_version: String,
+        extra_info: Option<HashMap<String, String>>,
+    ) -> Self {
+        Self {
+            version,
+            api_version,
+            capabilities: Vec::new(),
+            extra_info,
+        }
+    }
+
+    /// Declare the optional subcommands implemented by the plugin,
+    /// netavark only calls them when they are listed here.
+    pub fn with_capabilities(mut self, capabilities: &[&str]) -> Self {
+        self.capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+        self
+    }
+}
+
+/// Define the plugin functions
+pub trait Plugin {
+    // create a network config
+    fn create(&self, network: types::Network) -> Result<types::Network, Box<dyn Error>>;
+    /// set up the network configuration
+    fn setup(
+        &self,
+        netns: String,
+        opts: types::NetworkPluginExec,
+    ) -> Result<types::StatusBlock, Box<dyn Error>>;
+    /// tear down the network configuration
+    fn teardown(&self, netns: String, opts: types::NetworkPluginExec)
+        -> Result<(), Box<dyn Error>>;
+    /// the netavark services the plugin wants for the container it sets up,
+    /// called before setup
+    fn services(&self, _opts: &types::NetworkPluginExec) -> types::PluginServices {
+        types::PluginServices::default()
+    }
+    /// report the current state of the network configuration,
+    /// requires the status capability
+    fn status(
+        &self,
+        _netns: String,
+        _opts: types::NetworkPluginExec,
+    ) -> Result<types::StatusBlock, Box<dyn Error>> {
+        Err(new_error!("status is not supported by this plugin"))
+    }
+    /// apply changed dns servers or options of the network,
+    /// requires the update capability
+    fn update(&self, _network: types::Network) -> Result<(), Box<dyn Error>> {
+        Err(new_error!("update is not supported by this plugin"))
+    }
+}
+
+pub struct PluginExec<P: Plugin> {
+    plugin: P,
+    info: Info,
+}
+
+impl<P: Plugin> PluginExec<P> {
+    pub fn new(plugin: P, info: Info) -> Self {
+        PluginExec { plugin, info }
+    }
+
+    pub fn exec(&self) {
+        let args: Vec<String> = env::args().skip(1).collect();
+        match self.run(&args, &mut io::stdin(), &mut io::stdout()) {
+            Ok(_) => {}
+            Err(err) => {
+                let e = error::JsonError {
+                    error: err.to_string(),
+                };
+                serde_json::to_writer(io::stdout(), &e)
+                    .unwrap_or_else(|e| println!("failed to write json error: {e}: {err}"));
+                std::process::exit(1);
+            }
+        };
+    }
+
+    /// Run the plugin as daemon on the unix socket, netavark uses it instead of
+    /// executing the plugin when the socket path is registered in a
+    /// `<plugin name>.socket` file in a plugin directory.
+    /// Requests are handled one after the other, this function only returns on errors
+    /// of the listening socket.
+    pub fn serve(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        // remove the socket of a previous daemon
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        for stream in listener.incoming() {
+            if let Err(err) = self.handle_connection(stream?) {
+                eprintln!("failed to handle plugin request: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, mut stream: UnixStream) -> Result<(), Box<dyn Error>> {
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer)?;
+
+        let mut output = Vec::new();
+        let result = serde_json::from_slice::<types::PluginSocketRequest>(&buffer)
+            .map_err(|e| e.into())
+            .and_then(|request| {
+                let input = serde_json::to_vec(&request.input)?;
+                self.run(&request.args, &mut input.as_slice(), &mut output)
+            });
+        let reply = match result {
+            Ok(_) => types::PluginSocketReply {
+                exit_code: 0,
+                output: match output.is_empty() {
+                    true => None,
+                    false => Some(serde_json::from_slice(&output)?),
+                },
+            },
+            // the same as exec() for plugin executables
+            Err(err) => types::PluginSocketReply {
+                exit_code: 1,
+                output: Some(serde_json::to_value(error::JsonError {
+                    error: err.to_string(),
+                })?),
+            },
+        };
+        serde_json::to_writer(&mut stream, &reply)?;
+        Ok(())
+    }
+
+    /// Run the plugin subcommand, args start with the subcommand.
+    fn run(
+        &self,
+        args: &[String],
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut args = args.iter().cloned();
+
+        // match subcommand
+        match args.next().as_deref() {
+            Some("create") => {
+                let mut network = serde_json::from_reader(input)?;
+
+                network = self.plugin.create(network)?;
+
+                serde_json::to_writer(output, &network)?;
+            }
+            Some("setup") => {
+                let netns = args
+                    .next()
+                    .ok_or_else(|| new_error!("netns path argument is missing"))?;
+
+                let opts = serde_json::from_reader(input)?;
+
+                let services = self.plugin.services(&opts);
+                let status_block = types::PluginStatusBlock {
+                    status: self.plugin.setup(netns, opts)?,
+                    netavark: (services.dns || services.port_forward).then_some(services),
+                };
+                serde_json::to_writer(output, &status_block)?;
+            }
+            Some("teardown") => {
+                let netns = args
+                    .next()
+                    .ok_or_else(|| new_error!("netns path argument is missing"))?;
+
+                let opts = serde_json::from_reader(input)?;
+                self.plugin.teardown(netns, opts)?;
+            }
+            Some("status") => {
+                let netns = args
+                    .next()
+                    .ok_or_else(|| new_error!("netns path argument is missing"))?;
+
+                let opts = serde_json::from_reader(input)?;
+
+                let status_block = self.plugin.status(netns, opts)?;
+                serde_json::to_writer(output, &status_block)?;
+            }
+            Some("update") => {
+                let network = serde_json::from_reader(input)?;
+                self.plugin.update(network)?;
+            }
+            Some("info") => self.print_info(output)?,
+            Some(unknown) => {
+                return Err(new_error!("unknown subcommand: {}", unknown));
+            }
+            None => self.print_info(output)?,
+        };
+        Ok(())
+    }
+
+    fn print_info(&self, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(output, &self.info)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Test harness to run plugins in throwaway network namespaces, it needs root
+//! or CAP_SYS_ADMIN to create them.
+use std::{
+    error::Error,
+    fs::File,
+    os::fd::{AsFd, AsRawFd},
+};
+
+use log::error;
+use nix::sched::{unshare, CloneFlags};
+
+use crate::{
+    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    exec_netns,
+    network::{core_utils::join_netns, types},
+    wrap,
+};
+
+use super::{helpers::with_test_host_netns, Plugin};
+
+/// A host and a container netns for one test. Creating it moves the calling
+/// thread into the new host netns so the plugin cannot touch the real host,
+/// dropping it moves the thread back and the namespaces are removed.
+pub struct TestNamespaces {
+    original: File,
+    host: File,
+    container: File,
+}
+
+impl TestNamespaces {
+    pub fn new() -> NetavarkResult<Self> {
+        let original = open_thread_netns()?;
+        let namespaces = (|| {
+            let host = new_netns().wrap("create host netns")?;
+            let container = new_netns().wrap("create container netns")?;
+            join_netns(host.as_fd())?;
+            Ok((host, container))
+        })();
+        match namespaces {
+            Ok((host, container)) => Ok(TestNamespaces {
+                original,
+                host,
+                container,
+            }),
+            Err(err) => {
+                // new_netns() already moved the thread, do not leave it there
+                if let Err(e) = join_netns(original.as_fd()) {
+                    error!("failed to return to the original netns: {e}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The path of the container netns as passed to the plugin subcommands.
+    pub fn netns_path(&self) -> String {
+        format!("/proc/self/fd/{}", self.container.as_raw_fd())
+    }
+
+    /// Run the function in the container netns to check what the plugin did.
+    pub fn exec_in_container<T>(
+        &self,
+        exec: impl FnOnce() -> NetavarkResult<T>,
+    ) -> NetavarkResult<T> {
+        exec_netns!(self.host.as_fd(), self.container.as_fd(), result, exec());
+        result
+    }
+
+    /// Call the setup of the plugin for the container netns.
+    pub fn setup<P: Plugin>(
+        &self,
+        plugin: &P,
+        opts: types::NetworkPluginExec,
+    ) -> Result<types::StatusBlock, Box<dyn Error>> {
+        with_test_host_netns(&self.host, || plugin.setup(self.netns_path(), opts))?
+    }
+
+    /// Call the teardown of the plugin for the container netns.
+    pub fn teardown<P: Plugin>(
+        &self,
+        plugin: &P,
+        opts: types::NetworkPluginExec,
+    ) -> Result<(), Box<dyn Error>> {
+        with_test_host_netns(&self.host, || plugin.teardown(self.netns_path(), opts))?
+    }
+}
+
+impl Drop for TestNamespaces {
+    fn drop(&mut self) {
+        if let Err(e) = join_netns(self.original.as_fd()) {
+            // a panic while unwinding aborts and hides the test failure
+            if std::thread::panicking() {
+                error!("failed to return to the original netns: {e}");
+                return;
+            }
+            // the thread would stay in the test netns, better fail loudly
+            panic!("failed to return to the original netns: {e}");
+        }
+    }
+}
+
+/// There is no way to create a netns without entering it.
+fn new_netns() -> NetavarkResult<File> {
+    unshare(CloneFlags::CLONE_NEWNET).map_err(|e| NetavarkError::Io(e.into()))?;
+    open_thread_netns()
+}
+
+fn open_thread_netns() -> NetavarkResult<File> {
+    wrap!(File::open("/proc/thread-self/ns/net"), "open thread netns")
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_route::address::AddressAttribute;
+
+    use super::*;
+    use crate::{
+        network::netlink::{LinkID, Socket},
+        plugin::helpers::{get_ipam_addresses, interface_status_block, Namespaces},
+    };
+
+    /// Adds the ipam addresses to lo of the container, every netns has it.
+    struct LoopbackPlugin {}
+
+    impl Plugin for LoopbackPlugin {
+        fn create(&self, network: types::Network) -> Result<types::Network, Box<dyn Error>> {
+            Ok(network)
+        }
+
+        fn setup(
+            &self,
+            netns: String,
+            opts: types::NetworkPluginExec,
+        ) -> Result<types::StatusBlock, Box<dyn Error>> {
+            let mut namespaces = Namespaces::open(&netns)?;
+            let ipam = get_ipam_addresses(&opts)?;
+            let link = namespaces
+                .container
+                .netlink
+                .get_link(LinkID::Name("lo".to_string()))?;
+            namespaces.configure_container_link(link.header.index, &ipam, None)?;
+            Ok(interface_status_block(
+                "lo".to_string(),
+                String::new(),
+                ipam.net_addresses,
+            ))
+        }
+
+        fn teardown(
+            &self,
+            netns: String,
+            opts: types::NetworkPluginExec,
+        ) -> Result<(), Box<dyn Error>> {
+            let mut namespaces = Namespaces::open(&netns)?;
+            let ipam = get_ipam_addresses(&opts)?;
+            let link = namespaces
+                .container
+                .netlink
+                .get_link(LinkID::Name("lo".to_string()))?;
+            for addr in &ipam.container_addresses {
+                namespaces
+                    .container
+                    .netlink
+                    .del_addr(link.header.index, addr)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn plugin_exec() -> types::NetworkPluginExec {
+        serde_json::from_value(serde_json::json!({
+            "container_id": "someID",
+            "container_name": "someName",
+            "network": {
+                "name": "test",
+                "id": "testid",
+                "driver": "lo-plugin",
+                "ipv6_enabled": false,
+                "internal": false,
+                "dns_enabled": false,
+                "subnets": [{"subnet": "10.99.0.0/24"}]
+            },
+            "network_options": {
+                "interface_name": "eth0",
+                "static_ips": ["10.99.0.2"]
+            }
+        }))
+        .unwrap()
+    }
+
+    fn container_addresses(ns: &TestNamespaces) -> Vec<String> {
+        ns.exec_in_container(|| {
+            let mut sock = Socket::new()?;
+            let mut addresses = Vec::new();
+            for msg in sock.dump_addresses()? {
+                for nla in msg.attributes {
+                    if let AddressAttribute::Address(ip) = nla {
+                        addresses.push(format!("{ip}/{}", msg.header.prefix_len));
+                    }
+                }
+            }
+            Ok(addresses)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_plugin_harness() {
+        if !nix::unistd::getuid().is_root() {
+            eprintln!("test skipped, requires root");
+            return;
+        }
+        let original = std::fs::read_link("/proc/thread-self/ns/net").unwrap();
+        {
+            let ns = TestNamespaces::new().unwrap();
+            assert_ne!(
+                std::fs::read_link("/proc/thread-self/ns/net").unwrap(),
+                original
+            );
+
+            let plugin = LoopbackPlugin {};
+            let status = ns.setup(&plugin, plugin_exec()).unwrap();
+            let subnets = status.interfaces.unwrap()["lo"].subnets.clone().unwrap();
+            assert_eq!(subnets[0].ipnet.to_string(), "10.99.0.2/24");
+            assert!(container_addresses(&ns).contains(&"10.99.0.2/24".to_string()));
+
+            // nothing leaked into the test host netns
+            let mut host = Socket::new().unwrap();
+            assert!(host.dump_addresses().unwrap().is_empty());
+
+            ns.teardown(&plugin, plugin_exec()).unwrap();
+            assert!(!container_addresses(&ns).contains(&"10.99.0.2/24".to_string()));
+        }
+        assert_eq!(
+            std::fs::read_link("/proc/thread-self/ns/net").unwrap(),
+            original
+        );
+    }
+}
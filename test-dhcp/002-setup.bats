@@ -100,3 +100,160 @@ EOF
         # error and a return code of 156
         expected_rc=156 run_setup "$input_config"
 }
+
+@test "list and show leases" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        container_ip=$(echo "$output" | jq -r .yiaddr)
+
+        run_client_cmd list
+        assert `echo "$output" | jq -r '.leases | length'` == 1
+        assert `echo "$output" | jq -r '.leases[0].lease.yiaddr'` == "$container_ip"
+        assert `echo "$output" | jq -r '.leases[0].renewing'` == "true"
+
+        run_client_cmd show "$CONTAINER_MAC"
+        assert `echo "$output" | jq -r '.lease.mac_address'` == "$CONTAINER_MAC"
+        assert `echo "$output" | jq -r '.remaining_lease_time'` != 0
+
+        # An unknown mac address should result in a return code of 6
+        expected_rc=6 run_client_cmd show "aa:bb:cc:dd:ee:ff"
+}
+
+@test "setup sends hostname and client id" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "client_id": "my-client-id",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        run_helper grep "client provides name: foobar" "$TMP_TESTDIR/dnsmasq.log"
+}
+
+@test "setup applies mtu from lease" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        assert `echo "$output" | jq -r .mtu` == 1400
+        assert `echo "$output" | jq -r .ntp_servers[0]` == $(gateway_from_subnet "$SUBNET_CIDR")
+        run_in_container_netns ip -j link show veth0
+        assert `echo "$output" | jq -r .[0].mtu` == 1400
+}
+
+@test "setup keeps the mtu option of the network" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH",
+  "mtu": 1300
+}
+  \0
+EOF
+
+        run_in_container_netns ip link set veth0 mtu 1300
+        run_setup "$input_config"
+        assert `echo "$output" | jq -r .mtu` == 1400
+        run_in_container_netns ip -j link show veth0
+        assert `echo "$output" | jq -r .[0].mtu` == 1300
+}
+
+@test "setup applies classless static routes from lease" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        gw=$(gateway_from_subnet "$SUBNET_CIDR")
+        assert `echo "$output" | jq -r .static_routes[0].destination` == "10.99.0.0/16"
+        assert `echo "$output" | jq -r .static_routes[0].gateway` == "$gw"
+        run_in_container_netns ip -j route show 10.99.0.0/16
+        assert `echo "$output" | jq -r .[0].gateway` == "$gw"
+}
+
+@test "setup sends hostname without client id" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        container_ip=$(echo "$output" | jq -r .yiaddr)
+        # lease file format: expiry mac ip hostname client-id
+        run_helper grep " $container_ip " "$TMP_TESTDIR/dnsmasq.leases"
+        assert "$(echo "$output" | cut -d ' ' -f 2,4,5)" == "$CONTAINER_MAC foobar *"
+}
+
+@test "setup sends client id" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "$CONTAINER_MAC",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "client_id": "my-id",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+        run_setup "$input_config"
+        container_ip=$(echo "$output" | jq -r .yiaddr)
+        run_helper grep " $container_ip " "$TMP_TESTDIR/dnsmasq.leases"
+        # type 0 followed by "my-id"
+        assert "$(echo "$output" | cut -d ' ' -f 4,5)" == "foobar 00:6d:79:2d:69:64"
+}
@@ -35,3 +35,25 @@ EOF
        assert "$output" == 0
 
 }
+
+@test "teardown sends DHCPRELEASE" {
+      read -r -d '\0' input_config <<EOF
+{
+  "host_iface": "veth1",
+  "container_iface": "veth0",
+  "container_mac_addr": "${CONTAINER_MAC}",
+  "domain_name": "example.com",
+  "host_name": "foobar",
+  "version": 0,
+  "ns_path": "$NS_PATH"
+}
+  \0
+EOF
+
+       run_setup "$input_config"
+       container_ip=$(echo "$output" | jq -r .yiaddr)
+       run_teardown "$input_config"
+       # give dnsmasq a moment to log the release
+       sleep 1
+       run_helper grep "DHCPRELEASE(br0) $container_ip" "$TMP_TESTDIR/dnsmasq.log"
+}
@@ -354,6 +354,18 @@ dhcp-option=3,$gw
 # Set DNS server as Router.
 dhcp-option=6,$gw
 
+# Set NTP server as Router.
+dhcp-option=42,$gw
+
+# Set the interface MTU.
+dhcp-option=26,1400
+
+# Set a classless static route.
+dhcp-option=121,10.99.0.0/16,$gw
+
+# Keep the leases of the test, they contain the hostname and client-id.
+dhcp-leasefile=${TMP_TESTDIR}/dnsmasq.leases
+
 # Logging.
 log-facility=/var/log/dnsmasq.log   # logfile path.
 log-async
@@ -414,6 +426,13 @@ function run_client(){
   run_in_container_netns "./bin/netavark-dhcp-proxy-client" --uds "$TMP_TESTDIR/nv-proxy.sock" -f "${conf}" "${verb}"
 }
 
+###################
+#  run_client_cmd # run a test client command that needs no config
+###################
+function run_client_cmd(){
+  run_in_container_netns "./bin/netavark-dhcp-proxy-client" --uds "$TMP_TESTDIR/nv-proxy.sock" "$@"
+}
+
 ###################
 #  random_subnet  # generate a random private subnet
 ###################
@@ -33,3 +33,31 @@ load helpers
     json="$output"
     assert_json "$json" ".version" =~ "^1\.[0-9]+\.[0-9]+(-rc[0-9]|-dev)?" "correct version"
 }
+
+@test "netavark validate" {
+    run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json validate
+    assert "$output" == "" "valid config has no output"
+
+    config='{"name":"podman","id":"abc","driver":"bridge","network_interface":"podman9","ipv6_enabled":false,"internal":false,"dns_enabled":false,
+        "subnets":[{"subnet":"10.88.0.0/16","gateway":"10.89.0.1"},{"subnet":"10.88.1.0/24","lease_range":{"start_ip":"10.88.1.9","end_ip":"10.88.1.2"}}],
+        "options":{"mtu":"big"}}'
+    expected_rc=1 run_netavark validate <<<"$config"
+    result="$output"
+    assert_json "$result" ".error" =~ "gateway 10.89.0.1 is not in subnet 10.88.0.0/16" "gateway error"
+    assert_json "$result" ".error" =~ "lease range start_ip 10.88.1.9 is after end_ip 10.88.1.2" "lease range error"
+    assert_json "$result" ".error" =~ "subnet 10.88.0.0/16 overlaps with subnet 10.88.1.0/24" "overlap error"
+    assert_json "$result" ".error" =~ "unable to parse \"mtu\"" "driver option error"
+}
+
+@test "netavark schema" {
+    run_netavark schema
+    assert_json 'keys | join(",")' "Network,NetworkOptions,NetworkPluginExec,PluginInfo,StatusBlock" "all schemas are printed"
+
+    run_netavark schema Network
+    result="$output"
+    assert_json "$result" ".title" == "Network" "network schema title"
+    assert_json "$result" ".properties.options.properties.mtu.pattern" == '^[0-9]+$' "mtu is an unsigned integer"
+    assert_json "$result" '.properties.options.properties.isolate.enum | join(",")' == "true,false,strict" "isolate values"
+
+    expected_rc=2 run_netavark schema Unknown
+}
@@ -589,6 +589,173 @@ fw_driver=iptables
     run_nc_test "0" "tcp" 8080 "10.0.0.1" 8080
 }
 
+@test "$fw_driver - port forwarding conflict between containers" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state with the port mappings is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    # second container with the same host ports
+    jq '.container_id = "4b7d8c6ad4d4a8b0d8e1e1a4b6a0d1b2c3d4e5f60718293a4b5c6d7e8f90a1b2" |
+        .networks.podman.interface_name = "eth1" | .networks.podman.static_ips = ["10.88.0.15"]' \
+        ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json >"$NETAVARK_TMPDIR/conflict.json"
+
+    expected_rc=1 run_netavark --file "$NETAVARK_TMPDIR/conflict.json" setup $(get_container_netns_path)
+    assert_json "$output" ".error" =~ "port mapping 192.168.188.25:8080/tcp conflicts with container f922ffdda5718b26ea585a500d5ad05191da5461b06d6f62e4d1f66ca901a253 on network podman" "port conflict error"
+
+    # explicitly allowed
+    jq '.networks.podman.options = {"allow_port_conflicts": "true"}' "$NETAVARK_TMPDIR/conflict.json" \
+        >"$NETAVARK_TMPDIR/conflict-allowed.json"
+    run_netavark --file "$NETAVARK_TMPDIR/conflict-allowed.json" setup $(get_container_netns_path)
+}
+
+@test "$fw_driver - firewall dump" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    run_netavark firewall dump
+    live="$output"
+    assert_json "$live" ".driver" == "$fw_driver" "firewall driver"
+    assert_json "$live" ".networks[0]" == "10.88.0.0/16" "network subnet"
+    assert_json "$live" ".masquerade[0]" == "10.88.0.0/16" "masquerade subnet"
+    assert_json "$live" ".isolation[0].bridge" == "podman0" "isolation bridge"
+    assert_json "$live" ".isolation[0].isolation" == "Never" "isolation mode"
+    assert_json "$live" ".port_forwards | length" == "2" "port forwards"
+    assert_json "$live" ".port_forwards[0].host_ip" == "192.168.188.25" "host ip"
+    assert_json "$live" ".port_forwards[0].dest_ip" == "10.88.0.14" "container ip"
+
+    run_netavark firewall dump --state
+    assert "$output" == "$live" "live rules match the firewall state"
+}
+
+@test "$fw_driver - firewall reconcile" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+    run_netavark firewall dump --state
+    expected="$output"
+
+    run_in_host_netns iptables -F
+    run_in_host_netns iptables -t nat -F
+
+    run_netavark firewall dump
+    assert "$output" != "$expected" "rules are gone"
+
+    run_netavark firewall reconcile
+    run_netavark firewall dump
+    assert "$output" == "$expected" "rules are restored"
+
+    # nothing missing so nothing should change
+    run_netavark firewall reconcile
+    run_netavark firewall dump
+    assert "$output" == "$expected" "rules are not duplicated"
+}
+
+@test "$fw_driver - firewall migrate to nftables" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    run_netavark firewall migrate --to nftables
+    assert "$(<$NETAVARK_TMPDIR/config/firewall/firewall-driver)" "==" "nftables" "firewall-driver file content"
+
+    run_netavark firewall dump --state
+    expected="$output"
+    run_netavark firewall dump
+    assert "$output" == "$expected" "nftables rules match the state"
+
+    run_in_host_netns iptables -t nat -S
+    assert "$output" !~ "NETAVARK" "iptables rules removed"
+
+    # the configured driver must be switched as well
+    NETAVARK_FW=nftables run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json teardown $(get_container_netns_path)
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "10.88.0.0/16" "nftables rules removed"
+}
+
+@test "$fw_driver - snat source address" {
+    config=$(jq '.network_info.podman.options = {"snat_ipv4": "10.88.0.1", "snat_exclude": "192.168.0.0/16,fd00::/8"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[1]}" == "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "NETAVARK-1D8721804F16F ACCEPT rule"
+    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F -d 192.168.0.0/16 -j ACCEPT" "excluded destination rule"
+    assert "${lines[3]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j SNAT --to-source 10.88.0.1" "SNAT rule"
+    assert "${#lines[@]}" = 4 "too many NETAVARK-1D8721804F16F rules"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+}
+
+@test "$fw_driver - port forwarding allocate host port" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 0, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    if [[ "$rootless" == "true" ]]; then
+        expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+        assert_json ".error" "host port 0 is not supported in rootless mode" "rootless host port 0 error"
+        return
+    fi
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    host_port=$(jq -r '.podman.port_mappings[0].host_port' <<<"$output")
+    assert "$host_port" != "0" "allocated host port is reported"
+
+    run_in_host_netns iptables -S -t nat
+    assert "$output" =~ "--dport $host_port -j DNAT --to-destination 10.88.0.2:80" "port fw rule uses the allocated port"
+
+    # teardown must find the allocated port even when it is passed as 0
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S -t nat
+    assert "$output" !~ "--to-destination 10.88.0.2:80" "port fw rule should not exist"
+}
+
+@test "$fw_driver - port range forwarding to a single container port" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 3, "container_range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" '.podman | has("port_mappings")' == "false" "no port mappings reported without allocation"
+
+    run_in_host_netns iptables -S -t nat
+    assert "$output" =~ "--dport 8080:8082 -j DNAT --to-destination 10.88.0.2:80" "port range fw rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S -t nat
+    assert "$output" !~ "--to-destination 10.88.0.2:80" "port fw rule should not exist"
+}
+
+@test "$fw_driver - port forwarding invalid container range" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 3, "container_range": 2, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "container range 2 of container port 80 must be 1 or equal to the range 3" "invalid container range error"
+}
+
+@test "$fw_driver - port forwarding without localhost forwarding and hairpin masquerade" {
+    config=$(jq '.network_info.podman.options = {"localhost_forwarding": "false", "hairpin_masquerade": "false"} | .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S -t nat
+    assert "$output" =~ "! -d 127.0.0.0/8 -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.88.0.2:80" "localhost is excluded from the port fw rule"
+    assert "$output" !~ "-s 10.88.0.0/16 -p tcp -m tcp --dport 8080 -j NETAVARK-HOSTPORT-SETMARK" "no hairpin masquerade rule"
+    assert "$output" !~ "-s 127.0.0.1/32 -p tcp -m tcp --dport 8080 -j NETAVARK-HOSTPORT-SETMARK" "no localhost masquerade rule"
+
+    run_in_host_netns cat /proc/sys/net/ipv4/conf/podman0/route_localnet
+    assert "$output" == "0" "route_localnet is not enabled"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S -t nat
+    assert "$output" !~ "--to-destination 10.88.0.2:80" "port fw rule should not exist"
+
+    config=$(jq '.port_mappings[0].host_ip = "127.0.0.1"' <<<"$config")
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "port 8080 is published on 127.0.0.1 but localhost_forwarding is disabled" "loopback host ip error"
+}
+
 @test "bridge ipam none" {
            read -r -d '\0' config <<EOF
 {
@@ -667,6 +834,102 @@ EOF
     assert_json ".error" "IO error: unsupported ipam driver someDriver" "Driver is not supported error"
 }
 
+@test "$fw_driver - isolation groups" {
+    # isolate1: 10.89.0.2/24, isolate=true, group shared
+    config1=$(jq '.network_info.isolate1.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config1"
+
+    # isolate2: 10.89.1.2/24, isolate=true, no group
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    # isolate3: 10.89.2.2/24, isolate=strict, group shared
+    create_container_ns
+    config3=$(jq '.network_info.isolate3.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate3.json)
+    run_netavark setup $(get_container_netns_path 2) <<<"$config3"
+
+    run_in_host_netns iptables -S NETAVARK_ISOLATION_1
+    assert "$output" =~ "-A NETAVARK_ISOLATION_1 -i isolate1 -j NETAVARK-ISO-203A3E824C44C" "isolate1 group jump"
+    assert "$output" =~ "-A NETAVARK_ISOLATION_1 -i isolate3 -j NETAVARK-ISO-203A3E824C44C" "isolate3 group jump"
+    run_in_host_netns iptables -S NETAVARK-ISO-203A3E824C44C
+    assert "${lines[1]}" == "-A NETAVARK-ISO-203A3E824C44C -o isolate1 -j ACCEPT" "isolate1 group member"
+    assert "${lines[2]}" == "-A NETAVARK-ISO-203A3E824C44C -o isolate3 -j ACCEPT" "isolate3 group member"
+
+    if [[ "$rootless" != "true" ]]; then
+        # reconcile restores the group members removed outside of netavark
+        run_in_host_netns iptables -F NETAVARK-ISO-203A3E824C44C
+        run_netavark firewall reconcile
+        run_in_host_netns iptables -S NETAVARK-ISO-203A3E824C44C
+        assert "$output" =~ "-A NETAVARK-ISO-203A3E824C44C -o isolate1 -j ACCEPT" "isolate1 group member restored"
+        assert "$output" =~ "-A NETAVARK-ISO-203A3E824C44C -o isolate3 -j ACCEPT" "isolate3 group member restored"
+    fi
+
+    # group members reach each other
+    run_in_container_netns ping -w 1 -c 1 10.89.2.2
+    run_in_container_netns 2 ping -w 1 -c 1 10.89.0.2
+    # but not the networks outside of the group
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    run_in_host_netns iptables -S NETAVARK-ISO-203A3E824C44C
+    assert "${#lines[@]}" = 2 "only isolate3 is left in the group"
+
+    run_netavark teardown $(get_container_netns_path 2) <<<"$config3"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-ISO-203A3E824C44C
+}
+
+@test "$fw_driver - isolation groups require isolation" {
+    config=$(jq '.network_info.podman.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "isolation_groups requires the isolate option to be set" "isolation groups error"
+
+    config=$(jq '.network_info.podman.options = {"isolate": "true", "isolation_groups": "a b"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid isolation group name "a b", only alphanumeric characters, "-" and "_" are allowed' "invalid group name error"
+}
+
+@test "$fw_driver - log dropped isolation traffic" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    # isolate1: 10.89.0.2/24, isolate=true, logged with LOG
+    config=$(jq '.network_info.isolate1.options.log_dropped = "log"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # isolate2: 10.89.1.2/24, isolate=true, not logged
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    # podman0: not isolated, logged with NFLOG
+    create_container_ns
+    config=$(jq '.network_info.podman.options.log_dropped = "nflog"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 2) <<<"$config"
+
+    # the log rule must come before the drop rule of the same bridge
+    run_in_host_netns iptables -S NETAVARK_ISOLATION_2
+    assert "${lines[1]}" == "-A NETAVARK_ISOLATION_2 -o isolate2 -j DROP" "isolate2 drop rule without logging"
+    assert "${lines[2]}" =~ "-A NETAVARK_ISOLATION_2 -o isolate1 -m limit --limit 5/min -j LOG --log-prefix \"NETAVARK isolate1: \"" "isolate1 log rule"
+    assert "${lines[3]}" == "-A NETAVARK_ISOLATION_2 -o isolate1 -j DROP" "isolate1 drop rule"
+
+    run_in_host_netns iptables -S NETAVARK_ISOLATION_3
+    assert "${lines[1]}" =~ "-A NETAVARK_ISOLATION_3 -o podman0 -m limit --limit 5/min -j NFLOG --nflog-prefix \"NETAVARK podman0: \"" "podman0 nflog rule"
+    assert "${lines[2]}" == "-A NETAVARK_ISOLATION_3 -o podman0 -j DROP" "podman0 drop rule"
+    assert "${lines[3]}" == "-A NETAVARK_ISOLATION_3 -j NETAVARK_ISOLATION_2" "jump after the drop rules"
+
+    # from network isolate2 to isolate1
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+
+    run_netavark firewall counters
+    result="$output"
+    assert_json "$result" '.[] | select(.bridge == "isolate1") | .packets' "!=" "0" "dropped packets are counted"
+    assert_json "$result" '.[] | select(.bridge == "isolate2") | .packets' "==" "0" "networks without logging have counters"
+    assert_json "$result" '.[] | select(.bridge == "podman0") | .packets' "==" "0" "non isolated networks have counters"
+    assert_json "$result" ". | length" "==" "3" "all drop rules have counters"
+}
+
 @test "$fw_driver - isolate networks" {
     # create container/networks with isolation
 
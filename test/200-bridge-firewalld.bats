@@ -203,6 +203,91 @@ function setup() {
     expected_rc=1 run_helper ps "$aardvark_pid"
 }
 
+@test "$fw_driver - isolation groups" {
+    # isolate1: 10.89.0.2/24, isolate=true, group shared
+    config1=$(jq '.network_info.isolate1.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config1"
+
+    # isolate2: 10.89.1.2/24, isolate=true, no group
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    # isolate3: 10.89.2.2/24, isolate=strict, group shared
+    create_container_ns
+    config3=$(jq '.network_info.isolate3.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate3.json)
+    run_netavark setup $(get_container_netns_path 2) <<<"$config3"
+
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" =~ 'rule family="ipv4" source address="10.89.0.0/24" destination address="10.89.1.0/24" drop' "isolate1 to isolate2 rule"
+    assert "$output" =~ 'rule family="ipv4" source address="10.89.1.0/24" destination address="10.89.2.0/24" drop' "isolate2 to isolate3 rule"
+    assert "$output" =~ 'rule family="ipv4" source address="10.89.2.0/24" destination address="10.89.1.0/24" drop' "isolate3 to isolate2 rule"
+    assert "$output" !~ 'source address="10.89.0.0/24" destination address="10.89.2.0/24"' "no rule within the group"
+    assert "$output" !~ 'source address="10.89.2.0/24" destination address="10.89.0.0/24"' "no rule within the group"
+
+    # group members reach each other
+    run_in_container_netns ping -w 1 -c 1 10.89.2.2
+    run_in_container_netns 2 ping -w 1 -c 1 10.89.0.2
+    # but not the networks outside of the group
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+
+    # reconcile restores an isolation rule removed outside of netavark
+    run_in_host_netns firewall-cmd --policy netavark_policy --remove-rich-rule 'rule family="ipv4" source address="10.89.1.0/24" destination address="10.89.2.0/24" drop'
+    run_netavark firewall dump
+    assert_json "$output" '[.isolation_drops[] | select(.source == "10.89.1.0/24" and .destination == "10.89.2.0/24")] | length' == "0" "removed rule is not in the dump"
+    run_netavark firewall reconcile
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" =~ 'source address="10.89.1.0/24" destination address="10.89.2.0/24" drop' "rule restored by reconcile"
+
+    # the rules are restored after a firewalld reload
+    run_netavark_firewalld_reload
+    run_in_host_netns firewall-cmd --reload
+    sleep 1
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" =~ 'source address="10.89.1.0/24" destination address="10.89.2.0/24" drop' "rules restored after reload"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" !~ "10.89.0.0/24" "isolate1 rules removed on teardown"
+
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json teardown $(get_container_netns_path 1)
+    run_netavark teardown $(get_container_netns_path 2) <<<"$config3"
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "${#lines[@]}" = 0 "rich rules did not get removed on teardown"
+}
+
+@test "$fw_driver - snat source address" {
+    config=$(jq '.network_info.podman.options = {"snat_ipv4": "10.88.0.1"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns firewall-cmd --direct --get-all-rules
+    assert "$output" =~ "ipv4 nat POSTROUTING 0 -s 10.88.0.0/16 .* -d 224.0.0.0/4 -j SNAT --to-source 10.88.0.1" "SNAT direct rule"
+
+    # teardown must not fail when the rule is already gone
+    run_in_host_netns firewall-cmd --direct --remove-rules ipv4 nat POSTROUTING
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns firewall-cmd --zone netavark_zone --list-sources
+    assert "$output" !~ "10.88.0.0/16" "subnet removed from the zone"
+
+    config=$(jq '.network_info.podman.options.snat_exclude = "192.168.0.0/16" | .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' <<<"$config")
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "excluding destinations from source nat is not supported by the firewalld driver" "snat exclude error"
+
+    # the rejected setup must not leave anything behind
+    run_helper find "$NETAVARK_TMPDIR/config/firewall" -path "*/networks/*" -o -path "*/ports/*"
+    assert "$output" == "" "no firewall state left"
+    expected_rc=1 run_in_host_netns ip link show podman0
+    expected_rc=1 run_in_container_netns ip link show eth0
+    run_in_host_netns firewall-cmd --zone netavark_zone --list-sources
+    assert "$output" !~ "10.88.0.0/16" "subnet not added to the zone"
+
+    # so the host port is still free for the next container
+    config=$(jq 'del(.network_info.podman.options.snat_exclude)' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
 @test "$fw_driver - check error message from netns thread" {
     # create interface in netns to force error
     run_in_container_netns ip link add eth0 type dummy
@@ -325,6 +410,29 @@ function setup() {
     test_port_fw ip=dual hostip="::" connectip="fd65:8371:648b:0c06::1"
 }
 
+@test "$fw_driver - port forwarding without localhost forwarding and hairpin masquerade" {
+    config=$(jq '.network_info.podman.options = {"localhost_forwarding": "false", "hairpin_masquerade": "false"} | .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" == 'rule family="ipv4" source address="10.88.0.0/16" destination NOT address="10.88.0.0/16" masquerade' "masquerade rule without hairpin"
+    run_in_host_netns firewall-cmd --policy netavark_policy --query-masquerade
+    assert "$output" == "no" "the policy itself does not masquerade"
+    run_in_host_netns firewall-cmd --policy netavark_portfwd --list-forward-ports
+    assert "$output" =~ "port=8080:proto=tcp:toport=80:toaddr=10.88.0.2" "port forward rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "${#lines[@]}" = 0 "masquerade rule removed on teardown"
+
+    # hairpin masquerade is the default
+    config=$(jq '.network_info.podman.options = {}' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns firewall-cmd --policy netavark_policy --list-rich-rules
+    assert "$output" == 'rule family="ipv4" source address="10.88.0.0/16" masquerade' "masquerade rule with hairpin"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
 @test "netavark error - invalid host_ip in port mappings" {
     expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/invalid-port.json setup $(get_container_netns_path)
     assert_json ".error" "invalid host ip \"abcd\" provided for port 8080" "host ip error"
@@ -473,6 +473,129 @@ export NETAVARK_FW=nftables
     test_port_fw ip=6 proto=udp hostip="fd65:8371:648b:0c06::1"
 }
 
+@test "$fw_driver - firewall dump" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+
+    run_netavark firewall dump
+    live="$output"
+    assert_json "$live" ".driver" == "$fw_driver" "firewall driver"
+    assert_json "$live" ".networks[0]" == "10.88.0.0/16" "network subnet"
+    assert_json "$live" ".masquerade[0]" == "10.88.0.0/16" "masquerade subnet"
+    assert_json "$live" ".isolation[0].bridge" == "podman0" "isolation bridge"
+    assert_json "$live" ".isolation[0].isolation" == "Never" "isolation mode"
+    assert_json "$live" ".port_forwards | length" == "2" "port forwards"
+    assert_json "$live" ".port_forwards[0].host_ip" == "192.168.188.25" "host ip"
+    assert_json "$live" ".port_forwards[0].dest_ip" == "10.88.0.14" "container ip"
+
+    run_netavark firewall dump --state
+    assert "$output" == "$live" "live rules match the firewall state"
+}
+
+@test "$fw_driver - firewall reconcile" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+    run_netavark firewall dump --state
+    expected="$output"
+
+    run_in_host_netns nft delete table inet netavark
+
+    run_netavark firewall dump
+    assert "$output" != "$expected" "rules are gone"
+
+    run_netavark firewall reconcile
+    run_netavark firewall dump
+    assert "$output" == "$expected" "rules are restored"
+
+    # nothing missing so nothing should change
+    run_netavark firewall reconcile
+    run_netavark firewall dump
+    assert "$output" == "$expected" "rules are not duplicated"
+}
+
+@test "$fw_driver - firewall reconcile watch" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-tcp-udp.json setup $(get_container_netns_path)
+    run_netavark firewall dump --state
+    expected="$output"
+
+    run_netavark_firewall_reconcile_watch
+    # give it some time to subscribe to the events
+    sleep 1
+
+    run_in_host_netns nft delete table inet netavark
+    # this runs in the background so give it some time to add the rules
+    sleep 2
+    run_netavark firewall dump
+    assert "$output" == "$expected" "rules are restored"
+}
+
+@test "$fw_driver - snat source address" {
+    config=$(jq '.network_info.podman.options = {"snat_ipv4": "10.88.0.1", "snat_exclude": "192.168.0.0/16,fd00::/8"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns nft list chain inet netavark nv_53ce4390_10_88_0_0_nm16
+    assert "${lines[2]}" =~ "ip daddr 10.88.0.0/16 accept" "Accept subnet daddr rule"
+    assert "${lines[3]}" =~ "ip daddr 192.168.0.0/16 accept" "Accept excluded daddr rule"
+    assert "${lines[4]}" =~ "ip daddr != 224.0.0.0/4 snat ip to 10.88.0.1" "SNAT non-multicast daddr rule"
+    assert "${#lines[@]}" = 7 "too many nv_53ce4390_10_88_0_0_nm16 rules"
+}
+
+@test "$fw_driver - port forwarding allocate host port" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 0, "range": 1, "protocol": "udp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    if [[ "$rootless" == "true" ]]; then
+        expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+        assert_json ".error" "host port 0 is not supported in rootless mode" "rootless host port 0 error"
+        return
+    fi
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    host_port=$(jq -r '.podman.port_mappings[0].host_port' <<<"$output")
+    assert "$host_port" != "0" "allocated host port is reported"
+
+    run_in_host_netns nft list chain inet netavark nv_53ce4390_10_88_0_0_nm16_dnat
+    assert "$output" =~ "udp dport $host_port dnat ip to 10.88.0.2:80" "port fw rule uses the allocated port"
+
+    # teardown must find the allocated port even when it is passed as 0
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "dnat ip to 10.88.0.2:80" "port fw rule should not exist"
+}
+
+@test "$fw_driver - port range forwarding to a single container port" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 3, "container_range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns nft list chain inet netavark nv_53ce4390_10_88_0_0_nm16_dnat
+    assert "$output" =~ "tcp dport 8080-8082 dnat ip to 10.88.0.2:80" "port range fw rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "dnat ip to 10.88.0.2:80" "port fw rule should not exist"
+}
+
+@test "$fw_driver - port forwarding without localhost forwarding and hairpin masquerade" {
+    config=$(jq '.network_info.podman.options = {"localhost_forwarding": "false", "hairpin_masquerade": "false"} | .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns nft list chain inet netavark nv_53ce4390_10_88_0_0_nm16_dnat
+    assert "$output" =~ "ip daddr != 127.0.0.0/8 tcp dport 8080 dnat ip to 10.88.0.2:80" "localhost is excluded from the port fw rule"
+    assert "$output" !~ "jump NETAVARK-HOSTPORT-SETMARK" "no hairpin or localhost masquerade rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "dnat ip to 10.88.0.2:80" "port fw rule should not exist"
+}
+
 @test "bridge ipam none" {
            read -r -d '\0' config <<EOF
 {
@@ -551,6 +674,53 @@ EOF
     assert_json ".error" "IO error: unsupported ipam driver someDriver" "Driver is not supported error"
 }
 
+@test "$fw_driver - isolation groups" {
+    # isolate1: 10.89.0.2/24, isolate=true, group shared
+    config1=$(jq '.network_info.isolate1.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config1"
+
+    # isolate2: 10.89.1.2/24, isolate=true, no group
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    # isolate3: 10.89.2.2/24, isolate=strict, group shared
+    create_container_ns
+    config3=$(jq '.network_info.isolate3.options.isolation_groups = "shared"' ${TESTSDIR}/testfiles/isolate3.json)
+    run_netavark setup $(get_container_netns_path 2) <<<"$config3"
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-1
+    assert "$output" =~ "iifname \"isolate1\" jump nv_iso_shared" "isolate1 group jump"
+    assert "$output" =~ "iifname \"isolate3\" jump nv_iso_shared" "isolate3 group jump"
+    run_in_host_netns nft list chain inet netavark nv_iso_shared
+    assert "${lines[2]}" =~ "oifname \"isolate1\" accept" "isolate1 group member"
+    assert "${lines[3]}" =~ "oifname \"isolate3\" accept" "isolate3 group member"
+
+    if [[ "$rootless" != "true" ]]; then
+        # reconcile restores the group members removed outside of netavark
+        run_in_host_netns nft flush chain inet netavark nv_iso_shared
+        run_netavark firewall reconcile
+        run_in_host_netns nft list chain inet netavark nv_iso_shared
+        assert "$output" =~ "oifname \"isolate1\" accept" "isolate1 group member restored"
+        assert "$output" =~ "oifname \"isolate3\" accept" "isolate3 group member restored"
+    fi
+
+    # group members reach each other
+    run_in_container_netns ping -w 1 -c 1 10.89.2.2
+    run_in_container_netns 2 ping -w 1 -c 1 10.89.0.2
+    # but not the networks outside of the group
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 2 ping -w 1 -c 1 10.89.1.2
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    run_in_host_netns nft list chain inet netavark nv_iso_shared
+    assert "$output" !~ "isolate1" "isolate1 left the group"
+    assert "$output" =~ "oifname \"isolate3\" accept" "isolate3 is still in the group"
+
+    run_netavark teardown $(get_container_netns_path 2) <<<"$config3"
+    expected_rc=1 run_in_host_netns nft list chain inet netavark nv_iso_shared
+}
+
 @test "$fw_driver - isolate networks" {
     # create container/networks with isolation
 
@@ -582,10 +752,10 @@ EOF
 
     # check nftables NETAVARK-ISOLATION-2 chain
     run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-2
-    assert "${lines[2]}" =~ "oifname \"isolate1\" drop" "isolate1 network ISOLATION2 chain"
-    assert "${lines[3]}" =~ "oifname \"isolate2\" drop" "isolate2 network ISOLATION2 chain"
-    assert "${lines[4]}" =~ "oifname \"isolate3\" drop" "isolate3 network ISOLATION2 chain"
-    assert "${lines[5]}" =~ "oifname \"isolate4\" drop" "isolate4 network ISOLATION2 chain"
+    assert "${lines[2]}" =~ "oifname \"isolate1\" counter packets [0-9]+ bytes [0-9]+ drop" "isolate1 network ISOLATION2 chain"
+    assert "${lines[3]}" =~ "oifname \"isolate2\" counter packets [0-9]+ bytes [0-9]+ drop" "isolate2 network ISOLATION2 chain"
+    assert "${lines[4]}" =~ "oifname \"isolate3\" counter packets [0-9]+ bytes [0-9]+ drop" "isolate3 network ISOLATION2 chain"
+    assert "${lines[5]}" =~ "oifname \"isolate4\" counter packets [0-9]+ bytes [0-9]+ drop" "isolate4 network ISOLATION2 chain"
 
     # check nftables NETAVARK-ISOLATION-3 chain
     run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-3
@@ -670,7 +840,7 @@ EOF
 
     # check nftables NETAVARK-ISOLATION-3 chain
     run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-3
-    assert "${lines[2]}" =~ "oifname \"podman0\" drop" "non-isolated container ISOLATION3 drop rule"
+    assert "${lines[2]}" =~ "oifname \"podman0\" counter packets [0-9]+ bytes [0-9]+ drop" "non-isolated container ISOLATION3 drop rule"
     assert "${lines[3]}" =~ "jump NETAVARK-ISOLATION-2" "final rule in ISOLATION3 is jump to ISOLATION2"
 
     # this should be able to ping non-strict isolated containers
@@ -701,6 +871,33 @@ EOF
     assert "${#lines[@]}" = 5 "too many NETAVARK-ISOLATION-3 rules after teardown"
 }
 
+@test "$fw_driver - log dropped isolation traffic" {
+    if [[ "$rootless" == "true" ]]; then
+        skip "firewall state is only written as root"
+    fi
+
+    config=$(jq '.network_info.isolate1.options.log_dropped = "log"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate2.json setup $(get_container_netns_path 1)
+
+    run_in_host_netns nft list chain inet netavark NETAVARK-ISOLATION-2
+    assert "${lines[2]}" =~ "oifname \"isolate1\" limit rate 5/minute.* log prefix \"NETAVARK isolate1: \"" "isolate1 log rule"
+    assert "${lines[3]}" =~ "oifname \"isolate1\" counter packets 0 bytes 0 drop" "isolate1 drop rule with counter"
+    assert "${lines[4]}" =~ "oifname \"isolate2\" counter packets 0 bytes 0 drop" "isolate2 drop rule without logging"
+
+    # from network isolate2 to isolate1
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+
+    run_netavark firewall counters
+    result="$output"
+    assert_json "$result" ".[0].bridge" "==" "isolate1" "counter bridge"
+    assert_json "$result" ".[0].packets" "!=" "0" "dropped packets are counted"
+    assert_json "$result" ".[1].bridge" "==" "isolate2" "networks without logging have counters"
+    assert_json "$result" ". | length" "==" "2" "all isolated networks have counters"
+}
+
 @test "$fw_driver - test read only /proc" {
     if [ -n "$_CONTAINERS_ROOTLESS_UID" ]; then
         skip "test only supported when run as real root"
@@ -1003,3 +1200,14 @@ function check_simple_bridge_nftables() {
 
     expected_rc=1 run_in_host_netns nft list chain inet netavark $chain
 }
+
+@test "$fw_driver - auto firewall driver uses the recorded driver" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "$(<$NETAVARK_TMPDIR/config/firewall/firewall-driver)" "==" "nftables" "firewall-driver file content"
+
+    RUST_LOG=netavark=info NETAVARK_FW=auto run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    assert "$output" =~ "Automatically selected firewall driver nftables: used by the running containers" "auto driver log"
+
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "10.88.0.0/16" "network rules removed"
+}
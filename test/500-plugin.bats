@@ -73,3 +73,237 @@ function run_netavark_plugins() {
     run_netavark_plugins teardown $(get_container_netns_path) <<<"$config"
     assert 'stderr teardown' "stderr log"
 }
+
+@test "plugin - status" {
+    config=$(get_conf stderr-plugin)
+
+    run_netavark_plugins status $(get_container_netns_path) <<<"$config"
+    assert "${lines[0]}" == "stderr status" "stderr log on first line"
+    assert "${lines[1]}" =~ '^\{"plugin-net"\:\{"dns_search_domains"' "status block"
+
+    # plugins without the status capability are not asked
+    config=$(get_conf error-plugin)
+    run_netavark_plugins status $(get_container_netns_path) <<<"$config"
+    assert '{}' "no status"
+}
+
+@test "plugin - update" {
+    get_conf stderr-plugin | jq '.network_info."plugin-net"' > $NETAVARK_TMPDIR/network.json
+
+    run_netavark_plugins --file $NETAVARK_TMPDIR/network.json update plugin-net --network-dns-servers 8.8.8.8
+    assert 'stderr update [8.8.8.8]' "plugin got the new dns servers"
+
+    expected_rc=1 run_netavark_plugins --file $NETAVARK_TMPDIR/network.json update other-net --network-dns-servers 8.8.8.8
+    assert_json ".error" "network config is for network plugin-net not other-net" "network name mismatch"
+
+    # plugins without the update capability are not called
+    get_conf error-plugin | jq '.network_info."plugin-net"' > $NETAVARK_TMPDIR/network.json
+    run_netavark_plugins --file $NETAVARK_TMPDIR/network.json update plugin-net --network-dns-servers 8.8.8.8
+    assert '' "no error output"
+}
+
+@test "plugin - timeout" {
+    cat >$NETAVARK_TMPDIR/sleep-plugin <<'EOF2'
+#!/bin/sh
+case "$1" in
+    info) echo '{"version":"0.1.0","api_version":"2.0.0"}' ;;
+    *) exec sleep 60 ;;
+esac
+EOF2
+    chmod +x $NETAVARK_TMPDIR/sleep-plugin
+
+    config=$(get_conf sleep-plugin | jq '.network_info."plugin-net".options.plugin_timeout = "1"')
+    expected_rc=124 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'plugin "sleep-plugin" failed: timed out after 1s, plugin killed' "timeout from network option"
+
+    config=$(get_conf sleep-plugin)
+    NETAVARK_PLUGIN_TIMEOUT=1 expected_rc=124 run_netavark --plugin-directory $NETAVARK_TMPDIR teardown $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'plugin "sleep-plugin" failed: timed out after 1s, plugin killed' "timeout from env"
+}
+
+@test "plugin - cni chained plugins" {
+    # fake CNI plugins, log the calls and pass the result on
+    cat >$NETAVARK_TMPDIR/fake-ptp <<'EOF2'
+#!/bin/sh
+echo "ptp $CNI_COMMAND $CNI_IFNAME $CNI_CONTAINERID" >>${0%/*}/cni.log
+if [ "$CNI_COMMAND" = "ADD" ]; then
+    echo '{"cniVersion":"1.0.0","interfaces":[{"name":"veth0"},{"name":"'$CNI_IFNAME'","mac":"aa:bb:cc:dd:ee:01","sandbox":"'$CNI_NETNS'"}],"ips":[{"address":"10.22.0.5/16","gateway":"10.22.0.1","interface":1}],"dns":{"nameservers":["10.22.0.1"]}}'
+fi
+EOF2
+    cat >$NETAVARK_TMPDIR/fake-tuning <<'EOF2'
+#!/bin/sh
+input=$(cat)
+echo "tuning $CNI_COMMAND $(echo "$input" | jq -c '.mtu') $(echo "$input" | jq -c '.prevResult.ips[0].address')" >>${0%/*}/cni.log
+if [ "$CNI_COMMAND" = "ADD" ]; then
+    echo "$input" | jq -c '.prevResult'
+fi
+EOF2
+    chmod +x $NETAVARK_TMPDIR/fake-ptp $NETAVARK_TMPDIR/fake-tuning
+
+    cat >$NETAVARK_TMPDIR/net.conflist <<EOF2
+{
+    "cniVersion": "1.0.0",
+    "name": "cninet",
+    "plugins": [
+        {"type": "fake-ptp"},
+        {"type": "fake-tuning", "mtu": 1400}
+    ]
+}
+EOF2
+
+    config=$(get_conf cni | jq --arg conf $NETAVARK_TMPDIR/net.conflist '.network_info."plugin-net".options.cni_config = $conf')
+
+    run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json ".\"plugin-net\".interfaces.eth0.mac_address" "aa:bb:cc:dd:ee:01" "mac address from the cni result"
+    assert_json ".\"plugin-net\".interfaces.eth0.subnets[0].ipnet" "10.22.0.5/16" "ip from the cni result"
+    assert_json ".\"plugin-net\".dns_server_ips[0]" "10.22.0.1" "dns servers from the cni result"
+    assert_json ".\"plugin-net\".interfaces | length" "1" "host interfaces are not reported"
+
+    # the result is cached for DEL
+    run_helper jq -r '.ips[0].address' $NETAVARK_TMPDIR/config/cni-results/$(get_conf cni | jq -r '.network_info."plugin-net".id')_someID
+    assert "$output" == "10.22.0.5/16" "cached cni result"
+
+    run_netavark --plugin-directory $NETAVARK_TMPDIR teardown $(get_container_netns_path) <<<"$config"
+    assert "$(ls $NETAVARK_TMPDIR/config/cni-results)" == "" "cached cni result removed on teardown"
+
+    run cat $NETAVARK_TMPDIR/cni.log
+    assert "${lines[0]}" == "ptp ADD eth0 someID" "ptp added first"
+    assert "${lines[1]}" == "tuning ADD 1400 \"10.22.0.5/16\"" "tuning added second with the ptp result"
+    assert "${lines[2]}" == "tuning DEL 1400 \"10.22.0.5/16\"" "tuning deleted first with the cached result"
+    assert "${lines[3]}" == "ptp DEL eth0 someID" "ptp deleted last"
+
+    config=$(get_conf cni)
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "cni_config option is required for the cni driver" "missing cni config"
+
+    jq '.cniVersion = "0.2.0"' $NETAVARK_TMPDIR/net.conflist >$NETAVARK_TMPDIR/old.conflist
+    config=$(get_conf cni | jq --arg conf $NETAVARK_TMPDIR/old.conflist '.network_info."plugin-net".options.cni_config = $conf')
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "cni version 0.2.0 is not supported, netavark supports 0.3.0, 0.3.1, 0.4.0, 1.0.0, 1.1.0" "unsupported cni version"
+}
+
+@test "plugin - hooks" {
+    cat >$NETAVARK_TMPDIR/log-hook <<'EOF2'
+#!/bin/sh
+echo "${0##*/} $1 $(jq -c '.status.interfaces | keys? // null')" >>${0%/*}/hooks.log
+EOF2
+    cp $NETAVARK_TMPDIR/log-hook $NETAVARK_TMPDIR/log-hook2
+    cat >$NETAVARK_TMPDIR/fail-hook <<'EOF2'
+#!/bin/sh
+if [ "$1" = "setup" ]; then
+    echo '{"error":"hook error"}'
+    exit 1
+fi
+EOF2
+    chmod +x $NETAVARK_TMPDIR/*-hook $NETAVARK_TMPDIR/log-hook2
+    cp ./targets/release/examples/stderr-plugin ./targets/release/examples/host-device-plugin $NETAVARK_TMPDIR/
+
+    # the hooks get the status block of the driver on setup
+    run_in_host_netns ip link add dummy0 type dummy
+    config=$(get_conf host-device-plugin | jq '.network_info."plugin-net".hooks = ["log-hook", "log-hook2"]')
+    run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    run_netavark --plugin-directory $NETAVARK_TMPDIR teardown $(get_container_netns_path) <<<"$config"
+
+    run cat $NETAVARK_TMPDIR/hooks.log
+    assert "${lines[0]}" == 'log-hook setup ["dummy0"]' "first hook runs first on setup"
+    assert "${lines[1]}" == 'log-hook2 setup ["dummy0"]' "second hook on setup"
+    assert "${lines[2]}" == "log-hook2 teardown null" "second hook runs first on teardown"
+    assert "${lines[3]}" == "log-hook teardown null" "first hook on teardown"
+    rm $NETAVARK_TMPDIR/hooks.log
+
+    # a failed hook rolls back the hooks before it and the driver
+    config=$(get_conf stderr-plugin | jq '.network_info."plugin-net".hooks = ["log-hook", "fail-hook"]')
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert "${lines[0]}" == "stderr setup" "driver setup"
+    assert "${lines[1]}" == "stderr teardown" "driver teardown after the hook failed"
+    assert "${lines[2]}" == '{"error":"hook \"fail-hook\" failed: exit code 1, message: hook error"}' "hook error"
+    run cat $NETAVARK_TMPDIR/hooks.log
+    assert "$output" == "log-hook setup null
+log-hook teardown null" "first hook rolled back"
+
+    config=$(get_conf stderr-plugin | jq '.network_info."plugin-net".hooks = ["missing-hook"]')
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'hook "missing-hook" not found in the plugin directories' "missing hook"
+}
+
+@test "plugin - request port forwarding and dns" {
+    export NETAVARK_FW=nftables
+    run_in_host_netns ip link add dummy0 type dummy
+    run_in_host_netns ip addr add 10.99.0.2/24 dev dummy0
+
+    config=$(get_conf host-device-plugin | jq '.network_info."plugin-net".options = {"request_port_forward": "true"} |
+        .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]')
+
+    run_netavark_plugins setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" '."plugin-net".interfaces.dummy0.subnets[0].ipnet' "==" "10.99.0.2/24" "plugin status block"
+    assert_json "$result" '."plugin-net".netavark' "==" "null" "services are not part of the status block"
+
+    run_in_host_netns nft list table inet netavark
+    assert "$output" =~ "tcp dport 8080 dnat ip to 10.99.0.2:80" "port fw rule for the plugin address"
+
+    run_netavark_plugins teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns nft list table inet netavark
+    assert "$output" !~ "dnat ip to 10.99.0.2:80" "port fw rule should be removed"
+    run_in_host_netns ip link show dummy0
+
+    # dns needs a gateway for aardvark-dns which host-device never reports
+    config=$(get_conf host-device-plugin | jq '.network_info."plugin-net".options = {"request_dns": "true"} |
+        .network_info."plugin-net".dns_enabled = true')
+    expected_rc=1 run_netavark_plugins setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "plugin requested dns but did not report a gateway for aardvark-dns to listen on" "dns without gateway"
+    # the failed setup was rolled back
+    run_in_host_netns ip link show dummy0
+}
+
+@test "plugin - daemon socket" {
+    ./targets/release/examples/daemon-plugin $NETAVARK_TMPDIR/daemon.sock &>"$NETAVARK_TMPDIR/daemon.log" &
+    pid=$!
+    for i in {1..50}; do
+        test -S $NETAVARK_TMPDIR/daemon.sock && break
+        sleep 0.1
+    done
+    echo $NETAVARK_TMPDIR/daemon.sock >$NETAVARK_TMPDIR/daemon-net.socket
+
+    config=$(get_conf daemon-net)
+    run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json '."plugin-net".dns_search_domains[0]' "setup-1" "status block from the daemon"
+    run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert_json '."plugin-net".dns_search_domains[0]' "setup-2" "daemon keeps its state between requests"
+
+    run_netavark --plugin-directory $NETAVARK_TMPDIR teardown $(get_container_netns_path) <<<"$config"
+    assert '' "no error output"
+
+    config=$(get_conf daemon-net | jq '.container_name = "fail"')
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert '{"error":"plugin \"daemon-net\" failed: exit code 1, message: daemon setup error"}'
+
+    run cat $NETAVARK_TMPDIR/daemon.log
+    assert "$output" == "daemon setup 1
+daemon setup 2
+daemon teardown" "requests handled by a single daemon"
+
+    kill $pid
+    expected_rc=1 run_netavark --plugin-directory $NETAVARK_TMPDIR setup $(get_container_netns_path) <<<"$config"
+    assert "$output" =~ "connect to plugin socket" "daemon not running"
+}
+
+@test "plugin - list drivers" {
+    cp ./targets/release/examples/stderr-plugin $NETAVARK_TMPDIR/
+    cat >$NETAVARK_TMPDIR/future-plugin <<'EOF2'
+#!/bin/sh
+echo '{"version": "1.0.0", "api_version": "9.0.0"}'
+EOF2
+    chmod +x $NETAVARK_TMPDIR/future-plugin
+    echo $NETAVARK_TMPDIR/missing.sock >$NETAVARK_TMPDIR/daemon.socket
+
+    run_netavark --plugin-directory $NETAVARK_TMPDIR drivers
+    result="$output"
+    assert_json "$result" '[.[] | select(.type == "builtin") | .name] | join(",")' "==" "bridge,cni,ipvlan,macvlan" "built-in drivers"
+    assert_json "$result" '.[] | select(.name == "stderr-plugin") | .path' "==" "$NETAVARK_TMPDIR/stderr-plugin" "plugin path"
+    assert_json "$result" '.[] | select(.name == "stderr-plugin") | .api_version' "==" "2.0.0" "plugin api version"
+    assert_json "$result" '.[] | select(.name == "stderr-plugin") | .error' "==" "null" "working plugin has no error"
+    assert_json "$result" '.[] | select(.name == "future-plugin") | .error' "==" "plugin API version 9.0.0 is not supported, netavark supports API versions 1.x to 2.x" "incompatible plugin"
+    assert_json "$result" '.[] | select(.name == "daemon") | .socket' "==" "$NETAVARK_TMPDIR/missing.sock" "plugin socket"
+    assert_json "$result" '.[] | select(.name == "daemon") | .error' "=~" "connect to plugin socket" "daemon not responding"
+}
@@ -47,6 +47,9 @@ function basic_setup() {
 }
 
 function basic_teardown() {
+    if [ -n "${NETAVARK_RECONCILE_PID}" ]; then
+        kill -9 $NETAVARK_RECONCILE_PID
+    fi
     teardown_firewalld
     kill -9 $HOST_NS_PID
     for i in "${!CONTAINER_NS_PIDS[@]}"; do
@@ -143,6 +146,11 @@ function run_netavark_firewalld_reload() {
     NETAVARK_FIREWALLD_RELOAD_PID=$!
 }
 
+function run_netavark_firewall_reconcile_watch() {
+    # need to use nsetner as this will be run in the background
+    nsenter -n -t $HOST_NS_PID $NETAVARK --config "$NETAVARK_TMPDIR/config" firewall reconcile --watch &
+    NETAVARK_RECONCILE_PID=$!
+}
 
 ################
 #  run_in_container_netns  #  Run args in container netns